@@ -48,6 +48,9 @@ use std::sync::mpsc::{channel, Receiver, Sender}; // 线程间通信
 use std::thread;
 use std::io::Cursor;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use rodio::Source; // 音频播放
 use serde::{Serialize, Deserialize};
 
@@ -57,1035 +60,9033 @@ use serde::{Serialize, Deserialize};
 
 const BASE_HEIGHT: f32 = 720.0; // 基础分辨率高度
 const DIALOGUE_BOX_RATIO: f32 = 0.28; // 对话框占屏幕高度的比例
+const NARRATION_BOX_SCALE: f32 = 0.7; // 旁白模式下对话框高度相对正常高度的比例
+const DIALOGUE_AUTO_MIN_SCALE: f32 = 0.75; // 自动高度时对话框最矮为默认高度的比例
+const DIALOGUE_AUTO_MAX_RATIO: f32 = 0.5; // 自动高度时对话框最高占屏幕高度的比例
+const DIALOGUE_MIN_FONT_SCALE: f32 = 0.6; // 内容放不下时字号最多缩小到的比例
+const LINE_START_FORBIDDEN: &str = "。，、．！？）」』】〕〉》”’；：…—～ー・,.!?;:)]}"; // 避头：不能出现在行首的标点
+const LINE_END_FORBIDDEN: &str = "（「『【〔〈《“‘([{"; // 避尾：不能出现在行尾的标点
 const MAX_DT: f32 = 0.033; // 最大帧时间（30FPS保护）
+const DEFAULT_AUTO_DELAY: f32 = 2.0; // 自动播放时文本显示完毕后的停留秒数
+const FRAME_STEP_DT: f32 = 1.0 / 60.0; // 暂停时单帧步进的时间步长
+const DEFAULT_RENDER_FPS: f32 = 30.0; // 离线渲染默认帧率
+const LONG_PRESS_SECS: f64 = 0.5; // 单指长按选中角色的判定时长
+const MAX_UNDO_STEPS: usize = 50; // 撤销栈最多保留的剧本快照数
+const THUMBNAIL_SIZE: usize = 128; // 立绘缩略图（头像）边长
+const STORYBOARD_THUMB_SIZE: [usize; 2] = [192, 108]; // 分镜视图中每幕缩略图的尺寸（16:9）
+const RASTER_PAGE_MAX_SIDE: usize = 512; // 离屏光栅化缩略图用的贴图 CPU 副本最大边长（原尺寸贴图只留在资源缓存里）
+const STORYBOARD_REFRESH_SECS: f32 = 0.5; // 分镜视图打开时当前幕缩略图的刷新间隔
+const BOUNDS_REFRESH_SECS: f32 = 0.25; // 屏幕外角色姿势未被改动时重新计算包围盒的间隔（秒）
+const HOT_RELOAD_POLL_SECS: f32 = 1.0; // 热重载检查立绘文件修改时间的间隔
+const LOOK_AT_MAX_DEG: f32 = 30.0; // 看向鼠标时头部骨骼的最大偏转角度
+const CHARACTER_HEIGHT_RATIO: f32 = 0.9; // 立绘默认显示高度（setup pose 包围盒高度占屏幕高度的比例）
+const FALLBACK_CHARACTER_SCALE: f32 = 0.45; // 无法得到包围盒时的默认缩放（720p 基准）
+const DEPTH_MIN_SCALE: f32 = 0.7; // 景深排列：最远处（深度 1）角色的缩放倍率
+const DEPTH_MAX_DIM: f32 = 0.45; // 景深排列：最远处角色变暗的比例
+const ENTER_SLIDE_SECS: f32 = 0.5; // 入场滑入动画时长（秒）
+const ENTER_SLIDE_DISTANCE: f32 = 0.3; // 入场滑入的起始距离（屏幕宽度的比例）
+const DEFAULT_STAGGER_DELAY: f32 = 0.3; // 依次入场时相邻角色的默认间隔（秒）
+const EXPRESSION_TRACK: usize = 1; // 表情动画使用的轨道（0 号轨道为身体动作）
+const LOG_FILE: &str = "aefr.log"; // 日志文件（追加写入）
+const LAYOUTS_FILE: &str = "aefr_layouts.json"; // 命名构图预设的保存文件
+const PROJECT_EXTENSION: &str = "aefrproj"; // 多章节项目文件的扩展名（内容为 JSON）
+const PERSIST_KEY: &str = "aefr_state"; // eframe 持久化存储中界面状态的键
+const BLINK_TRACK: usize = 2; // 自动眨眼使用的轨道
+const BLINK_INTERVAL: (f32, f32) = (2.0, 5.0); // 自动眨眼的随机间隔范围（秒）
+const TYPE_INTERVAL: f32 = 0.03; // 打字机默认每字间隔（秒）
+const LOAD_READ_SHARE: f32 = 0.7; // 加载进度中读取文件所占比例
+const LOAD_DECODE_SHARE: f32 = 0.2; // 加载进度中解码贴图所占比例（其余为解析骨骼）
+const LOAD_CHUNK_SIZE: usize = 256 * 1024; // 分块读取的块大小
+const DEFAULT_LOAD_TIMEOUT: f32 = 120.0; // 立绘加载默认超时（秒，含排队时间），超过后自动放弃
+const TEXTURE_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.25..=1.0; // 立绘贴图分辨率的可调范围（相对原图）
+const MAX_LOAD_WORKERS: usize = 4; // 立绘加载线程池的最大线程数
+const SEGMENT_SEPARATOR: &str = "\n---\n"; // 对话内容分段分隔符（单独一行 ---）
+const VOICE_TYPE_INTERVAL: (f32, f32) = (0.01, 0.15); // 按语音时长匹配打字速度时的每字间隔范围
+const VOICE_ADVANCE_PAUSE: f32 = 0.5; // 语音播完后到自动推进的停顿（秒）
+const SKIP_ADVANCE_INTERVAL: f32 = 0.05; // 快进时每次推进的间隔（秒），只为让画面来得及刷新
+const ZIP_ENTRY_SEPARATOR: &str = "::"; // 压缩包路径与包内 atlas 路径的分隔符（如 pack.zip::chars/a.atlas）
+const NAME_BOX_BORDER: f32 = 24.0; // 名字框贴图默认的九宫格边宽（贴图像素）
+const DEFAULT_GIF_FPS: f32 = 20.0; // 导出 GIF 的默认帧率
+const DEFAULT_GIF_SIZE: u32 = 512; // 导出 GIF 的默认长边像素
+const SHADOW_WIDTH_RATIO: f32 = 0.35; // 脚底阴影宽度相对立绘（setup pose）高度的比例
+const SHADOW_ASPECT: f32 = 0.18; // 脚底阴影椭圆的高宽比
+const SHADOW_SEGMENTS: u32 = 32; // 脚底阴影椭圆的分段数
+const SPEAKER_LABEL_LIFT: f32 = 0.12; // 头顶名字标签相对头部骨骼上抬的距离（立绘显示高度的比例）
+const DUCK_ATTACK_SECS: f32 = 0.15; // BGM 闪避时音量压低所用的时间（秒）
+const SPECTRUM_WINDOW: usize = 1024; // 频谱分析窗口（样本数，必须为 2 的幂）
+const SPECTRUM_BATCH: usize = 256; // 音频线程每攒够这么多样本提交一次
+const SPECTRUM_BARS: usize = 24; // 频谱条数（按对数频率分段）
+const SPECTRUM_FLOOR_DB: f32 = 60.0; // 频谱条显示的动态范围（低于满幅这么多分贝时为 0）
+const SPECTRUM_FALL_PER_SEC: f32 = 1.5; // 频谱条每秒最多回落的高度比例
+const DEFAULT_CACHE_LIMIT_MB: u32 = if cfg!(target_os = "android") { 256 } else { 1024 }; // 立绘资源缓存默认内存上限
 
 // ============================================================================
 // 数据结构定义
 // ============================================================================
 
 /// 单幕场景数据
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 struct Scene {
-    bg_path: Option<String>,           // 背景图片路径
+    #[serde(default)]
+    background: SceneBackground,       // 背景：图片（路径）、纯色或线性渐变
+    #[serde(default, rename = "bg_path", skip_serializing)]
+    legacy_bg_path: Option<String>,    // 旧版剧本单独记录的背景图片路径，读取时并入 background
+    #[serde(default)]
+    foreground_path: Option<String>,   // 前景图片路径（覆盖在所有立绘之上，如雨丝、窗框）
     bgm_path: Option<String>,          // 背景音乐路径
+    #[serde(default)]
+    bgm_loop_start: Option<f32>,       // BGM 循环起点（秒）：先完整播放一遍，之后只循环起点到结尾（None 为整首循环）
+    #[serde(default)]
+    voice_path: Option<String>,        // 本幕语音（自动播放时等语音播完再推进）
     char_paths: [Option<String>; 5],   // 5个角色槽位的Spine资源路径
     char_anims: [Option<String>; 5],   // 各角色当前动画
+    #[serde(default)]
+    char_skins: [Option<String>; 5],   // 各角色当前皮肤
+    #[serde(default)]
+    char_attachments: [Vec<(String, Option<String>)>; 5], // 各角色手动设置的插槽附件（插槽名, 附件名；None 为隐藏）
     speaker_name: String,              // 说话者姓名
     speaker_aff: String,               // 说话者所属（学校/组织）
     dialogue_content: String,          // 对话内容
+    #[serde(default)]
+    speaker_slot: Option<usize>,       // 说话者所在槽位（用于说话弹跳等强调效果）
+    #[serde(default)]
+    choices: Vec<(String, usize)>,     // 分支选项（选项文本, 目标幕索引）
+    #[serde(default)]
+    transition: Option<TransitionConfig>, // 进入本幕时的过渡（None 使用全局默认）
+    #[serde(default = "default_ambient_tint")]
+    ambient_tint: Color32,             // 环境光：与所有立绘颜色相乘（白色为无影响）
+    #[serde(default)]
+    auto_advance_after: Option<f32>,   // 幕级定时推进（秒）：进入本幕后到点自动进入下一幕，不受全局 AUTO 开关影响
+    #[serde(default)]
+    notes: String,                     // 制作备注（只在编辑界面显示，播放时不显示）
+    #[serde(default)]
+    color_filter: ColorFilter,         // 全局颜色滤镜（作用于立绘、背景与前景，如回忆场景的黑白/复古）
 }
 
-/// 完整剧本（多幕场景集合）
-#[derive(Serialize, Deserialize, Clone, Default)]
-struct Scenario {
-    scenes: Vec<Scene>,
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            background: SceneBackground::default(),
+            legacy_bg_path: None,
+            foreground_path: None,
+            bgm_path: None,
+            bgm_loop_start: None,
+            voice_path: None,
+            char_paths: Default::default(),
+            char_anims: Default::default(),
+            char_skins: Default::default(),
+            char_attachments: Default::default(),
+            speaker_name: String::new(),
+            speaker_aff: String::new(),
+            dialogue_content: String::new(),
+            speaker_slot: None,
+            choices: Vec::new(),
+            transition: None,
+            ambient_tint: default_ambient_tint(),
+            auto_advance_after: None,
+            notes: String::new(),
+            color_filter: ColorFilter::None,
+        }
+    }
 }
 
-// ============================================================================
-// 程序入口点（跨平台处理）
-// ============================================================================
+fn default_ambient_tint() -> Color32 { Color32::WHITE }
 
-#[cfg(not(target_os = "android"))]
-fn main() -> eframe::Result<()> {
-    let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([1280.0, 720.0]) // 默认窗口大小
-            .with_title("AEFR Editor - OxidizedSchale Edition"),
-        vsync: true, // 开启垂直同步
-        ..Default::default()
-    };
-    eframe::run_native("AEFR_App", options, Box::new(|cc| Box::new(AefrApp::new(cc))))
+/// 把一幕的对话内容按分隔符切成依次点击显示的片段（没有分隔符时只有一段）
+fn dialogue_segments(content: &str) -> Vec<&str> {
+    content.split(SEGMENT_SEPARATOR).collect()
 }
 
-// Android平台特殊入口点
-#[cfg(target_os = "android")]
-fn main() -> eframe::Result<()> {
-    eframe::run_native("AEFR_App", eframe::NativeOptions::default(), Box::new(|cc| Box::new(AefrApp::new(cc))))
+/// 按比例压暗颜色的 RGB，保持 Alpha 不变
+fn dim_color(c: Color32, factor: f32) -> Color32 {
+    let f = |x: u8| (x as f32 * factor).round() as u8;
+    Color32::from_rgba_premultiplied(f(c.r()), f(c.g()), f(c.b()), c.a())
 }
 
-// Android JNI入口（供原生Activity调用）
-#[cfg(target_os = "android")]
-#[no_mangle]
-fn android_main(app: android_activity::AndroidApp) {
-    let _ = eframe::run_native("AEFR_App", eframe::NativeOptions::default(), Box::new(|cc| Box::new(AefrApp::new(cc))));
+/// 在两个颜色之间按 t（0~1）逐通道线性插值
+fn lerp_color(a: Color32, b: Color32, t: f32) -> Color32 {
+    let mix = |x: u8, y: u8| (x as f32 + (y as f32 - x as f32) * t).round() as u8;
+    Color32::from_rgba_premultiplied(mix(a.r(), b.r()), mix(a.g(), b.g()), mix(a.b(), b.b()), mix(a.a(), b.a()))
 }
 
-// ============================================================================
-// 核心架构组件
-// ============================================================================
+/// 画面颜色滤镜：对立绘、背景与前景贴图的颜色做矩阵变换（对话框等界面不受影响）
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+enum ColorFilter {
+    #[default]
+    None,      // 原色
+    Grayscale, // 灰度（黑白）
+    Sepia,     // 复古（褐色调）
+    Invert,    // 反色
+}
 
-/// 绅士调度器 - 防止计算线程抢占UI/音频线程
-struct AefrScheduler { pool: rayon::ThreadPool }
-impl AefrScheduler {
-    fn new() -> Self {
-        let logic_cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
-        Self { 
-            pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(if logic_cores > 2 { logic_cores - 2 } else { 1 }) // 保留2个核心给系统
-                .build().unwrap() 
+impl ColorFilter {
+    fn label(self) -> &'static str {
+        match self {
+            ColorFilter::None => "原色",
+            ColorFilter::Grayscale => "黑白",
+            ColorFilter::Sepia => "复古",
+            ColorFilter::Invert => "反色",
         }
     }
-    fn run_parallel<OP>(&self, op: OP) where OP: FnOnce() + Send { 
-        self.pool.install(op); // 在专用线程池中执行并行任务
+
+    /// 变换一个预乘颜色：灰度与复古是 RGB 的线性组合，可以直接作用在预乘值上（结果不超过 Alpha）；
+    /// 反色在预乘空间中为 a - c
+    fn apply(self, c: Color32) -> Color32 {
+        let [r, g, b, a] = c.to_array().map(|x| x as f32);
+        let mix = |kr: f32, kg: f32, kb: f32| (r * kr + g * kg + b * kb).min(a).round() as u8;
+        match self {
+            ColorFilter::None => c,
+            ColorFilter::Grayscale => {
+                let l = mix(0.299, 0.587, 0.114);
+                Color32::from_rgba_premultiplied(l, l, l, c.a())
+            }
+            ColorFilter::Sepia => Color32::from_rgba_premultiplied(
+                mix(0.393, 0.769, 0.189),
+                mix(0.349, 0.686, 0.168),
+                mix(0.272, 0.534, 0.131),
+                c.a(),
+            ),
+            ColorFilter::Invert => Color32::from_rgba_premultiplied(c.a() - c.r().min(c.a()), c.a() - c.g().min(c.a()), c.a() - c.b().min(c.a()), c.a()),
+        }
+    }
+
+    /// 变换与贴图相乘的顶点颜色（环境光、插槽颜色、压暗等）：灰度与复古时转为同亮度的灰色，
+    /// 与已滤镜的贴图相乘后仍是纯灰/褐色调（直接套用复古会让色调叠加两次）；反色无法拆到乘积的两边，保持原色
+    fn apply_tint(self, c: Color32) -> Color32 {
+        match self {
+            ColorFilter::Grayscale | ColorFilter::Sepia => ColorFilter::Grayscale.apply(c),
+            ColorFilter::None | ColorFilter::Invert => c,
+        }
+    }
+
+    /// 对整张贴图应用滤镜（原色时直接共用原图）
+    fn apply_image(self, image: &Arc<egui::ColorImage>) -> Arc<egui::ColorImage> {
+        if self == ColorFilter::None {
+            return image.clone();
+        }
+        let pixels = image.pixels.par_iter().map(|&c| self.apply(c)).collect();
+        Arc::new(egui::ColorImage { size: image.size, pixels })
     }
 }
 
-/// 应用命令枚举 - 线程间消息传递的指令
-enum AppCommand {
-    Dialogue { name: String, affiliation: String, content: String }, // 更新对话
-    RequestLoad { slot_idx: usize, path: String }, // 异步加载角色
-    LoadSuccess(usize, Box<SpineObject>, egui::ColorImage, String, Vec<String>), // 加载成功回调
-    RemoveCharacter(usize), // 移除角色
-    LoadBackground(String), // 加载背景
-    LoadBackgroundSuccess(egui::ColorImage), // 背景加载成功
-    PlayBgm(String), // 播放背景音乐
-    PlaySe(String), // 播放音效
-    AudioReady(Vec<u8>, bool), // 音频数据就绪
-    StopBgm, // 停止背景音乐
-    SetAnimation { slot_idx: usize, anim_name: String, loop_anim: bool }, // 设置动画
-    Log(String), // 日志记录
+/// 一幕的背景：纯色与渐变不需要图片，适合黑屏、白屏过场
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+enum SceneBackground {
+    Image(Option<String>),                                  // 背景图片路径（None 为未指定背景）
+    Solid(Color32),                                         // 纯色
+    Gradient { start: Color32, end: Color32, horizontal: bool }, // 线性渐变（默认从上到下，horizontal 为从左到右）
 }
 
-/// 音频管理器
-struct AudioManager {
-    _stream: rodio::OutputStream,
-    _stream_handle: rodio::OutputStreamHandle,
-    bgm_sink: rodio::Sink, // BGM专用混音器
-    se_sink: rodio::Sink,  // 音效专用混音器
+impl Default for SceneBackground {
+    fn default() -> Self {
+        SceneBackground::Image(None)
+    }
 }
-impl AudioManager {
-    fn new() -> Result<Self, String> {
-        // 初始化音频输出流
-        let (_stream, stream_handle) = rodio::OutputStream::try_default().map_err(|e| e.to_string())?;
-        let bgm_sink = rodio::Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
-        let se_sink = rodio::Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
-        Ok(Self { _stream, _stream_handle: stream_handle, bgm_sink, se_sink })
+
+impl SceneBackground {
+    fn label(&self) -> &'static str {
+        match self {
+            SceneBackground::Image(_) => "图片",
+            SceneBackground::Solid(_) => "纯色",
+            SceneBackground::Gradient { .. } => "渐变",
+        }
     }
-    
-    fn play_bgm(&self, data: Vec<u8>) {
-        // 解码并循环播放BGM
-        if let Ok(source) = rodio::Decoder::new(Cursor::new(data)) {
-            self.bgm_sink.stop(); 
-            self.bgm_sink.append(source.repeat_infinite()); 
-            self.bgm_sink.play();
+
+    /// 图片背景的路径（纯色/渐变或未指定时为 None）
+    fn image_path(&self) -> Option<&String> {
+        match self {
+            SceneBackground::Image(path) => path.as_ref(),
+            _ => None,
         }
     }
-    
-    fn play_se(&self, data: Vec<u8>) {
-        // 解码并播放音效（单次）
-        if let Ok(source) = rodio::Decoder::new(Cursor::new(data)) { 
-            self.se_sink.append(source); 
-            self.se_sink.play(); 
+
+    fn is_image(&self) -> bool {
+        matches!(self, SceneBackground::Image(_))
+    }
+
+    /// 相对位置 (u, v)（0~1）处的颜色，图片背景返回 None
+    fn color_at(&self, u: f32, v: f32) -> Option<Color32> {
+        match *self {
+            SceneBackground::Image(_) => None,
+            SceneBackground::Solid(c) => Some(c),
+            SceneBackground::Gradient { start, end, horizontal } => Some(lerp_color(start, end, if horizontal { u } else { v })),
         }
     }
-    
-    fn stop_bgm(&self) { self.bgm_sink.stop(); }
-}
 
-// ============================================================================
-// Spine 2D骨骼动画对象
-// ============================================================================
+    /// 用纯色矩形或四角着色的 Mesh 填充 `rect`（与对话框渐变同样的画法），颜色经过滤镜变换；图片背景不绘制
+    fn paint(&self, painter: &egui::Painter, rect: Rect, filter: ColorFilter) {
+        let color = |u, v| self.color_at(u, v).map(|c| filter.apply(c));
+        match self {
+            SceneBackground::Image(_) => {}
+            SceneBackground::Solid(_) => {
+                painter.rect_filled(rect, 0.0, color(0.0, 0.0).unwrap_or(Color32::BLACK));
+            }
+            SceneBackground::Gradient { .. } => {
+                let mut mesh = Mesh::default();
+                for (pos, (u, v)) in [(rect.left_top(), (0.0, 0.0)), (rect.right_top(), (1.0, 0.0)), (rect.right_bottom(), (1.0, 1.0)), (rect.left_bottom(), (0.0, 1.0))] {
+                    mesh.colored_vertex(pos, color(u, v).unwrap_or(Color32::BLACK));
+                }
+                mesh.add_triangle(0, 1, 2);
+                mesh.add_triangle(0, 2, 3);
+                painter.add(Shape::mesh(mesh));
+            }
+        }
+    }
 
-pub struct SpineObject {
-    skeleton: Skeleton,               // Spine骨架实例
-    state: AnimationState,            // 动画状态机
-    _texture: Option<TextureHandle>,  // 纹理句柄（所有权管理）
-    texture_id: Option<TextureId>,    // 纹理ID（渲染时使用）
-    pub position: Pos2,               // 屏幕位置
-    pub scale: f32,                   // 缩放比例
-    skeleton_data: Arc<rusty_spine::SkeletonData>, // 共享骨架数据
+    /// 导出 HTML 时的 CSS background-image（纯色也写成渐变，统一赋值）
+    fn css(&self) -> Option<String> {
+        let hex = |c: Color32| format!("#{:02x}{:02x}{:02x}", c.r(), c.g(), c.b());
+        match *self {
+            SceneBackground::Image(_) => None,
+            SceneBackground::Solid(c) => Some(format!("linear-gradient({0}, {0})", hex(c))),
+            SceneBackground::Gradient { start, end, horizontal } => Some(format!(
+                "linear-gradient(to {}, {}, {})", if horizontal { "right" } else { "bottom" }, hex(start), hex(end)
+            )),
+        }
+    }
 }
-unsafe impl Send for SpineObject {} // 标记为可跨线程安全发送
 
-impl SpineObject {
-    /// 异步加载Spine资源（不涉及GPU操作）
-    fn load_async_no_gpu(path_str: &str) -> Result<(Self, egui::ColorImage, String, Vec<String>), String> {
-        // 1. 加载.atlas文件
-        let atlas_path = std::path::Path::new(path_str);
-        let atlas = Arc::new(Atlas::new_from_file(atlas_path).map_err(|e| format!("Atlas Error: {}", e))?);
-        let page = atlas.pages().next().ok_or("Atlas has no pages")?;
-        let page_name = page.name().to_string();
-        
-        // 2. 加载纹理图片
-        let img_path = atlas_path.parent().ok_or("Invalid path")?.join(&page_name);
-        let img = image::open(&img_path).map_err(|e| format!("Image Load Error: {}", e))?;
-        let color_image = egui::ColorImage::from_rgba_unmultiplied([img.width() as _, img.height() as _], 
-            img.to_rgba8().as_raw()
-        );
+/// 解析 #RGB / #RRGGBB 形式的颜色（# 可省略）
+fn parse_hex_color(s: &str) -> Option<Color32> {
+    let hex = s.trim_start_matches('#');
+    let channel = |i: usize, len: usize| u8::from_str_radix(hex.get(i * len..(i + 1) * len)?, 16).ok();
+    match hex.len() {
+        3 => Some(Color32::from_rgb(channel(0, 1)? * 17, channel(1, 1)? * 17, channel(2, 1)? * 17)),
+        6 => Some(Color32::from_rgb(channel(0, 2)?, channel(1, 2)?, channel(2, 2)?)),
+        _ => None,
+    }
+}
 
-        // 3. 加载骨骼数据（优先尝试二进制格式，其次JSON格式）
-        let skel_path = atlas_path.with_extension("skel");
-        let json_path = atlas_path.with_extension("json");
-        
-        let skeleton_data = if skel_path.exists() {
-            let skeleton_bin = SkeletonBinary::new(atlas.clone());
-            Arc::new(skeleton_bin.read_skeleton_data_file(&skel_path).map_err(|e| format!("Binary load failed: {}", e))?)
-        } else if json_path.exists() {
-            let skeleton_json = SkeletonJson::new(atlas.clone());
-            Arc::new(skeleton_json.read_skeleton_data_file(&json_path).map_err(|e| format!("JSON load failed: {}", e))?)
-        } else { 
-            return Err("Missing .skel or .json".into()); 
-        };
+/// 切幕过渡类型
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+enum TransitionKind {
+    Cut,       // 硬切（无过渡）
+    #[default]
+    FadeBlack, // 淡出到黑再淡入
+    FadeWhite, // 淡出到白再淡入
+    Dissolve,  // 旧背景交叉溶解到新背景
+}
 
-        // 4. 创建动画状态机和骨架实例
-        let state_data = Arc::new(AnimationStateData::new(skeleton_data.clone()));
-        let mut state = AnimationState::new(state_data);
-        let anim_names: Vec<String> = skeleton_data.animations().map(|a| a.name().to_string()).collect();
-        
-        // 默认播放第一个动画
-        if let Some(anim) = skeleton_data.animations().next() { 
-            let _ = state.set_animation(0, &anim, true); 
+impl TransitionKind {
+    /// 过渡类型显示名称
+    fn label(self) -> &'static str {
+        match self {
+            TransitionKind::Cut => "硬切",
+            TransitionKind::FadeBlack => "黑场淡入淡出",
+            TransitionKind::FadeWhite => "白场淡入淡出",
+            TransitionKind::Dissolve => "交叉溶解",
         }
+    }
+}
 
-        Ok((Self { 
-            skeleton: Skeleton::new(skeleton_data.clone()), 
-            state, 
-            _texture: None, 
-            texture_id: None, 
-            position: Pos2::ZERO, 
-            scale: 0.45, 
-            skeleton_data 
-        }, color_image, page_name, anim_names))
+/// 切幕过渡配置
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+struct TransitionConfig {
+    kind: TransitionKind,
+    duration: f32, // 过渡总时长（秒）
+}
+
+impl Default for TransitionConfig {
+    fn default() -> Self {
+        Self { kind: TransitionKind::default(), duration: 0.6 }
     }
+}
 
-    /// 获取所有动画名称列表
-    fn get_anim_names(&self) -> Vec<String> { 
-        self.skeleton_data.animations().map(|a| a.name().to_string()).collect() 
+/// 进行中的切幕过渡
+struct ActiveTransition {
+    config: TransitionConfig,
+    elapsed: f32,                            // 已进行时间
+    target_scene: usize,                     // 目标幕索引
+    switched: bool,                          // 是否已切换到目标幕
+    old_background: Option<TextureHandle>,   // 交叉溶解时保留的旧背景
+    from_ambient: Color32,                   // 切幕前的环境光（过渡期间向目标幕插值）
+}
+
+impl ActiveTransition {
+    /// 整体进度（0~1）
+    fn progress(&self) -> f32 {
+        (self.elapsed / self.config.duration).clamp(0.0, 1.0)
     }
-    
-    /// 按名称设置动画
-    fn set_animation_by_name(&mut self, anim_name: &str, loop_anim: bool) -> bool {
-        if let Some(anim) = self.skeleton_data.animations().find(|a| a.name() == anim_name) {
-            let _ = self.state.set_animation(0, &anim, loop_anim); 
-            true
-        } else { 
-            false 
-        }
+
+    /// 淡入淡出遮罩不透明度（0~1）：前半程渐显，后半程渐隐
+    fn overlay_alpha(&self) -> f32 {
+        let half = self.config.duration / 2.0;
+        let alpha = if self.elapsed < half { self.elapsed / half } else { 1.0 - (self.elapsed - half) / half };
+        alpha.clamp(0.0, 1.0)
     }
-    
-    /// 并行更新动画状态（由调度器调用）
-    fn update_parallel(&mut self, dt: f32) {
-        let dt = dt.min(MAX_DT); // 限制最大时间步长
-        self.state.update(dt);
-        self.skeleton.set_to_setup_pose(); // 重置到绑定姿势
-        let _ = self.state.apply(&mut self.skeleton);
-        self.skeleton.update_world_transform(); // 更新世界变换
-        self.skeleton.update_cache(); // 更新缓存
+}
+
+/// 立绘排列预设
+#[derive(Clone, Copy, PartialEq, Default)]
+enum LayoutPreset {
+    #[default]
+    Uniform,   // 按槽位均匀排列
+    SplitSides, // 左右两组对峙
+    Pair,      // 两两对话：主角在左，其余在右
+}
+
+impl LayoutPreset {
+    /// 预设显示名称
+    fn label(self) -> &'static str {
+        match self {
+            LayoutPreset::Uniform => "均匀排列",
+            LayoutPreset::SplitSides => "左右分组",
+            LayoutPreset::Pair => "两两对话",
+        }
     }
-    
-    /// 渲染Spine对象到egui Mesh
-    fn paint(&self, ui: &mut egui::Ui) {
-        let tex_id = match self.texture_id { 
-            Some(id) => id, 
-            None => return 
+
+    /// 计算槽位 `slot_idx` 上第 k 个已加载角色（共 n 个）的横向位置百分比与是否水平翻转
+    /// 左侧角色保持原朝向，右侧角色翻转以面向左侧
+    fn place(self, slot_idx: usize, k: usize, n: usize) -> (f32, bool) {
+        // 在 [from, to] 区间内均分 count 个位置
+        let spread = |i: usize, count: usize, from: f32, to: f32| {
+            if count <= 1 { (from + to) / 2.0 } else { from + (to - from) * i as f32 / (count - 1) as f32 }
         };
-        let mut mesh = Mesh::with_texture(tex_id);
-        let mut world_vertices = Vec::with_capacity(1024); // 重用顶点缓冲区
-        
-        // 遍历所有绘制顺序的插槽
-        for slot in self.skeleton.draw_order() {
-            let attachment = match slot.attachment() { 
-                Some(a) => a, 
-                None => continue 
-            };
-            
-            // 处理区域附件（RegionAttachment，普通图片）
-            if let Some(region) = attachment.as_region() {
-                unsafe {
-                    if world_vertices.len() < 8 { world_vertices.resize(8, 0.0); }
-                    region.compute_world_vertices(&slot.bone(), &mut world_vertices, 0, 2);
-                    self.push_to_mesh(&mut mesh, &world_vertices[0..8], &region.uvs(), &[0, 1, 2, 2, 3, 0], &*slot, region.color());
+        match self {
+            LayoutPreset::Uniform => (0.15 + (slot_idx as f32 * 0.175), false), // 按槽位固定，空槽位也占位
+            LayoutPreset::SplitSides => {
+                let left = (n + 1) / 2;
+                if k < left {
+                    (spread(k, left, 0.12, 0.38), false)
+                } else {
+                    (spread(k - left, n - left, 0.62, 0.88), true)
                 }
-            } 
-            // 处理网格附件（MeshAttachment，变形网格）
-            else if let Some(mesh_att) = attachment.as_mesh() {
-                unsafe {
-                    let len = mesh_att.world_vertices_length() as usize;
-                    if world_vertices.len() < len { world_vertices.resize(len, 0.0); }
-                    mesh_att.compute_world_vertices(&*slot, 0, len as i32, &mut world_vertices, 0, 2);
-                    let uvs = std::slice::from_raw_parts(mesh_att.uvs(), len);
-                    let tris = std::slice::from_raw_parts(mesh_att.triangles(), mesh_att.triangles_count() as usize);
-                    self.push_to_mesh(&mut mesh, &world_vertices[0..len], uvs, tris, &*slot, mesh_att.color());
+            }
+            LayoutPreset::Pair => {
+                if k == 0 {
+                    (0.28, false)
+                } else {
+                    (spread(k - 1, n - 1, 0.62, 0.85), true)
                 }
             }
         }
-        ui.painter().add(Shape::mesh(mesh));
     }
-    
-    /// 将顶点数据推送到Mesh
-    fn push_to_mesh(&self, mesh: &mut Mesh, w_v: &[f32], uvs: &[f32], tris: &[u16], slot: &Slot, att_c: rusty_spine::Color) {
-        let s_c = slot.color(); // 插槽颜色（叠加附件颜色）
-        let color = Color32::from_rgba_premultiplied(
-            (s_c.r * att_c.r * 255.0) as u8, 
-            (s_c.g * att_c.g * 255.0) as u8,
-            (s_c.b * att_c.b * 255.0) as u8, 
-            (s_c.a * att_c.a * 255.0) as u8,
-        );
-        
-        // 添加顶点
-        let count = usize::min(uvs.len() / 2, w_v.len() / 2);
-        let idx_offset = mesh.vertices.len() as u32;
-        
-        for i in 0..count {
-            let pos = Pos2::new(
-                w_v[i*2] * self.scale + self.position.x, 
-                -w_v[i*2+1] * self.scale + self.position.y // Y轴翻转（Spine使用向上为负的坐标系）
-            );
-            mesh.vertices.push(Vertex { 
-                pos, 
-                uv: Pos2::new(uvs[i*2], uvs[i*2+1]), 
-                color 
-            });
-        }
-        
-        // 添加三角形索引
-        for &idx in tris { 
-            mesh.indices.push(idx_offset + idx as u32); 
+}
+
+/// 立绘贴图的 Alpha 存储方式（取决于素材导出时是否勾选了预乘）
+#[derive(Clone, Copy, PartialEq, Default)]
+enum TextureAlpha {
+    #[default]
+    Straight,      // 普通 PNG（非预乘），加载时预乘
+    Premultiplied, // Spine 导出时已预乘（PMA），按原样上传
+}
+
+impl TextureAlpha {
+    /// 显示名称
+    fn label(self) -> &'static str {
+        match self {
+            TextureAlpha::Straight => "普通（非预乘）",
+            TextureAlpha::Premultiplied => "已预乘（PMA）",
         }
     }
 }
 
-// ============================================================================
-// 主应用程序逻辑
-// ============================================================================
-
-struct AefrApp {
-    scheduler: AefrScheduler,            // 绅士调度器
-    is_auto_enabled: bool,               // 自动播放开关
-    show_dialogue: bool,                 // 对话框显示开关
-    scenario: Scenario,                   // 当前剧本
-    current_scene_idx: usize,            // 当前场景索引
-    target_chars: Vec<char>,             // 目标文本字符（打字机效果）
-    visible_count: usize,                // 当前可见字符数
-    type_timer: f32,                     // 打字机计时器
-    console_open: bool,                  // 控制台面板开关
-    selected_slot: usize,                // 当前选中的角色槽位
-    console_input: String,               // 控制台输入缓冲
-    console_logs: Vec<String>,           // 控制台日志
-    show_anim_preview: bool,             // 动画预览面板开关
-    preview_anim_idx: usize,             // 预览动画索引
-    characters: Vec<Option<SpineObject>>, // 5个角色槽位
-    background: Option<TextureHandle>,   // 背景纹理
-    audio_manager: Option<AudioManager>, // 音频管理器（可选）
-    tx: Sender<AppCommand>,              // 命令发送器
-    rx: Receiver<AppCommand>,            // 命令接收器
+/// 对话框外观样式（语义相同：说话者名/所属/内容/打字机/继续指示，只是绘制不同）
+#[derive(Clone, Copy, PartialEq, Default)]
+enum DialogueStyle {
+    #[default]
+    BlueArchive, // 蔚蓝档案风格：底部深蓝背景 + 名字区渐变
+    ClassicBar,  // 经典视觉小说：底部留边的半透明圆角条
+    Centered,    // 居中框：名字与内容居中排列，适合旁白/独白
 }
 
-impl AefrApp {
-    fn new(cc: &eframe::CreationContext) -> Self {
-        // 初始化配置
-        setup_embedded_font(&cc.egui_ctx);
-        egui_extras::install_image_loaders(&cc.egui_ctx);
-        let (tx, rx) = channel();
-        let audio_manager = AudioManager::new().ok(); // 音频管理器可能初始化失败
-        
-        // 创建初始场景
-        let startup_text = "AEFR 已启动！\n正在等待指令......";
-        let mut first_scene = Scene::default();
-        first_scene.speaker_name = "OxidizedSchale".into();
-        first_scene.speaker_aff = "AEFR Contributors".into();
-        first_scene.dialogue_content = startup_text.into();
+impl DialogueStyle {
+    /// 显示名称
+    fn label(self) -> &'static str {
+        match self {
+            DialogueStyle::BlueArchive => "蔚蓝档案",
+            DialogueStyle::ClassicBar => "经典底栏",
+            DialogueStyle::Centered => "居中框",
+        }
+    }
 
-        Self {
-            scheduler: AefrScheduler::new(),
-            is_auto_enabled: true, 
-            show_dialogue: true,
-            scenario: Scenario { scenes: vec![first_scene] },
-            current_scene_idx: 0,
-            target_chars: startup_text.chars().collect(), 
-            visible_count: 0, 
-            type_timer: 0.0,
-            console_open: false,
-            selected_slot: 0,
-            console_input: String::new(),
-            console_logs: vec!["[系统] 编辑器就绪。".into()],
-            show_anim_preview: false,
-            preview_anim_idx: 0,
-            characters: (0..5).map(|_| None).collect(), // 初始化5个空槽位
-            background: None,
-            audio_manager,
-            tx, rx,
+    /// 按样式绘制对话框，返回是否被点击（用于跳过打字机/推进剧情）
+    /// `is_finished` 为打字机是否已显示完整段文字（显示完才绘制继续指示器）
+    /// 继续指示器与避头尾断行三种样式共用
+    fn draw(self, ui: &mut egui::Ui, screen: Rect, skin: DialogueSkin, text: DialogueContent, is_finished: bool) -> bool {
+        match self {
+            DialogueStyle::BlueArchive => draw_ba_dialogue(ui, screen, skin, text, is_finished),
+            DialogueStyle::ClassicBar => draw_classic_dialogue(ui, screen, skin, text, is_finished),
+            DialogueStyle::Centered => draw_centered_dialogue(ui, screen, skin, text, is_finished),
         }
     }
+}
 
-    /// 同步当前场景数据到UI状态
-    fn sync_scene_to_ui(&mut self) {
-        if let Some(scene) = self.scenario.scenes.get(self.current_scene_idx) {
-            self.target_chars = scene.dialogue_content.chars().collect();
+/// 对话框文字效果类型
+#[derive(Clone, Copy, PartialEq, Default)]
+enum TextEffectKind {
+    #[default]
+    None,    // 无效果（只画一遍文字）
+    Outline, // 描边：八个方向各多画一遍
+    Shadow,  // 投影：右下方多画一遍
+}
+
+impl TextEffectKind {
+    fn label(self) -> &'static str {
+        match self {
+            TextEffectKind::None => "无",
+            TextEffectKind::Outline => "描边",
+            TextEffectKind::Shadow => "投影",
         }
     }
+}
 
-    /// 解析并发送控制台命令
-    fn parse_and_send_command(&mut self, input: &str) {
-        let input_trimmed = input.trim();
-        if input_trimmed.is_empty() { return; }
-        self.console_logs.push(format!("> {}", input_trimmed));
-        let tx = self.tx.clone();
-        let cmd_lower = input_trimmed.to_lowercase();
-
-        // 命令解析逻辑
-        if cmd_lower.starts_with("load ") { // load [槽位] [路径]
-            let parts: Vec<&str> = input_trimmed.splitn(2, ' ').collect();
-            if parts.len() == 2 {
-                if let Ok(idx) = parts[0][5..].trim().parse::<usize>() {
-                   let _ = tx.send(AppCommand::RequestLoad { 
-                       slot_idx: idx, 
-                       path: parts[1].replace("\"", "") 
-                   });
-                }
-            }
-        } else if cmd_lower.starts_with("anim ") { // anim [槽位][动画名] [循环]
-            let parts: Vec<&str> = input_trimmed.split_whitespace().collect();
-            if parts.len() >= 2 {
-                if let Ok(idx) = parts[1].parse::<usize>() {
-                    let anim_name = parts[2].to_string();
-                    let loop_anim = parts.get(3).map_or(true, |s| s.to_lowercase() == "true");
-                    let _ = tx.send(AppCommand::SetAnimation { 
-                        slot_idx: idx, 
-                        anim_name, 
-                        loop_anim 
-                    });
+/// 对话框文字的描边/投影，提升浅色或复杂背景上的可读性
+/// 描边每段文字要多画八遍，文字很多时可关闭或改用投影
+#[derive(Clone, Copy)]
+struct TextEffect {
+    kind: TextEffectKind,
+    color: Color32, // 描边/投影颜色
+    width: f32,     // 描边宽度/投影偏移（像素）
+}
+
+impl Default for TextEffect {
+    fn default() -> Self {
+        Self { kind: TextEffectKind::None, color: Color32::from_black_alpha(200), width: 1.5 }
+    }
+}
+
+impl TextEffect {
+    /// 按效果绘制已排版的文字：先在偏移位置用效果色画底，再画文字本身
+    fn paint(&self, painter: &egui::Painter, pos: Pos2, galley: Arc<egui::Galley>, color: Color32) {
+        let w = self.width;
+        let offsets: Vec<Vec2> = match self.kind {
+            TextEffectKind::None => Vec::new(),
+            TextEffectKind::Outline => vec![
+                Vec2::new(-w, -w), Vec2::new(0.0, -w), Vec2::new(w, -w),
+                Vec2::new(-w, 0.0),                    Vec2::new(w, 0.0),
+                Vec2::new(-w, w),  Vec2::new(0.0, w),  Vec2::new(w, w),
+            ],
+            TextEffectKind::Shadow => vec![Vec2::new(w, w)],
+        };
+        for offset in offsets {
+            painter.galley_with_override_text_color(pos + offset, galley.clone(), self.color);
+        }
+        painter.galley(pos, galley, color);
+    }
+}
+
+/// 继续指示器的形状
+#[derive(Clone, Copy, PartialEq, Default)]
+enum IndicatorShape {
+    #[default]
+    Triangle, // 实心倒三角
+    Dot,      // 圆点
+    Arrow,    // 折线箭头（﹀）
+}
+
+impl IndicatorShape {
+    fn label(self) -> &'static str {
+        match self {
+            IndicatorShape::Triangle => "三角",
+            IndicatorShape::Dot => "圆点",
+            IndicatorShape::Arrow => "箭头",
+        }
+    }
+}
+
+/// 继续指示器的动画方式
+#[derive(Clone, Copy, PartialEq, Default)]
+enum IndicatorMotion {
+    #[default]
+    Float, // 上下浮动
+    Blink, // 明暗闪烁
+    Spin,  // 原地旋转
+}
+
+impl IndicatorMotion {
+    fn label(self) -> &'static str {
+        match self {
+            IndicatorMotion::Float => "浮动",
+            IndicatorMotion::Blink => "闪烁",
+            IndicatorMotion::Spin => "旋转",
+        }
+    }
+}
+
+/// 文本显示完毕后对话框里的"继续"指示器样式
+#[derive(Clone, Copy)]
+struct ContinueIndicator {
+    shape: IndicatorShape,
+    motion: IndicatorMotion,
+    color: Option<Color32>, // None 时使用各对话框样式自己的颜色
+    speed: f32,             // 动画角频率（弧度/秒）
+    amplitude: f32,         // 浮动幅度（像素）
+}
+
+impl Default for ContinueIndicator {
+    fn default() -> Self {
+        Self { shape: IndicatorShape::Triangle, motion: IndicatorMotion::Float, color: None, speed: 3.0, amplitude: 3.0 }
+    }
+}
+
+impl ContinueIndicator {
+    /// 在 `center` 处绘制指示器，`size` 为图形半宽，`default_color` 为对话框样式的默认颜色
+    fn paint(&self, ui: &egui::Ui, center: Pos2, size: f32, default_color: Color32) {
+        let phase = ui.input(|i| i.time) * self.speed as f64;
+        let (lift, alpha, angle) = match self.motion {
+            IndicatorMotion::Float => (phase.sin() as f32 * self.amplitude, 1.0, 0.0),
+            IndicatorMotion::Blink => (0.0, 0.55 + 0.45 * phase.sin() as f32, 0.0),
+            IndicatorMotion::Spin => (0.0, 1.0, (phase % std::f64::consts::TAU) as f32),
+        };
+        let center = center + Vec2::new(0.0, lift);
+        let color = self.color.unwrap_or(default_color).gamma_multiply(alpha);
+        let (sin, cos) = angle.sin_cos();
+        let at = |x: f32, y: f32| center + Vec2::new(x * cos - y * sin, x * sin + y * cos);
+        let painter = ui.painter();
+        match self.shape {
+            IndicatorShape::Triangle => {
+                painter.add(Shape::convex_polygon(vec![at(-size, -size), at(size, -size), at(0.0, size)], color, Stroke::NONE));
+            }
+            IndicatorShape::Dot => {
+                painter.circle_filled(center, size * 0.6, color);
+            }
+            IndicatorShape::Arrow => {
+                let points = vec![at(-size, -size * 0.4), at(0.0, size * 0.6), at(size, -size * 0.4)];
+                painter.add(Shape::line(points, Stroke::new(size * 0.35, color)));
+            }
+        }
+    }
+}
+
+/// 名字框背景贴图：九宫格拉伸以适应不同长度的名字
+#[derive(Clone, Copy)]
+struct NameBoxSkin<'a> {
+    texture: &'a TextureHandle,
+    border: f32, // 贴图四周不参与拉伸的边宽（贴图像素）
+}
+
+/// 对话框要显示的一段文字（已替换变量）
+#[derive(Clone, Copy)]
+struct DialogueContent<'a> {
+    name: &'a str,         // 说话者名（为空时按旁白显示）
+    affiliation: &'a str,  // 说话者所属
+    content: &'a str,      // 打字机当前显示的部分
+    full_content: &'a str, // 完整文本（用于自动高度与断行的排版测量）
+}
+
+/// 对话框的外观设置：字体、头像、名字框贴图、文字效果、继续指示器与自动高度
+/// 名字框贴图、文字描边/投影与自动高度目前只有蔚蓝档案风格使用
+#[derive(Clone, Copy)]
+struct DialogueSkin<'a> {
+    fonts: &'a DialogueFonts,
+    avatar: Option<&'a TextureHandle>, // 说话者头像（槽位缩略图）
+    name_box: Option<NameBoxSkin<'a>>,
+    effect: &'a TextEffect,
+    indicator: &'a ContinueIndicator,
+    auto_height: bool, // 对话框高度随内容自适应
+}
+
+/// 离线渲染（导出帧序列）状态机
+/// 每推进一帧就请求一次截图，收到截图并保存后才推进下一帧，保证帧序列确定
+struct OfflineRender {
+    out_dir: std::path::PathBuf, // 帧图片输出目录
+    fps: f32,                    // 固定帧率
+    frame_idx: u32,              // 已保存的帧数
+    awaiting_capture: bool,      // 是否正在等待本帧截图
+}
+
+/// 对话框文字字体配置（字号为 720p 下的基准值，更小的屏幕上按比例缩小）
+#[derive(Clone)]
+struct DialogueFonts {
+    name: egui::FontId,    // 说话者名字字体（所属文字按其 0.75 倍绘制）
+    content: egui::FontId, // 对话内容字体
+}
+
+impl Default for DialogueFonts {
+    fn default() -> Self {
+        Self {
+            name: egui::FontId::proportional(30.0),
+            content: egui::FontId::proportional(25.0),
+        }
+    }
+}
+
+impl DialogueFonts {
+    /// 按屏幕高度缩放字号（不放大，最多缩小到约 73%）
+    fn scaled(font: &egui::FontId, screen: Rect) -> egui::FontId {
+        let factor = (screen.height() / BASE_HEIGHT).clamp(0.73, 1.0);
+        egui::FontId::new(font.size * factor, font.family.clone())
+    }
+}
+
+/// 说话弹跳配置
+#[derive(Clone, Copy)]
+struct TalkBounce {
+    enabled: bool,
+    amplitude: f32, // 弹跳幅度（像素，720p 基准）
+    duration: f32,  // 弹跳时长（秒）
+}
+
+impl Default for TalkBounce {
+    fn default() -> Self {
+        Self { enabled: true, amplitude: 12.0, duration: 0.35 }
+    }
+}
+
+/// 自动表情规则：对话内容包含关键词时，说话者在表情轨道上播放对应动画
+#[derive(Clone)]
+struct ExpressionRule {
+    keyword: String,
+    anim_name: String,
+}
+
+/// 默认规则（动画名因模型而异，找不到时不生效）
+fn default_expression_rules() -> Vec<ExpressionRule> {
+    vec![
+        ExpressionRule { keyword: "！".into(), anim_name: "Surprise".into() },
+        ExpressionRule { keyword: "？".into(), anim_name: "Question".into() },
+    ]
+}
+
+/// 打字机音效配置
+#[derive(Clone)]
+struct TypeSound {
+    enabled: bool,
+    volume: f32,          // 音量（0~1）
+    every: usize,         // 每隔几个字符播放一次
+    path: Option<String>, // 音效文件路径（未设置时不播放）
+}
+
+impl Default for TypeSound {
+    fn default() -> Self {
+        Self { enabled: true, volume: 0.5, every: 2, path: None }
+    }
+}
+
+/// 立绘脚底阴影配置
+#[derive(Clone)]
+struct CharacterShadow {
+    enabled: bool,
+    opacity: f32, // 阴影中心的不透明度（0~1），向边缘渐隐
+    offset: f32,  // 阴影相对脚底的下移量（像素，720p 基准）
+}
+
+impl Default for CharacterShadow {
+    fn default() -> Self {
+        Self { enabled: false, opacity: 0.35, offset: 0.0 }
+    }
+}
+
+/// BGM 闪避（ducking）配置：语音或音效播放时压低 BGM，播完后平滑恢复
+#[derive(Clone)]
+struct BgmDuck {
+    enabled: bool,
+    level: f32,   // 压低后的 BGM 音量（0~1，相对原音量）
+    release: f32, // 语音/音效结束后恢复到原音量所用的秒数
+}
+
+impl Default for BgmDuck {
+    fn default() -> Self {
+        Self { enabled: true, level: 0.3, release: 0.8 }
+    }
+}
+
+/// 取一个 [0, 1) 的伪随机数
+/// 没有引入随机数库，用当前时间的纳秒部分作为随机源即可满足切歌、眨眼这类需求
+fn pseudo_random() -> f32 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    (nanos % 1_000_000) as f32 / 1_000_000.0
+}
+
+/// 随机生成下一次自动眨眼前的等待时间
+fn next_blink_delay() -> f32 {
+    BLINK_INTERVAL.0 + pseudo_random() * (BLINK_INTERVAL.1 - BLINK_INTERVAL.0)
+}
+
+/// 把文本中的 `{key}` 替换为变量值；未定义的变量和不成对的花括号原样保留
+fn substitute_variables(text: &str, vars: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}') {
+            Some(end) => {
+                let key = &after[..end];
+                match vars.get(key) {
+                    Some(value) => out.push_str(value),
+                    None => { out.push('{'); out.push_str(key); out.push('}'); }
+                }
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 打字机每次推进显示的单位
+#[derive(Clone, Copy, PartialEq, Default)]
+enum TypeUnit {
+    #[default]
+    Char,      // 逐字（中文默认）
+    Word,      // 逐词：英文按空格分隔的单词，中日文仍按字
+    Paragraph, // 整段瞬显
+}
+
+impl TypeUnit {
+    fn label(self) -> &'static str {
+        match self {
+            TypeUnit::Char => "按字",
+            TypeUnit::Word => "按词",
+            TypeUnit::Paragraph => "整段",
+        }
+    }
+
+    /// 从已显示 `shown` 个字符推进一步后应显示的字符数
+    /// 按词时一步包含前导空白、整个单词及紧随的标点，中英混排时 CJK 字符各自成一步
+    /// 组合 emoji、变体选择符与组合附加符号总是和前面的字符一起出现
+    fn advance(self, chars: &[char], shown: usize) -> usize {
+        let len = chars.len();
+        match self {
+            TypeUnit::Char => cluster_end(chars, (shown + 1).min(len)),
+            TypeUnit::Paragraph => len,
+            TypeUnit::Word => {
+                let is_word = |c: char| c.is_alphanumeric() && !is_cjk(c);
+                let mut i = shown;
+                while i < len && chars[i].is_whitespace() {
+                    i += 1;
+                }
+                if i < len && is_word(chars[i]) {
+                    // 单词内的撇号与连字符（don't、well-known）不拆开
+                    while i < len && (is_word(chars[i])
+                        || ("'-’".contains(chars[i]) && chars.get(i + 1).map_or(false, |&c| is_word(c))))
+                    {
+                        i += 1;
+                    }
+                } else if i < len {
+                    i = cluster_end(chars, i + 1);
                 }
+                while i < len && !chars[i].is_whitespace() && !is_word(chars[i]) && !is_cjk(chars[i]) {
+                    i += 1;
+                }
+                i.max((shown + 1).min(len))
+            }
+        }
+    }
+}
+
+/// 从 `end`（某个字符之后的位置）向后扩展到整个字形簇的末尾
+/// 覆盖零宽连接符序列（👨‍👩‍👧）、肤色修饰、变体选择符、键帽、旗帜（成对的区域指示符）与组合附加符号
+fn cluster_end(chars: &[char], mut end: usize) -> usize {
+    let is_regional = |c: char| matches!(c as u32, 0x1F1E6..=0x1F1FF);
+    let is_extender = |c: char| matches!(c as u32,
+        0x0300..=0x036F | 0x20D0..=0x20FF | 0xFE00..=0xFE0F | 0x1F3FB..=0x1F3FF | 0xE0020..=0xE007F);
+    if end == 0 || end > chars.len() {
+        return end.min(chars.len());
+    }
+    // 旗帜：两个区域指示符组成一个字形
+    if is_regional(chars[end - 1]) && chars.get(end).map_or(false, |&c| is_regional(c))
+        && chars[..end - 1].iter().rev().take_while(|&&c| is_regional(c)).count() % 2 == 0
+    {
+        end += 1;
+    }
+    loop {
+        match chars.get(end) {
+            Some(&c) if is_extender(c) => end += 1,
+            Some('\u{200D}') => end = (end + 2).min(chars.len()),
+            _ => return end,
+        }
+    }
+}
+
+/// 中日韩文字（含假名、全角字符）：按词显示时每个字单独成一步
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x2E80..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF | 0xFF00..=0xFFEF | 0x20000..=0x2FA1F)
+}
+
+/// 打字机音效在标点和空白处停顿，不发声
+fn is_typing_pause(c: char) -> bool {
+    c.is_whitespace() || c.is_ascii_punctuation() || "，。！？、；：…—「」『』（）《》“”‘’～".contains(c)
+}
+
+/// BGM 播放列表模式
+#[derive(Clone, Copy, PartialEq, Default)]
+enum PlaylistMode {
+    #[default]
+    Sequential, // 顺序播放，播完最后一首回到第一首
+    Shuffle,    // 随机播放（不连续重复同一首）
+    RepeatOne,  // 单曲循环
+}
+
+impl PlaylistMode {
+    /// 模式显示名称
+    fn label(self) -> &'static str {
+        match self {
+            PlaylistMode::Sequential => "顺序",
+            PlaylistMode::Shuffle => "随机",
+            PlaylistMode::RepeatOne => "单曲循环",
+        }
+    }
+}
+
+/// BGM 播放列表状态
+#[derive(Default)]
+struct BgmPlaylist {
+    tracks: Vec<String>, // 曲目路径
+    mode: PlaylistMode,
+    current: usize,      // 当前曲目索引
+    active: bool,        // 是否由播放列表接管 BGM（单独播放/停止 BGM 时退出）
+    loading: bool,       // 当前曲目正在读取/解码（此时混音器为空不代表播完）
+    failures: usize,     // 连续播放失败的曲目数（全部失败时停止列表）
+}
+
+impl BgmPlaylist {
+    /// 当前曲目播完后应播放的曲目索引
+    fn next_index(&self) -> usize {
+        let n = self.tracks.len().max(1);
+        match self.mode {
+            PlaylistMode::Sequential => (self.current + 1) % n,
+            PlaylistMode::RepeatOne => self.current.min(n - 1),
+            PlaylistMode::Shuffle => {
+                if n == 1 { return 0; }
+                let skip = ((pseudo_random() * (n - 1) as f32) as usize).min(n - 2);
+                (self.current + 1 + skip) % n
+            }
+        }
+    }
+}
+
+/// 资源检查发现的缺失资源
+struct MissingResource {
+    scene_idx: usize,  // 所在幕索引
+    kind: String,      // 资源类型（背景/BGM/角色槽位）
+    path: String,      // 剧本中记录的路径
+}
+
+/// 完整剧本（多幕场景集合）
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Scenario {
+    scenes: Vec<Scene>,
+}
+
+/// 多章节项目：按顺序引用多个剧本文件作为章节，相对路径基于项目文件所在目录
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Project {
+    chapters: Vec<std::path::PathBuf>,
+}
+
+/// 单个槽位角色的摆放（横向位置、手动偏移、缩放、朝向）
+#[derive(Serialize, Deserialize, Clone)]
+struct SlotTransform {
+    layout_x: f32,
+    offset: [f32; 2],
+    user_scale: f32,
+    flip_x: bool,
+    #[serde(default)]
+    depth: f32,
+}
+
+/// 创作者面板各子面板的开关（打开后可分别拖动、缩放）
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct CreatorPanels {
+    scenes: bool,    // 剧本幕数管理、保存加载与导出
+    resources: bool, // 槽位、立绘、背景与构图
+    audio: bool,     // BGM、音效、语音与播放列表
+    dialogue: bool,  // 当前幕对话编辑
+    console: bool,   // 命令行与日志
+}
+
+impl Default for CreatorPanels {
+    fn default() -> Self {
+        Self { scenes: true, resources: true, audio: true, dialogue: true, console: true }
+    }
+}
+
+/// 跨次启动保留的界面状态（经 eframe 持久化存储读写）
+#[derive(Serialize, Deserialize, Default)]
+struct PersistedState {
+    window_size: Option<[f32; 2]>,               // 窗口内部尺寸（逻辑像素）
+    console_open: bool,                          // 创作者面板是否打开
+    selected_slot: usize,                        // 选中的槽位
+    recent_file: Option<std::path::PathBuf>,     // 最近打开/保存的剧本
+    #[serde(default)]
+    name_box: Option<(String, f32)>,             // 名字框贴图路径与九宫格边宽
+    #[serde(default)]
+    panels: CreatorPanels,                       // 创作者面板各子面板的开关
+}
+
+/// 命名构图预设：保存时各槽位角色的摆放，空槽位为 None
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct LayoutSnapshot {
+    slots: Vec<Option<SlotTransform>>,
+}
+
+/// 读取磁盘上的构图预设，文件不存在或损坏时返回空表
+fn load_layout_snapshots() -> HashMap<String, LayoutSnapshot> {
+    std::fs::read_to_string(LAYOUTS_FILE).ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// 控制台命令注册表（命令名, 参数说明），用于输入时的补全提示
+const CONSOLE_COMMANDS: &[(&str, &str)] = &[
+    ("load", "[槽位] [路径] [初始动画]"),
+    ("anim", "[槽位] [动画名] [循环]"),
+    ("skin", "[槽位] [皮肤名]"),
+    ("attach", "[槽位] [插槽名] [附件名|none]"),
+    ("bgm", "[路径]"),
+    ("se", "[路径]"),
+    ("bg", "[路径]"),
+    ("fg", "[路径]（不带路径则移除前景）"),
+    ("bgcolor", "[#RRGGBB] [#RRGGBB] [h]（一个颜色为纯色，两个为渐变；off 恢复图片）"),
+    ("playlist", "[路径1] [路径2] ...（不带参数则停止）"),
+    ("stop", ""),
+    ("render", "[输出目录] [帧率]"),
+    ("gif", "[槽位] [动画名] [输出路径]"),
+    ("html", "[输出路径]"),
+    ("set", "[变量名] [值]"),
+    ("run", "[脚本文件路径]"),
+    ("font", "[字体文件路径]"),
+    ("emojifont", "[字体文件路径]"),
+    ("namebox", "[图片路径]（不带路径则恢复默认渐变）"),
+    ("fade", "[槽位] [目标透明度] [时长]"),
+    ("stagger", "[每人间隔秒数]"),
+    ("interleave", "[槽位A] [槽位B] [前移量]（off 关闭）"),
+    ("face", "[槽位A] [槽位B]（off 关闭）"),
+    ("filter", "[none|gray|sepia|invert]"),
+    ("chapter", "[章节序号]（切换到项目中的指定章节）"),
+    ("bone", "[槽位] [骨骼名] [角度|reset]"),
+    ("pause", ""),
+    ("resume", ""),
+    ("talk", "[姓名]|[所属]|[内容]|[说话者槽位]"),
+];
+
+/// 新建剧本向导中的内置模板
+#[derive(Clone, Copy, PartialEq, Default)]
+enum ScenarioTemplate {
+    #[default]
+    Blank,          // 空白：一幕空对话
+    TwoPersonTalk,  // 双人对话：两个说话者轮流发言
+    NarrationOpening, // 旁白开场：旁白铺垫后主角登场
+}
+
+impl ScenarioTemplate {
+    fn label(self) -> &'static str {
+        match self {
+            ScenarioTemplate::Blank => "空白",
+            ScenarioTemplate::TwoPersonTalk => "双人对话",
+            ScenarioTemplate::NarrationOpening => "旁白开场",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            ScenarioTemplate::Blank => "只有一幕空白对话，从零开始。",
+            ScenarioTemplate::TwoPersonTalk => "4 幕，槽位 0 与槽位 1 的两名角色轮流发言。",
+            ScenarioTemplate::NarrationOpening => "3 幕，先用两段旁白交代背景，再由主角登场。",
+        }
+    }
+
+    /// 生成带占位说话者与台词的剧本
+    fn build(self) -> Scenario {
+        let line = |name: &str, aff: &str, content: &str, slot: Option<usize>| Scene {
+            speaker_name: name.into(),
+            speaker_aff: aff.into(),
+            dialogue_content: content.into(),
+            speaker_slot: slot,
+            ..Scene::default()
+        };
+        let scenes = match self {
+            ScenarioTemplate::Blank => vec![Scene::default()],
+            ScenarioTemplate::TwoPersonTalk => vec![
+                line("角色A", "所属A", "（角色A 的第一句台词）", Some(0)),
+                line("角色B", "所属B", "（角色B 的回应）", Some(1)),
+                line("角色A", "所属A", "（角色A 的第二句台词）", Some(0)),
+                line("角色B", "所属B", "（角色B 的结束语）", Some(1)),
+            ],
+            ScenarioTemplate::NarrationOpening => vec![
+                line("", "", "（旁白：交代时间与地点）", None),
+                line("", "", "（旁白：引出主角）", None),
+                line("主角", "所属", "（主角登场的第一句台词）", Some(0)),
+            ],
+        };
+        Scenario { scenes }
+    }
+}
+
+/// 两版剧本之间一幕的变化（幕号均为 0 起的索引）
+enum SceneChange {
+    Added(usize),                                          // 新版本中新增的幕
+    Removed(usize),                                        // 旧版本中被删除的幕
+    Modified { old: usize, new: usize, fields: Vec<FieldChange> }, // 同一位置被修改的幕
+}
+
+/// 单个字段的文本变化
+struct FieldChange {
+    field: String,
+    old: String,
+    new: String,
+}
+
+/// 剧本对比窗口的状态：旧版本来自文件，新版本来自文件或当前剧本（None）
+#[derive(Default)]
+struct ScenarioDiffView {
+    old: Option<(String, Scenario)>,  // （文件名, 剧本）
+    new: Option<(String, Scenario)>,  // None 时与当前编辑中的剧本对比
+    changes: Vec<SceneChange>,
+    unchanged: usize,                 // 完全相同的幕数
+}
+
+/// 读取并解析剧本文件（旧版剧本的背景路径并入新的背景字段）
+fn read_scenario_file(path: &std::path::Path) -> Result<Scenario, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut scenario = serde_json::from_str::<Scenario>(&data).map_err(|e| e.to_string())?;
+    for scene in &mut scenario.scenes {
+        if let Some(path) = scene.legacy_bg_path.take() {
+            if scene.background == SceneBackground::Image(None) {
+                scene.background = SceneBackground::Image(Some(path));
             }
-        } else if cmd_lower.starts_with("bgm ") { // bgm[路径]
-             let _ = tx.send(AppCommand::PlayBgm(input_trimmed[4..].trim().replace("\"", "")));
-        } else if cmd_lower.starts_with("se ") { // se [路径]
-             let _ = tx.send(AppCommand::PlaySe(input_trimmed[3..].trim().replace("\"", "")));
-        } else if cmd_lower == "stop" { // stop
-             let _ = tx.send(AppCommand::StopBgm);
-        } else if cmd_lower.starts_with("talk ") { // talk [姓名]|[所属]|[内容]
-            let p: Vec<&str> = input_trimmed[5..].split('|').collect();
-            if p.len() == 3 {
-                let _ = tx.send(AppCommand::Dialogue { 
-                    name: p[0].into(), 
-                    affiliation: p[1].into(), 
-                    content: p[2].into() 
+        }
+    }
+    Ok(scenario)
+}
+
+/// 把一幕展开为（字段名, 文本值）列表，用于字段级对比
+fn scene_fields(scene: &Scene) -> Vec<(String, String)> {
+    let opt = |v: &Option<String>| v.clone().unwrap_or_default();
+    let mut fields = vec![
+        ("说话者".to_string(), scene.speaker_name.clone()),
+        ("所属".to_string(), scene.speaker_aff.clone()),
+        ("对话".to_string(), scene.dialogue_content.clone()),
+        ("说话槽位".to_string(), scene.speaker_slot.map(|s| s.to_string()).unwrap_or_default()),
+        ("背景".to_string(), opt(&scene.background.image_path().cloned())),
+        ("背景类型".to_string(), scene.background.label().to_string()),
+        ("前景".to_string(), opt(&scene.foreground_path)),
+        ("BGM".to_string(), opt(&scene.bgm_path)),
+        ("BGM 循环起点".to_string(), scene.bgm_loop_start.map(|t| format!("{:.2} 秒", t)).unwrap_or_default()),
+        ("语音".to_string(), opt(&scene.voice_path)),
+        ("选项".to_string(), scene.choices.iter().map(|(text, target)| format!("{} → 第 {} 幕", text, target + 1)).collect::<Vec<_>>().join(" / ")),
+        ("过渡".to_string(), scene.transition.map(|t| format!("{} {:.2} 秒", t.kind.label(), t.duration)).unwrap_or_default()),
+        ("环境光".to_string(), format!("{:?}", scene.ambient_tint.to_array())),
+        ("滤镜".to_string(), scene.color_filter.label().to_string()),
+        ("定时推进".to_string(), scene.auto_advance_after.map(|t| format!("{:.1} 秒", t)).unwrap_or_default()),
+        ("备注".to_string(), scene.notes.clone()),
+    ];
+    for i in 0..scene.char_paths.len() {
+        fields.push((format!("槽位 {} 立绘", i), opt(&scene.char_paths[i])));
+        fields.push((format!("槽位 {} 动画", i), opt(&scene.char_anims[i])));
+        fields.push((format!("槽位 {} 皮肤", i), opt(&scene.char_skins[i])));
+        fields.push((format!("槽位 {} 附件", i), scene.char_attachments[i].iter()
+            .map(|(slot, att)| format!("{}={}", slot, att.as_deref().unwrap_or("隐藏")))
+            .collect::<Vec<_>>().join(", ")));
+    }
+    fields
+}
+
+/// 逐幕对比两版剧本
+/// 先按整幕相等求最长公共子序列对齐两边，未对齐的幕在同一空隙内按顺序两两配对视为修改，多出的为新增/删除
+fn diff_scenarios(old: &Scenario, new: &Scenario) -> (Vec<SceneChange>, usize) {
+    let (a, b) = (&old.scenes, &new.scenes);
+    // lcs[i][j]：a[i..] 与 b[j..] 的最长公共子序列长度
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    loop {
+        let matched = i < a.len() && j < b.len() && a[i] == b[j];
+        let at_end = i == a.len() && j == b.len();
+        if matched || at_end {
+            // 结束一段空隙：配对的视为修改，其余为删除/新增
+            for k in 0..removed.len().max(added.len()) {
+                changes.push(match (removed.get(k), added.get(k)) {
+                    (Some(&o), Some(&n)) => {
+                        let fields = scene_fields(&a[o]).into_iter().zip(scene_fields(&b[n]))
+                            .filter(|((_, x), (_, y))| x != y)
+                            .map(|((field, old), (_, new))| FieldChange { field, old, new })
+                            .collect();
+                        SceneChange::Modified { old: o, new: n, fields }
+                    }
+                    (Some(&o), None) => SceneChange::Removed(o),
+                    (None, Some(&n)) => SceneChange::Added(n),
+                    (None, None) => unreachable!(),
                 });
             }
-        } else if cmd_lower.starts_with("bg ") { // bg [路径]
-            let _ = tx.send(AppCommand::LoadBackground(input_trimmed[3..].trim().replace("\"", "")));
+            removed.clear();
+            added.clear();
+            if at_end {
+                break;
+            }
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            added.push(j);
+            j += 1;
+        } else {
+            removed.push(i);
+            i += 1;
         }
     }
+    (changes, lcs[0][0])
+}
 
-    /// 处理异步事件（从其他线程接收）
-    fn handle_async_events(&mut self, ctx: &egui::Context) {
-        while let Ok(cmd) = self.rx.try_recv() {
-            match cmd {
-                // 更新对话内容
-                AppCommand::Dialogue { name, affiliation, content } => {
-                    let scene = &mut self.scenario.scenes[self.current_scene_idx];
-                    scene.speaker_name = name; 
-                    scene.speaker_aff = affiliation; 
-                    scene.dialogue_content = content;
-                    self.sync_scene_to_ui(); 
-                    self.visible_count = 0; // 触发打字机效果
+/// 剧本统计信息
+struct ScenarioStats {
+    scene_count: usize,
+    char_count: usize,                      // 对话总字符数（不含空白，中文按字计）
+    speakers: Vec<(String, usize, usize)>,  // （说话者, 出场幕数, 台词字符数），按出场幕数降序
+    bg_count: usize,                        // 用到的不同背景数
+    bgm_count: usize,                       // 用到的不同 BGM 数
+}
+
+impl Scenario {
+    /// 检查旧剧本或手改 JSON 中越界的槽位与选项引用：越界的说话者槽位清除，越界的选项移除
+    /// 返回每处修正的说明，供调用方写入日志
+    fn sanitize(&mut self) -> Vec<String> {
+        let scene_count = self.scenes.len();
+        let mut warnings = Vec::new();
+        for (i, scene) in self.scenes.iter_mut().enumerate() {
+            let slot_count = scene.char_paths.len();
+            if let Some(slot) = scene.speaker_slot.filter(|&s| s >= slot_count) {
+                warnings.push(format!("第 {} 幕的说话者槽位 {} 超出范围（0~{}），已清除", i + 1, slot, slot_count - 1));
+                scene.speaker_slot = None;
+            }
+            scene.choices.retain(|(text, target)| {
+                let valid = *target < scene_count;
+                if !valid {
+                    warnings.push(format!("第 {} 幕的选项「{}」指向不存在的第 {} 幕，已移除", i + 1, text, target + 1));
                 }
-                // 日志记录
-                AppCommand::Log(msg) => self.console_logs.push(msg),
-                
-                // 异步加载角色资源
-                AppCommand::RequestLoad { slot_idx, path } => {
-                    let tx_cb = self.tx.clone(); 
-                    self.console_logs.push(format!("[解析] {}", path));
-                    
-                    let path_clone = path.clone();
-                    thread::spawn(move || {
-                        match SpineObject::load_async_no_gpu(&path_clone) {
-                            Ok((obj, img, page, anims)) => { 
-                                let _ = tx_cb.send(AppCommand::LoadSuccess(slot_idx, Box::new(obj), img, page, anims)); 
-                            },
-                            Err(e) => { 
-                                let _ = tx_cb.send(AppCommand::Log(format!("[错误] {}", e))); 
+                valid
+            });
+        }
+        warnings
+    }
+
+    /// 遍历所有幕计算统计信息
+    fn stats(&self) -> ScenarioStats {
+        let count_chars = |s: &str| dialogue_segments(s).iter().flat_map(|seg| seg.chars()).filter(|c| !c.is_whitespace()).count();
+        let mut speakers: Vec<(String, usize, usize)> = Vec::new();
+        let mut bgs: Vec<&String> = Vec::new();
+        let mut bgms: Vec<&String> = Vec::new();
+        for scene in &self.scenes {
+            let chars = count_chars(&scene.dialogue_content);
+            let name = if scene.speaker_name.trim().is_empty() { "（旁白）" } else { scene.speaker_name.trim() };
+            match speakers.iter_mut().find(|(n, _, _)| n == name) {
+                Some(entry) => { entry.1 += 1; entry.2 += chars; }
+                None => speakers.push((name.to_string(), 1, chars)),
+            }
+            if let Some(p) = scene.background.image_path() { if !bgs.contains(&p) { bgs.push(p); } }
+            if let Some(p) = &scene.bgm_path { if !bgms.contains(&p) { bgms.push(p); } }
+        }
+        speakers.sort_by(|a, b| b.1.cmp(&a.1));
+        ScenarioStats {
+            scene_count: self.scenes.len(),
+            char_count: self.scenes.iter().map(|s| count_chars(&s.dialogue_content)).sum(),
+            speakers,
+            bg_count: bgs.len(),
+            bgm_count: bgms.len(),
+        }
+    }
+}
+
+// ============================================================================
+// 程序入口点（跨平台处理）
+// ============================================================================
+
+#[cfg(not(target_os = "android"))]
+fn main() -> eframe::Result<()> {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([1280.0, 720.0]) // 默认窗口大小
+            .with_title("AEFR Editor - OxidizedSchale Edition"),
+        vsync: true, // 开启垂直同步
+        multisampling: 4, // 4x MSAA：立绘 mesh 缩小显示时边缘不出现锯齿
+        ..Default::default()
+    };
+    eframe::run_native("AEFR_App", options, Box::new(|cc| Box::new(AefrApp::new(cc))))
+}
+
+/// 启动参数中的剧本文件路径（双击剧本文件或 `aefr path/to/scenario.json` 启动时）
+#[cfg(not(target_os = "android"))]
+fn startup_scenario_path() -> Option<std::path::PathBuf> {
+    std::env::args_os().skip(1).map(std::path::PathBuf::from).find(|p| p.is_file())
+}
+
+/// Android 上通过 intent 打开的剧本（`android_main` 启动时读出，创建应用时取用）
+#[cfg(target_os = "android")]
+static ANDROID_INTENT_SCENARIO: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+
+/// Android 没有命令行参数，改用启动 Activity 的 intent 携带的剧本（从文件管理器或分享打开时）
+#[cfg(target_os = "android")]
+fn startup_scenario_path() -> Option<std::path::PathBuf> {
+    ANDROID_INTENT_SCENARIO.get().cloned()
+}
+
+/// 读取启动 intent 的数据 URI，返回可以按普通文件读取的剧本路径；调用失败时清除 Java 异常后返回 None
+#[cfg(target_os = "android")]
+fn android_intent_scenario(app: &android_activity::AndroidApp) -> Option<std::path::PathBuf> {
+    // 必要性证明：VM 与 Activity 指针由 android-activity 提供，在 android_main 运行期间一直有效
+    let vm = unsafe { jni::JavaVM::from_raw(app.vm_as_ptr() as *mut jni::sys::JavaVM) }.ok()?;
+    let mut env = vm.attach_current_thread().ok()?;
+    let activity = unsafe { jni::objects::JObject::from_raw(app.activity_as_ptr() as jni::sys::jobject) };
+    let path = read_intent_uri(&mut env, app, &activity);
+    let _ = env.exception_clear();
+    path
+}
+
+/// file:// 直接取路径；content://（文件管理器、聊天软件分享）经 ContentResolver 打开后复制到应用内部目录
+#[cfg(target_os = "android")]
+fn read_intent_uri(env: &mut jni::JNIEnv, app: &android_activity::AndroidApp, activity: &jni::objects::JObject) -> Option<std::path::PathBuf> {
+    use jni::objects::{JObject, JString, JValue};
+    fn call_object<'local>(env: &mut jni::JNIEnv<'local>, obj: &JObject, name: &str, sig: &str, args: &[JValue]) -> Option<JObject<'local>> {
+        let value = env.call_method(obj, name, sig, args).ok()?.l().ok()?;
+        (!value.is_null()).then_some(value)
+    }
+    fn call_string(env: &mut jni::JNIEnv, obj: &JObject, name: &str) -> Option<String> {
+        let value = call_object(env, obj, name, "()Ljava/lang/String;", &[])?;
+        env.get_string(&JString::from(value)).ok().map(String::from)
+    }
+    let intent = call_object(env, activity, "getIntent", "()Landroid/content/Intent;", &[])?;
+    let uri = call_object(env, &intent, "getData", "()Landroid/net/Uri;", &[])?;
+    match call_string(env, &uri, "getScheme")?.as_str() {
+        "file" => call_string(env, &uri, "getPath").map(std::path::PathBuf::from),
+        "content" => {
+            use std::os::fd::FromRawFd;
+            let resolver = call_object(env, activity, "getContentResolver", "()Landroid/content/ContentResolver;", &[])?;
+            let mode = JObject::from(env.new_string("r").ok()?);
+            let descriptor = call_object(env, &resolver, "openFileDescriptor",
+                "(Landroid/net/Uri;Ljava/lang/String;)Landroid/os/ParcelFileDescriptor;",
+                &[JValue::Object(&uri), JValue::Object(&mode)])?;
+            let fd = env.call_method(&descriptor, "detachFd", "()I", &[]).ok()?.i().ok()?;
+            // 必要性证明：detachFd 把文件描述符的所有权交给调用方，由 File 负责关闭
+            let mut source = unsafe { std::fs::File::from_raw_fd(fd) };
+            let target = app.internal_data_path()?.join("intent_scenario.json");
+            let mut out = std::fs::File::create(&target).ok()?;
+            std::io::copy(&mut source, &mut out).ok()?;
+            Some(target)
+        }
+        _ => None,
+    }
+}
+
+// Android平台特殊入口点
+#[cfg(target_os = "android")]
+fn main() -> eframe::Result<()> {
+    eframe::run_native("AEFR_App", eframe::NativeOptions::default(), Box::new(|cc| Box::new(AefrApp::new(cc))))
+}
+
+// Android JNI入口（供原生Activity调用）
+#[cfg(target_os = "android")]
+#[no_mangle]
+fn android_main(app: android_activity::AndroidApp) {
+    if let Some(path) = android_intent_scenario(&app) {
+        let _ = ANDROID_INTENT_SCENARIO.set(path);
+    }
+    let _ = eframe::run_native("AEFR_App", eframe::NativeOptions::default(), Box::new(|cc| Box::new(AefrApp::new(cc))));
+}
+
+// ============================================================================
+// 核心架构组件
+// ============================================================================
+
+/// 绅士调度器 - 防止计算线程抢占UI/音频线程
+struct AefrScheduler { pool: rayon::ThreadPool }
+impl AefrScheduler {
+    fn new() -> Self {
+        let logic_cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        Self { 
+            pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(if logic_cores > 2 { logic_cores - 2 } else { 1 }) // 保留2个核心给系统
+                .build().unwrap() 
+        }
+    }
+    fn run_parallel<OP>(&self, op: OP) where OP: FnOnce() + Send { 
+        self.pool.install(op); // 在专用线程池中执行并行任务
+    }
+}
+
+/// 应用命令枚举 - 线程间消息传递的指令
+enum AppCommand {
+    Dialogue { name: String, affiliation: String, content: String, speaker_slot: Option<usize> }, // 更新对话
+    RequestLoad { slot_idx: usize, path: String, initial_anim: Option<String>, priority: LoadPriority }, // 异步加载角色（可指定初始动画与调度优先级）
+    PreloadReady(String, Box<SpineObject>, Vec<(String, egui::ColorImage)>, usize), // 预加载完成（资源路径、解析结果、各 atlas 页纹理、骨骼文件字节数），只放入缓存
+    LoadSuccess(usize, u64, String, Box<SpineObject>, Vec<(String, egui::ColorImage)>, Vec<String>, usize), // 加载成功回调（槽位、任务编号、资源路径、各 atlas 页纹理、动画名、骨骼文件字节数）
+    LoadProgress(usize, f32, LoadStage), // 角色加载进度（槽位, 0~1, 阶段）
+    LoadFailed(usize, u64, String), // 角色加载失败（槽位, 任务编号, 错误信息）
+    ChooseZipAtlas(ZipChoice), // 压缩包内有多个立绘，等待用户选择
+    RemoveCharacter(usize), // 移除角色
+    LoadBackground(String), // 加载背景
+    LoadBackgroundSuccess(Arc<egui::ColorImage>, Arc<egui::ColorImage>, ColorFilter), // 背景加载成功（已套滤镜的原图, 缩略图用小图, 所用滤镜）
+    LoadForeground(String), // 加载前景
+    LoadForegroundSuccess(Arc<egui::ColorImage>, Arc<egui::ColorImage>, ColorFilter), // 前景加载成功（同背景）
+    LoadNameBox(Option<String>), // 设置名字框贴图（None 恢复默认渐变）
+    LoadNameBoxSuccess(String, egui::ColorImage), // 名字框贴图加载成功（路径, 图片）
+    ClearForeground, // 移除前景
+    PlayBgm(String), // 播放背景音乐
+    PlaySe(String), // 播放音效
+    PlayVoice(String), // 播放当前幕语音
+    VoiceReady(u64, Vec<u8>), // 语音数据就绪（请求编号, 数据；读取失败时为空）
+    AudioReady(String, Vec<u8>, bool), // 音频数据就绪（路径, 数据, 是否BGM）
+    StopBgm, // 停止背景音乐
+    SetBgmPlaylist(Vec<String>), // 设置并开始播放BGM播放列表（空列表则停止）
+    SetAnimation { slot_idx: usize, anim_name: String, loop_anim: bool }, // 设置动画
+    SetSkin { slot_idx: usize, skin_name: String }, // 切换皮肤
+    SetAttachment { slot_idx: usize, slot_name: String, attachment: Option<String> }, // 设置插槽附件（None 为隐藏）
+    SeekAnimation { slot_idx: usize, time: f32 }, // 定位当前动画到指定时间
+    FadeCharacter { slot_idx: usize, target_opacity: f32, duration: f32 }, // 角色不透明度渐变（淡入淡出）
+    StaggerEnter { delay_per_char: f32 }, // 在场角色从左到右依次滑入
+    SetBoneRotation { slot_idx: usize, bone: String, degrees: Option<f32> }, // 手动设置骨骼旋转（None 恢复动画控制）
+    SetPaused(bool), // 暂停/恢复全部动画
+    StartOfflineRender { out_dir: String, fps: f32 }, // 开始离线渲染帧序列
+    ExportGif(GifExport), // 把角色的一个动画周期导出为 GIF
+    ExportHtml(String), // 把剧本导出为自包含的 HTML 播放页（输出路径）
+    Log(String), // 日志记录
+    LoadFont(String), // 加载外部字体文件
+    FontReady(String, Vec<u8>), // 字体数据就绪（字体名, 数据）
+    LoadFallbackFont(String), // 加载 emoji/符号回退字体
+    FallbackFontReady(String, Vec<u8>), // 回退字体数据就绪（字体名, 数据）
+    LoadTypeSound(String), // 加载打字机音效
+    TypeSoundReady(Vec<u8>), // 打字机音效数据就绪
+    RunScript(String), // 从文件读取命令脚本并逐行执行
+    ScriptReady(String), // 脚本读取完成（脚本全文）
+    ThumbnailReady(ThumbnailTarget, egui::ColorImage), // 后台光栅化的缩略图完成
+    CharacterPagesReady(u64, ColorFilter, Vec<(Arc<egui::ColorImage>, Arc<egui::ColorImage>)>), // 后台处理好的立绘贴图（请求编号, 滤镜, （原图, 处理后的贴图））
+    Playback(Box<AppCommand>), // 播放流程发出的命令（切幕恢复画面、试听等）：照常处理，但不算对剧本的修改
+}
+
+impl AppCommand {
+    /// 命令作用的角色槽位（用于统一的越界检查），与槽位无关的命令为 None
+    fn slot_index(&self) -> Option<usize> {
+        match self {
+            AppCommand::RequestLoad { slot_idx, .. } | AppCommand::SetAnimation { slot_idx, .. } |
+            AppCommand::SetSkin { slot_idx, .. } | AppCommand::SetAttachment { slot_idx, .. } |
+            AppCommand::SeekAnimation { slot_idx, .. } | AppCommand::FadeCharacter { slot_idx, .. } |
+            AppCommand::SetBoneRotation { slot_idx, .. } => Some(*slot_idx),
+            AppCommand::RemoveCharacter(idx) => Some(*idx),
+            AppCommand::Dialogue { speaker_slot, .. } => *speaker_slot,
+            AppCommand::ExportGif(job) => Some(job.slot_idx),
+            _ => None,
+        }
+    }
+
+    /// 该命令是否会修改剧本内容（用于标记未保存；经 `AppCommand::Playback` 发出的不计）
+    fn modifies_scenario(&self) -> bool {
+        matches!(self,
+            AppCommand::Dialogue { .. } | AppCommand::RequestLoad { .. } | AppCommand::RemoveCharacter(_) |
+            AppCommand::LoadBackground(_) | AppCommand::LoadForeground(_) | AppCommand::ClearForeground |
+            AppCommand::PlayBgm(_) | AppCommand::StopBgm |
+            AppCommand::SetAnimation { .. } | AppCommand::SetSkin { .. } | AppCommand::SetAttachment { .. }
+        )
+    }
+}
+
+/// BGM 频谱采样器：包装音频源，把播放出的样本（各声道取平均）转交给 UI 线程分析
+/// 音频线程里只攒批并用 try_lock 提交，拿不到锁时丢弃这一批，绝不阻塞播放
+struct SpectrumTap<S> {
+    inner: S,
+    shared: Arc<std::sync::Mutex<VecDeque<f32>>>,
+    batch: Vec<f32>,
+    frame_sum: f32, // 当前帧已读声道的样本和
+    frame_pos: u16, // 当前帧已读的声道数
+}
+
+impl<S> SpectrumTap<S> {
+    fn new(inner: S, shared: Arc<std::sync::Mutex<VecDeque<f32>>>) -> Self {
+        Self { inner, shared, batch: Vec::with_capacity(SPECTRUM_BATCH), frame_sum: 0.0, frame_pos: 0 }
+    }
+}
+
+impl<S> Iterator for SpectrumTap<S> where S: Source, S::Item: rodio::Sample {
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        let sample = self.inner.next()?;
+        self.frame_sum += sample.to_f32();
+        self.frame_pos += 1;
+        if self.frame_pos >= self.inner.channels().max(1) {
+            self.batch.push(self.frame_sum / self.frame_pos as f32);
+            self.frame_sum = 0.0;
+            self.frame_pos = 0;
+            if self.batch.len() >= SPECTRUM_BATCH {
+                if let Ok(mut shared) = self.shared.try_lock() {
+                    shared.extend(self.batch.drain(..));
+                    let excess = shared.len().saturating_sub(SPECTRUM_WINDOW);
+                    shared.drain(..excess);
+                }
+                self.batch.clear();
+            }
+        }
+        Some(sample)
+    }
+}
+
+impl<S> Source for SpectrumTap<S> where S: Source, S::Item: rodio::Sample {
+    fn current_frame_len(&self) -> Option<usize> { self.inner.current_frame_len() }
+    fn channels(&self) -> u16 { self.inner.channels() }
+    fn sample_rate(&self) -> u32 { self.inner.sample_rate() }
+    fn total_duration(&self) -> Option<std::time::Duration> { self.inner.total_duration() }
+}
+
+/// 原地基 2 FFT（长度必须为 2 的幂）
+fn fft_in_place(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    // 位反转重排
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    // 逐级蝶形合并
+    let mut len = 2;
+    while len <= n {
+        let angle = -std::f32::consts::TAU / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// 把一窗样本分析为 `bands` 个对数频段的强度（0~1，按分贝映射）
+fn spectrum_bands(samples: &[f32], bands: usize) -> Vec<f32> {
+    let n = samples.len();
+    if n < 4 || bands == 0 {
+        return vec![0.0; bands];
+    }
+    // 汉宁窗减少频谱泄漏
+    let mut re: Vec<f32> = samples.iter().enumerate()
+        .map(|(i, s)| s * 0.5 * (1.0 - (std::f32::consts::TAU * i as f32 / (n - 1) as f32).cos()))
+        .collect();
+    let mut im = vec![0.0; n];
+    fft_in_place(&mut re, &mut im);
+    let half = n / 2;
+    let full_scale = n as f32 / 4.0; // 满幅正弦加汉宁窗后的频点幅度
+    (0..bands).map(|b| {
+        let lo = ((half as f32).powf(b as f32 / bands as f32) as usize).max(1);
+        let hi = ((half as f32).powf((b + 1) as f32 / bands as f32) as usize).clamp(lo + 1, half);
+        let peak = (lo..hi).map(|k| (re[k] * re[k] + im[k] * im[k]).sqrt()).fold(0.0, f32::max);
+        let db = 20.0 * (peak / full_scale).max(1e-6).log10();
+        ((db + SPECTRUM_FLOOR_DB) / SPECTRUM_FLOOR_DB).clamp(0.0, 1.0)
+    }).collect()
+}
+
+/// UI 线程上的频谱分析状态：最近一窗样本与平滑后的频谱条高度
+#[derive(Default)]
+struct SpectrumAnalyzer {
+    window: VecDeque<f32>,
+    bars: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    /// 追加新样本并重新分析；没有新样本（BGM 停止或暂停）时各条逐渐回落
+    fn update(&mut self, samples: Vec<f32>, dt: f32) {
+        let fresh = !samples.is_empty();
+        self.window.extend(samples);
+        let excess = self.window.len().saturating_sub(SPECTRUM_WINDOW);
+        self.window.drain(..excess);
+        let target = if fresh && self.window.len() == SPECTRUM_WINDOW {
+            spectrum_bands(self.window.make_contiguous(), SPECTRUM_BARS)
+        } else {
+            vec![0.0; SPECTRUM_BARS]
+        };
+        self.bars.resize(SPECTRUM_BARS, 0.0);
+        for (bar, t) in self.bars.iter_mut().zip(target) {
+            *bar = if t > *bar { t } else { (*bar - SPECTRUM_FALL_PER_SEC * dt).max(t) };
+        }
+    }
+}
+
+/// 绘制频谱条（半透明底板上从底部向上生长）
+fn draw_spectrum(painter: &egui::Painter, rect: Rect, bars: &[f32]) {
+    if bars.is_empty() {
+        return;
+    }
+    painter.rect_filled(rect, 4.0, Color32::from_black_alpha(90));
+    let gap = 2.0;
+    let bar_w = (rect.width() - gap * (bars.len() + 1) as f32) / bars.len() as f32;
+    for (i, &v) in bars.iter().enumerate() {
+        let h = (rect.height() - gap * 2.0) * v;
+        let x = rect.left() + gap + i as f32 * (bar_w + gap);
+        let bar = Rect::from_min_max(Pos2::new(x, rect.bottom() - gap - h), Pos2::new(x + bar_w, rect.bottom() - gap));
+        painter.rect_filled(bar, 1.0, Color32::from_rgb(0, 180, 255).gamma_multiply(0.5 + 0.5 * v));
+    }
+}
+
+/// 解码内存中的音频数据，失败时给出面向用户的原因
+fn decode_audio(data: Vec<u8>) -> Result<rodio::Decoder<Cursor<Vec<u8>>>, String> {
+    if data.is_empty() {
+        return Err("文件为空或读取失败".into());
+    }
+    rodio::Decoder::new(Cursor::new(data)).map_err(|e| match e {
+        rodio::decoder::DecoderError::UnrecognizedFormat => "格式不支持（可用 mp3 / wav / ogg）".to_string(),
+        e => e.to_string(),
+    })
+}
+
+/// 音频管理器
+struct AudioManager {
+    _stream: rodio::OutputStream,
+    _stream_handle: rodio::OutputStreamHandle,
+    bgm_sink: rodio::Sink, // BGM专用混音器
+    se_sink: rodio::Sink,  // 音效专用混音器
+    voice_sink: rodio::Sink, // 语音专用混音器（切幕时打断）
+    blip: Option<rodio::source::Buffered<rodio::Decoder<Cursor<Vec<u8>>>>>, // 预解码的打字机音效
+    bgm_gain: f32, // BGM 当前音量倍率（闪避渐变中的值）
+    bgm_samples: Arc<std::sync::Mutex<VecDeque<f32>>>, // BGM 最近播放的样本（频谱显示用）
+}
+impl AudioManager {
+    fn new() -> Result<Self, String> {
+        // 初始化音频输出流
+        let (_stream, stream_handle) = rodio::OutputStream::try_default().map_err(|e| e.to_string())?;
+        let bgm_sink = rodio::Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
+        let se_sink = rodio::Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
+        let voice_sink = rodio::Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
+        Ok(Self { _stream, _stream_handle: stream_handle, bgm_sink, se_sink, voice_sink, blip: None, bgm_gain: 1.0, bgm_samples: Arc::default() })
+    }
+    
+    /// 解码并播放BGM（`looping` 为 false 时只播一遍，供播放列表切歌），解码失败时返回错误描述
+    /// 指定 `loop_start` 时先完整播放一遍（含 intro），之后只循环从循环起点到结尾的部分
+    fn play_bgm(&self, data: Vec<u8>, looping: bool, loop_start: Option<f32>) -> Result<(), String> {
+        let loop_part = match loop_start.filter(|_| looping) {
+            // intro 与循环段各需要一个解码器，循环段跳过 intro 后再无限重复
+            Some(start) => Some(decode_audio(data.clone())?.skip_duration(std::time::Duration::from_secs_f32(start.max(0.0)))),
+            None => None,
+        };
+        let source = decode_audio(data)?;
+        self.bgm_sink.stop(); 
+        // 每段音源都经过频谱采样器，采样器只转交样本、不改变声音
+        match (looping, loop_part) {
+            (true, Some(loop_part)) => {
+                self.bgm_sink.append(SpectrumTap::new(source, self.bgm_samples.clone()));
+                self.bgm_sink.append(SpectrumTap::new(loop_part.repeat_infinite(), self.bgm_samples.clone()));
+            }
+            (true, None) => self.bgm_sink.append(SpectrumTap::new(source.repeat_infinite(), self.bgm_samples.clone())),
+            (false, _) => self.bgm_sink.append(SpectrumTap::new(source, self.bgm_samples.clone())),
+        }
+        self.bgm_sink.play();
+        Ok(())
+    }
+
+    /// BGM 混音器是否已播放完毕（队列为空）
+    fn bgm_finished(&self) -> bool { self.bgm_sink.empty() }
+    
+    /// 解码并播放音效（单次）
+    fn play_se(&self, data: Vec<u8>) -> Result<(), String> {
+        let source = decode_audio(data)?;
+        self.se_sink.append(source); 
+        self.se_sink.play(); 
+        Ok(())
+    }
+    
+    fn stop_bgm(&self) { self.bgm_sink.stop(); }
+
+    /// 取走 BGM 自上次调用以来播放的样本（频谱分析用）
+    fn take_bgm_samples(&self) -> Vec<f32> {
+        self.bgm_samples.lock().map(|mut s| s.drain(..).collect()).unwrap_or_default()
+    }
+
+    /// 打断当前语音并播放新语音，返回语音时长
+    /// 部分格式（如 VBR 的 mp3）无法预知时长，此时时长为 None，只能靠 `voice_finished` 判断
+    fn play_voice(&self, data: Vec<u8>) -> Result<Option<f32>, String> {
+        self.voice_sink.stop();
+        let source = decode_audio(data)?;
+        let duration = source.total_duration().map(|d| d.as_secs_f32());
+        self.voice_sink.append(source);
+        self.voice_sink.play();
+        Ok(duration)
+    }
+
+    fn stop_voice(&self) { self.voice_sink.stop(); }
+
+    /// 语音是否已播放完毕
+    fn voice_finished(&self) -> bool { self.voice_sink.empty() }
+
+    /// 按语音/音效的播放状态推进 BGM 音量渐变，每帧调用
+    /// 有语音或音效在播时快速压到 `level`，两者都播完（`empty()`）后按 `release` 平滑恢复
+    fn update_duck(&mut self, dt: f32, cfg: &BgmDuck) {
+        let speaking = !self.voice_sink.empty() || !self.se_sink.empty();
+        let target = if cfg.enabled && speaking { cfg.level.clamp(0.0, 1.0) } else { 1.0 };
+        let secs = if target < self.bgm_gain { DUCK_ATTACK_SECS } else { cfg.release };
+        // 每秒变化量按「从 level 走到 1」所需时间换算，压低与恢复都是匀速渐变
+        let step = if secs > 0.0 { dt * (1.0 - cfg.level.clamp(0.0, 1.0)).max(0.01) / secs } else { 1.0 };
+        let gain = if self.bgm_gain < target {
+            (self.bgm_gain + step).min(target)
+        } else {
+            (self.bgm_gain - step).max(target)
+        };
+        if gain != self.bgm_gain {
+            self.bgm_gain = gain;
+            self.bgm_sink.set_volume(gain);
+        }
+    }
+
+    /// 设置打字机音效（预先解码缓存，之后每次播放只复制缓冲）
+    fn set_blip(&mut self, data: Vec<u8>) -> Result<(), String> {
+        self.blip = Some(decode_audio(data)?.buffered());
+        Ok(())
+    }
+
+    /// 播放一次打字机音效
+    /// 不经过 se_sink 排队，直接混入输出流，连续快速播放时各声部互相叠加而不是互相等待/打断
+    fn play_blip(&self, volume: f32) {
+        if let Some(blip) = &self.blip {
+            let _ = self._stream_handle.play_raw(blip.clone().amplify(volume).convert_samples());
+        }
+    }
+}
+
+// ============================================================================
+// Spine 2D骨骼动画对象
+// ============================================================================
+
+/// 按文件名识别的模型格式
+#[derive(Clone, Copy, PartialEq)]
+enum ModelFormat {
+    Spine,   // .atlas（同目录下配套 .skel/.json）
+    SpineZip, // .zip 压缩包（内含 atlas + skel/json + 贴图），可用 pack.zip::包内路径.atlas 指定其一
+    Live2D,  // .model3.json / .model.json / .moc3
+    Unknown,
+}
+
+impl ModelFormat {
+    fn detect(path: &str) -> Self {
+        let lower = path.to_lowercase();
+        if split_zip_path(path).is_some() {
+            ModelFormat::SpineZip
+        } else if lower.ends_with(".atlas") {
+            ModelFormat::Spine
+        } else if lower.ends_with(".model3.json") || lower.ends_with(".model.json") || lower.ends_with(".moc3") {
+            ModelFormat::Live2D
+        } else {
+            ModelFormat::Unknown
+        }
+    }
+}
+
+/// 渲染批次：按纹理切分Mesh，纹理不变时持续追加到同一个Mesh
+#[derive(Default)]
+struct MeshBatcher {
+    meshes: Vec<Mesh>,
+}
+impl MeshBatcher {
+    /// 获取使用指定纹理的当前Mesh，纹理切换时开启新批次
+    fn mesh_for(&mut self, tex_id: TextureId) -> &mut Mesh {
+        if self.meshes.last().map_or(true, |m| m.texture_id != tex_id) {
+            self.meshes.push(Mesh::with_texture(tex_id));
+        }
+        self.meshes.last_mut().unwrap()
+    }
+}
+
+/// 角色加载所处阶段（无法分块的步骤用阶段标记代替字节进度）
+#[derive(Clone, Copy, PartialEq)]
+enum LoadStage {
+    Queued,     // 排队等待加载线程
+    Extracting, // 解压压缩包
+    Reading,  // 读取文件
+    Decoding, // 解码贴图
+    Parsing,  // 解析骨骼
+}
+
+impl LoadStage {
+    fn label(self) -> &'static str {
+        match self {
+            LoadStage::Queued => "排队中",
+            LoadStage::Extracting => "解压压缩包",
+            LoadStage::Reading => "读取文件",
+            LoadStage::Decoding => "解码图片",
+            LoadStage::Parsing => "解析骨骼",
+        }
+    }
+}
+
+/// 压缩包内有多个立绘时等待用户选择的加载请求
+struct ZipChoice {
+    slot_idx: usize,              // 目标槽位
+    load_id: u64,                 // 发起本次选择的加载任务编号
+    archive: String,              // 压缩包路径
+    entries: Vec<String>,         // 包内所有 atlas 路径
+    initial_anim: Option<String>, // 原请求的初始动作
+}
+
+/// 进行中的立绘加载任务：编号用于识别已被取消/取代的任务迟到的结果，
+/// 取消标志由加载线程在读取每一块和每个阶段之间检查
+struct LoadTask {
+    id: u64,
+    cancel: Arc<AtomicBool>,
+    started: std::time::Instant,
+}
+
+/// 立绘加载的调度优先级：当前幕需要的立绘先于后续幕的预加载
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LoadPriority {
+    Preload, // 预加载后续幕（只放入缓存）
+    Current, // 当前幕或用户直接发起的加载
+}
+
+/// 排队中的加载任务
+struct LoadJob {
+    priority: LoadPriority,
+    seq: u64, // 提交顺序，同优先级先到先加载
+    run: Box<dyn FnOnce() + Send>,
+}
+
+/// 立绘加载线程池：固定数量的线程从共享队列中取优先级最高的任务，
+/// 快速切幕时新幕的加载会排到尚未开始的预加载前面；已开始的任务不会被打断
+#[derive(Clone)]
+struct LoadPool {
+    queue: Arc<(std::sync::Mutex<Vec<LoadJob>>, std::sync::Condvar)>,
+    next_seq: Arc<AtomicU64>,
+}
+
+impl LoadPool {
+    fn new(workers: usize) -> Self {
+        let queue: Arc<(std::sync::Mutex<Vec<LoadJob>>, std::sync::Condvar)> = Arc::default();
+        for _ in 0..workers.max(1) {
+            let queue = queue.clone();
+            thread::spawn(move || loop {
+                let job = {
+                    let (lock, ready) = &*queue;
+                    let mut jobs = lock.lock().unwrap_or_else(|e| e.into_inner());
+                    while jobs.is_empty() {
+                        jobs = ready.wait(jobs).unwrap_or_else(|e| e.into_inner());
+                    }
+                    let best = (0..jobs.len())
+                        .max_by_key(|&i| (jobs[i].priority, std::cmp::Reverse(jobs[i].seq)))
+                        .unwrap_or(0);
+                    jobs.swap_remove(best)
+                };
+                (job.run)();
+            });
+        }
+        Self { queue, next_seq: Arc::default() }
+    }
+
+    /// 提交任务；被取消的任务由任务自身在开始时检查取消标志后直接返回
+    fn submit(&self, priority: LoadPriority, run: impl FnOnce() + Send + 'static) {
+        let (lock, ready) = &*self.queue;
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        lock.lock().unwrap_or_else(|e| e.into_inner()).push(LoadJob { priority, seq, run: Box::new(run) });
+        ready.notify_one();
+    }
+
+    /// 等待 `delay` 后再提交任务：等待在单独的计时线程中进行，不占用加载线程
+    fn submit_after(&self, delay: std::time::Duration, priority: LoadPriority, run: impl FnOnce() + Send + 'static) {
+        let pool = self.clone();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            pool.submit(priority, run);
+        });
+    }
+}
+
+/// 一个槽位的立绘加载任务（含重试进度），完成后回传 `LoadSuccess` / `LoadFailed`
+/// 可重试的失败在等待后重新排队，等待期间加载线程可以处理其他任务
+struct CharacterLoad {
+    slot_idx: usize,
+    load_id: u64,
+    path: String,                  // 剧本中记录的路径（随结果回传）
+    file: String,                  // 实际读取的路径（相对路径已按剧本目录解析）
+    initial_anim: Option<String>,
+    alpha: TextureAlpha,
+    retry: LoadRetry,
+    priority: LoadPriority,
+    attempt: u32,                  // 已重试次数
+    started: std::time::Instant,   // 首次排队的时间（用时包含重试等待）
+    cancel: Arc<AtomicBool>,
+    tx: Sender<AppCommand>,
+    pool: LoadPool,
+}
+
+impl CharacterLoad {
+    fn submit(self) {
+        let pool = self.pool.clone();
+        pool.submit(self.priority, move || self.run());
+    }
+
+    fn run(mut self) {
+        let (tx_cb, slot_idx) = (self.tx.clone(), self.slot_idx);
+        let report = |ratio: f32, stage: LoadStage| { let _ = tx_cb.send(AppCommand::LoadProgress(slot_idx, ratio, stage)); };
+        let cancelled = || self.cancel.load(Ordering::Relaxed);
+        if cancelled() {
+            return;
+        }
+        report(0.0, LoadStage::Reading);
+        // 压缩包内有多个立绘且未指定时交给用户选择（读取失败等情况交给下面的加载流程报告）
+        if let Some((archive, None)) = split_zip_path(&self.file).filter(|_| self.attempt == 0) {
+            if let Ok(entries) = zip_atlas_entries(archive) {
+                if entries.len() > 1 {
+                    if !cancelled() {
+                        let archive = archive.to_string();
+                        let (load_id, initial_anim) = (self.load_id, self.initial_anim.clone());
+                        let _ = tx_cb.send(AppCommand::ChooseZipAtlas(ZipChoice { slot_idx, load_id, archive, entries, initial_anim }));
+                    }
+                    return;
+                }
+            }
+        }
+        let result = SpineObject::load_async_no_gpu(&self.file, self.initial_anim.as_deref(), self.alpha, &self.cancel, &report);
+        if cancelled() {
+            return; // 主线程取消时已经结束了这次加载
+        }
+        match result {
+            Ok((obj, pages, anims, skel_bytes)) => {
+                let _ = tx_cb.send(AppCommand::Log(format!("[系统] 槽位 {} 后台加载用时 {} ms", slot_idx, self.started.elapsed().as_millis())));
+                let _ = tx_cb.send(AppCommand::LoadSuccess(slot_idx, self.load_id, self.path, Box::new(obj), pages, anims, skel_bytes));
+            }
+            Err(e) if e.retryable && self.attempt < self.retry.attempts => {
+                self.attempt += 1;
+                let wait = self.retry.delay * self.attempt as f32;
+                let _ = tx_cb.send(AppCommand::Log(format!("[系统] 槽位 {} 加载失败，{:.1} 秒后重试（{}/{}）: {}", slot_idx, wait, self.attempt, self.retry.attempts, e.message)));
+                report(0.0, LoadStage::Queued);
+                let (pool, priority) = (self.pool.clone(), self.priority);
+                pool.submit_after(std::time::Duration::from_secs_f32(wait), priority, move || self.run());
+            }
+            Err(e) => {
+                let message = if self.attempt > 0 { format!("{}（已重试 {} 次）", e.message, self.attempt) } else { e.message };
+                let _ = tx_cb.send(AppCommand::LoadFailed(slot_idx, self.load_id, message));
+            }
+        }
+    }
+}
+
+/// 立绘加载错误：`retryable` 为 false 的错误（文件不存在、格式不支持、解析失败）重试也不会成功，直接报告
+/// 只有读取文件时的 IO 错误（文件不存在除外）可重试，其他字符串错误转换时一律不重试
+struct LoadError {
+    message: String,
+    retryable: bool,
+}
+
+impl LoadError {
+    fn permanent(message: impl Into<String>) -> Self {
+        Self { message: message.into(), retryable: false }
+    }
+
+    /// 文件读取错误：文件不存在时不重试，其他 IO 错误（被占用、网络盘中断等）可重试
+    fn io(context: &str, path: &std::path::Path, e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            Self::permanent(format!("文件不存在: {}", path.display()))
+        } else {
+            Self { message: format!("{} ({}): {}", context, path.display(), e), retryable: true }
+        }
+    }
+}
+
+impl From<String> for LoadError {
+    fn from(message: String) -> Self {
+        Self::permanent(message)
+    }
+}
+
+impl From<&str> for LoadError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+/// 加载失败自动重试：第 n 次重试前等待 `delay * n` 秒
+#[derive(Clone, Copy)]
+struct LoadRetry {
+    attempts: u32, // 最多重试次数（0 为不重试）
+    delay: f32,    // 首次重试前的等待（秒），之后逐次递增
+}
+
+impl Default for LoadRetry {
+    fn default() -> Self {
+        Self { attempts: 3, delay: 0.5 }
+    }
+}
+
+/// 分块读取整个文件，每读完一块用本块字节数回调一次；`cancel` 置位时中断读取
+fn read_with_progress(path: &std::path::Path, cancel: &AtomicBool, mut on_chunk: impl FnMut(u64)) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(path)?;
+    let mut data = Vec::with_capacity(file.metadata().map_or(0, |m| m.len() as usize));
+    let mut buf = vec![0u8; LOAD_CHUNK_SIZE];
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "已取消"));
+        }
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(data);
+        }
+        data.extend_from_slice(&buf[..n]);
+        on_chunk(n as u64);
+    }
+}
+
+/// 非预乘通道值 → 预乘通道值的查找表（按 [alpha][通道] 索引）
+/// 由 `Color32::from_rgba_unmultiplied` 逐项生成，结果与之逐位一致；
+/// 它对每个半透明像素都要在线性/伽马空间往返（含 powf），查表只需一次内存访问
+fn premultiply_table() -> &'static [[u8; 256]; 256] {
+    static TABLE: std::sync::OnceLock<Box<[[u8; 256]; 256]>> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = Box::new([[0u8; 256]; 256]);
+        for (a, row) in table.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = Color32::from_rgba_unmultiplied(c as u8, 0, 0, a as u8).r();
+            }
+        }
+        table
+    })
+}
+
+/// 把非预乘 RGBA 像素批量预乘为 egui 图片（查表 + 多核并行），在加载线程里调用
+fn premultiplied_image(size: [usize; 2], rgba: &[u8]) -> egui::ColorImage {
+    let table = premultiply_table();
+    let pixels = rgba.par_chunks_exact(4).map(|p| {
+        let row = &table[p[3] as usize];
+        Color32::from_rgba_premultiplied(row[p[0] as usize], row[p[1] as usize], row[p[2] as usize], p[3])
+    }).collect();
+    egui::ColorImage { size, pixels }
+}
+
+/// 区域附件（四边形）的三角形索引
+const REGION_TRIANGLES: [u16; 6] = [0, 1, 2, 2, 3, 0];
+
+/// 附件顶点的安全提取层：与 Spine C 运行时交互的 unsafe 集中在这里，
+/// 对外只给出长度已校验一致的位置、UV 与三角形索引，脏数据在这里被拒绝而不会在渲染时越界
+/// 作为跨帧复用的缓冲区由调用方持有，避免每个附件都重新分配
+#[derive(Default)]
+struct AttachmentGeometry {
+    positions: Vec<[f32; 2]>, // 世界坐标（骨骼坐标系，Y 轴向上）
+    uvs: Vec<[f32; 2]>,       // 贴图坐标，与 positions 一一对应
+    indices: Vec<u16>,        // 三角形索引，3 个一组，均小于顶点数
+    scratch: Vec<f32>,        // compute_world_vertices 输出的平铺坐标
+}
+
+impl AttachmentGeometry {
+    fn positions(&self) -> &[[f32; 2]] { &self.positions }
+    fn uvs(&self) -> &[[f32; 2]] { &self.uvs }
+    fn indices(&self) -> &[u16] { &self.indices }
+
+    /// 把当前提取的顶点并入包围盒
+    fn extend_bounds(&self, bounds: &mut Rect) {
+        for p in &self.positions {
+            bounds.extend_with(Pos2::new(p[0], p[1]));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.positions.clear();
+        self.uvs.clear();
+        self.indices.clear();
+    }
+
+    /// 提取区域附件（普通图片，固定 4 个顶点）
+    fn load_region(&mut self, slot: &Slot, region: &rusty_spine::RegionAttachment) {
+        self.clear();
+        self.scratch.clear();
+        self.scratch.resize(8, 0.0);
+        // 必要性证明：compute_world_vertices 是 Spine C 运行时的 FFI 调用，没有安全替代
+        // 安全边界：输出缓冲区恰为区域附件的 4 个顶点（8 个浮点），写入不会越界
+        unsafe { region.compute_world_vertices(&slot.bone(), &mut self.scratch, 0, 2); }
+        self.positions.extend(self.scratch.chunks_exact(2).map(|v| [v[0], v[1]]));
+        self.uvs.extend(region.uvs().chunks_exact(2).map(|v| [v[0], v[1]]));
+        self.indices.extend_from_slice(&REGION_TRIANGLES);
+    }
+
+    /// 提取网格附件（可变形网格）；顶点数为奇数、指针为空或索引越界等脏数据时返回 false 且不留下任何顶点
+    /// 传入插槽而非骨骼：C 运行时会优先使用插槽上本帧动画写入的变形（FFD）顶点，没有变形时才回退到绑定姿势顶点
+    fn load_mesh(&mut self, slot: &Slot, mesh: &rusty_spine::MeshAttachment) -> bool {
+        self.clear();
+        let len = mesh.world_vertices_length().max(0) as usize;
+        if len == 0 || len % 2 != 0 {
+            return false;
+        }
+        self.scratch.clear();
+        self.scratch.resize(len, 0.0);
+        // 必要性证明：世界坐标只能由 C 运行时计算，UV 与三角形数组也只能经它给出的裸指针读取
+        // 安全边界：输出缓冲区已扩容为 world_vertices_length；按 Spine 运行时约定 uvs 长度与世界坐标相同、
+        // triangles 长度为 triangles_count，读取前排除了空指针与非正长度，切片只在本函数内使用
+        let (uvs, tris) = unsafe {
+            mesh.compute_world_vertices(slot, 0, len as i32, &mut self.scratch, 0, 2);
+            let (uv_ptr, tri_ptr, tri_count) = (mesh.uvs(), mesh.triangles(), mesh.triangles_count());
+            if uv_ptr.is_null() || tri_ptr.is_null() || tri_count <= 0 {
+                return false;
+            }
+            (std::slice::from_raw_parts(uv_ptr, len), std::slice::from_raw_parts(tri_ptr, tri_count as usize))
+        };
+        // 索引必须成组且都指向存在的顶点，否则整份网格丢弃
+        let vertex_count = len / 2;
+        if tris.len() % 3 != 0 || tris.iter().any(|&i| i as usize >= vertex_count) {
+            return false;
+        }
+        self.positions.extend(self.scratch.chunks_exact(2).map(|v| [v[0], v[1]]));
+        self.uvs.extend(uvs.chunks_exact(2).map(|v| [v[0], v[1]]));
+        self.indices.extend_from_slice(tris);
+        true
+    }
+}
+
+/// 缓存中的一份已解析立绘资源
+struct CachedModel {
+    skeleton_data: Arc<rusty_spine::SkeletonData>, // 共享骨架数据
+    pages: Vec<(String, Arc<egui::ColorImage>)>,   // 各页原尺寸贴图（按 atlas 页顺序；槽位中的角色只持有弱引用）
+    bytes: usize,                                  // 估算内存占用（字节）
+    last_used: std::time::Instant,                 // 最后一次加载或复用的时间
+}
+
+impl CachedModel {
+    /// 是否仍有槽位中的角色使用这份资源（在用的资源不会被卸载）
+    fn in_use(&self) -> bool {
+        Arc::strong_count(&self.skeleton_data) > 1
+    }
+}
+
+/// 立绘资源缓存：按 atlas 路径保存解析好的骨架数据与贴图，同一立绘再次加载时跳过读取与解析
+/// 总估算内存超过上限时，按最久未使用的顺序卸载没有槽位在用的资源
+/// （在用的资源仍被角色的 `Arc` 引用，卸载记录也释放不了内存，因此保留到槽位不再使用为止）
+#[derive(Default)]
+struct ResourceCache {
+    entries: HashMap<String, CachedModel>,
+}
+
+impl ResourceCache {
+    /// 取出缓存的资源并刷新使用时间
+    fn get(&mut self, path: &str) -> Option<(Arc<rusty_spine::SkeletonData>, Vec<(String, Arc<egui::ColorImage>)>)> {
+        let entry = self.entries.get_mut(path)?;
+        entry.last_used = std::time::Instant::now();
+        Some((entry.skeleton_data.clone(), entry.pages.clone()))
+    }
+
+    /// 记录一份新解析的资源（同路径的旧记录被替换，如热重载后）
+    /// 估算大小为各页贴图像素数据加加载时实际读入的骨骼文件字节数
+    fn insert(&mut self, path: String, skeleton_data: Arc<rusty_spine::SkeletonData>, pages: Vec<(String, Arc<egui::ColorImage>)>, skel_bytes: usize) {
+        let bytes = pages.iter().map(|(_, img)| img.pixels.len() * 4).sum::<usize>() + skel_bytes;
+        self.entries.insert(path, CachedModel { skeleton_data, pages, bytes, last_used: std::time::Instant::now() });
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.entries.values().map(|e| e.bytes).sum()
+    }
+
+    /// 超过上限时按最后使用时间卸载没有槽位在用的资源，返回被卸载的路径
+    fn evict(&mut self, limit_bytes: usize) -> Vec<String> {
+        let mut candidates: Vec<(std::time::Instant, String)> = self.entries.iter()
+            .filter(|(_, e)| !e.in_use())
+            .map(|(path, e)| (e.last_used, path.clone()))
+            .collect();
+        candidates.sort();
+        let mut total = self.total_bytes();
+        let mut evicted = Vec::new();
+        for (_, path) in candidates {
+            if total <= limit_bytes {
+                break;
+            }
+            if let Some(entry) = self.entries.remove(&path) {
+                total -= entry.bytes;
+                evicted.push(path);
+            }
+        }
+        evicted
+    }
+}
+
+/// 手动覆盖的骨骼本地旋转（在动画应用之后、更新世界变换之前写入）
+struct BoneOverride {
+    bone: String,     // 骨骼名（与骨架数据中的大小写一致）
+    degrees: f32,     // 旋转角度（度，逆时针为正）
+    additive: bool,   // true 时叠加在动画结果上，否则直接替换
+}
+
+/// 渲染时遇到的未处理附件（调试构建下收集，每种插槽+类型只报告一次）
+#[derive(Default)]
+struct AttachmentDiagnostics {
+    seen: HashSet<String>, // 已报告过的 "插槽/附件/类型"
+    pending: Vec<String>,  // 尚未输出到日志的诊断信息
+}
+
+/// 单个角色的不透明度渐变
+struct CharacterFade {
+    from: f32,
+    to: f32,
+    duration: f32,
+    elapsed: f32,
+}
+
+/// 穿插绘制的两个角色槽位：两者的插槽按统一深度交错绘制
+#[derive(Clone, Copy, PartialEq)]
+struct InterleavePair {
+    first: usize,
+    second: usize,
+    bias: f32, // 较后绘制的角色整体前移的深度（-1~1，正值更靠前）
+}
+
+/// 单个角色的入场滑入：等待 `delay` 秒（期间不绘制）后从画面外侧滑到原位并淡入
+struct CharacterEnter {
+    delay: f32,
+    elapsed: f32,
+}
+
+impl CharacterEnter {
+    /// 入场进度（0~1，已做缓出），等待期间为 0
+    fn progress(&self) -> f32 {
+        let p = ((self.elapsed - self.delay) / ENTER_SLIDE_SECS).clamp(0.0, 1.0);
+        1.0 - (1.0 - p).powi(3)
+    }
+}
+
+pub struct SpineObject {
+    skeleton: Skeleton,               // Spine骨架实例
+    state: AnimationState,            // 动画状态机
+    _textures: Vec<TextureHandle>,    // 纹理句柄（所有权管理）
+    page_images: Vec<Arc<egui::ColorImage>>, // 各页纹理的低分辨率 CPU 副本（离屏光栅化用，与 texture_ids 一一对应；离屏副本中为原尺寸）
+    source_pages: Vec<std::sync::Weak<egui::ColorImage>>, // 各页原尺寸贴图（由资源缓存持有，被卸载后为空），用于共用纹理与重新上传
+    color_filter: ColorFilter,        // 已上传贴图套用的颜色滤镜，顶点颜色按同一滤镜转换
+    texture_ids: Vec<(String, TextureId)>, // 按 atlas 页顺序排列的（页名, 纹理ID）
+    pub position: Pos2,               // 屏幕位置
+    pub scale: f32,                   // 缩放比例
+    foot_offset: f32,                 // setup pose 下模型底部相对原点的Y偏移（骨骼坐标）
+    base_scale: f32,                  // 归一化缩放（720p 基准）：按模型包围盒推算，让不同导出比例的模型默认显示高度一致
+    setup_bounds: Option<Rect>,       // setup pose 下的包围盒（骨骼坐标），单人预览时用于居中与适配视口
+    pub layout_x: f32,                // 横向位置（屏幕宽度百分比）
+    pub flip_x: bool,                 // 水平翻转（改变朝向）
+    pub user_scale: f32,              // 用户手动缩放倍率（叠加在自动缩放上）
+    pub offset: Vec2,                 // 用户手动位置偏移（720p 基准，随屏幕缩放）
+    bounce_elapsed: Option<f32>,      // 说话弹跳已进行时间（None 表示未在弹跳）
+    pub opacity: f32,                 // 整体不透明度（0~1），乘进所有顶点颜色
+    pub depth: f32,                   // 离镜头远近（0 最近 ~ 1 最远），景深排列时决定缩放、明暗与绘制顺序
+    fade: Option<CharacterFade>,      // 进行中的淡入淡出
+    enter: Option<CharacterEnter>,    // 进行中的入场滑入
+    blink_anim: Option<String>,       // 自动眨眼动画名（None 表示不眨眼）
+    blink_timer: f32,                 // 距下次眨眼的倒计时（秒）
+    bone_overrides: Vec<BoneOverride>, // 手动控制的骨骼旋转
+    attachment_overrides: Vec<(String, Option<String>)>, // 手动设置的插槽附件（插槽名, 附件名；None 为隐藏）
+    pose_dirty: bool,                 // 动画、皮肤或骨骼覆盖被改动，下次更新必须重算姿势
+    settled: bool,                    // 所有轨道都已播完且不在混合：姿势不会再变，可沿用缓存的世界变换
+    diagnostics: RefCell<AttachmentDiagnostics>, // 未渲染附件的诊断信息（paint 只持有 &self）
+    bounds_cache: std::cell::Cell<Option<Rect>>, // 最近一次绘制时的包围盒（骨骼坐标系），用于快速判断是否在屏幕外
+    bounds_age: std::cell::Cell<f32>, // 包围盒缓存已经过的时间（秒），姿势被改动时置为无穷大
+    skeleton_data: Arc<rusty_spine::SkeletonData>, // 共享骨架数据
+}
+unsafe impl Send for SpineObject {} // 标记为可跨线程安全发送
+
+impl SpineObject {
+    /// 异步加载Spine资源（不涉及GPU操作）
+    /// `initial_anim` 指定加载后直接播放的动画，未指定或找不到时播放第一个动画
+    /// `alpha` 指明贴图是否已预乘，保证上传到 GPU 的纹理统一为预乘格式
+    /// `progress` 在加载过程中报告总体进度（0~1）与所处阶段
+    /// `cancel` 置位后在下一次读取或阶段切换时提前返回错误
+    /// 只有读取文件时的 IO 错误（被占用、读取中断等）标记为可重试，文件不存在、格式不支持与解析失败都不重试
+    /// 返回值最后一项为实际读入的骨骼文件字节数（供资源缓存估算内存）
+    fn load_async_no_gpu(path_str: &str, initial_anim: Option<&str>, alpha: TextureAlpha, cancel: &AtomicBool, progress: &dyn Fn(f32, LoadStage)) -> Result<(Self, Vec<(String, egui::ColorImage)>, Vec<String>, usize), LoadError> {
+        let check_cancel = || if cancel.load(Ordering::Relaxed) { Err("加载已取消".to_string()) } else { Ok(()) };
+        // 0. 先按扩展名识别格式，避免把其他格式交给 atlas 解析器得到含糊的错误
+        match ModelFormat::detect(path_str) {
+            ModelFormat::Spine => {}
+            ModelFormat::SpineZip => return Self::load_from_zip(path_str, initial_anim, alpha, cancel, progress),
+            ModelFormat::Live2D => return Err(LoadError::permanent("检测到 Live2D 模型，暂不支持，请使用 Spine 导出（.atlas + .skel/.json）")),
+            ModelFormat::Unknown => return Err(LoadError::permanent(format!("不支持的模型格式: {}（请选择 Spine 的 .atlas 文件或 .zip 压缩包）", path_str))),
+        }
+
+        // 1. 加载.atlas文件，确定需要读取的贴图与骨骼文件
+        let atlas_path = std::path::Path::new(path_str);
+        let atlas_dir = atlas_path.parent().ok_or("Invalid path")?;
+        let atlas_data = std::fs::read(atlas_path).map_err(|e| LoadError::io("Atlas Error", atlas_path, e))?;
+        let atlas = Arc::new(Atlas::new(&atlas_data, atlas_dir).map_err(|e| format!("Atlas Error: {}", e))?);
+        let page_names: Vec<String> = atlas.pages().map(|p| p.name().to_string()).collect();
+        if page_names.is_empty() {
+            return Err("Atlas has no pages".into());
+        }
+        // 优先使用二进制骨骼数据，其次JSON
+        let skel_path = [atlas_path.with_extension("skel"), atlas_path.with_extension("json")]
+            .into_iter()
+            .find(|p| p.exists())
+            .ok_or_else(|| LoadError::permanent("Missing .skel or .json"))?;
+
+        // 2. 按字节分块读取所有文件（读取占总进度的前 70%）
+        let files: Vec<std::path::PathBuf> = page_names.iter().map(|n| atlas_dir.join(n)).chain([skel_path.clone()]).collect();
+        let total: u64 = files.iter().map(|f| std::fs::metadata(f).map_or(0, |m| m.len())).sum::<u64>().max(1);
+        let mut read = 0u64;
+        let mut contents = Vec::with_capacity(files.len());
+        for file in &files {
+            let data = read_with_progress(file, cancel, |n| {
+                read += n;
+                progress(LOAD_READ_SHARE * read as f32 / total as f32, LoadStage::Reading);
+            }).map_err(|e| LoadError::io("Read Error", file, e))?;
+            contents.push(data);
+        }
+        let skel_data = contents.pop().unwrap_or_default();
+        let skel_bytes = skel_data.len();
+
+        // 3. 解码每一页的纹理图片（复杂立绘可能拆成多张贴图）
+        let mut pages = Vec::new();
+        let page_count = page_names.len();
+        for (i, (page_name, data)) in page_names.into_iter().zip(contents).enumerate() {
+            check_cancel()?;
+            progress(LOAD_READ_SHARE + LOAD_DECODE_SHARE * i as f32 / page_count as f32, LoadStage::Decoding);
+            let img = image::load_from_memory(&data).map_err(|e| format!("Image Load Error ({}): {}", page_name, e))?;
+            let size = [img.width() as _, img.height() as _];
+            let rgba = img.to_rgba8();
+            // egui 按预乘 Alpha 混合：普通贴图需要预乘，PMA 贴图再预乘一次会让半透明边缘发黑
+            let color_image = match alpha {
+                TextureAlpha::Straight => premultiplied_image(size, rgba.as_raw()),
+                TextureAlpha::Premultiplied => egui::ColorImage::from_rgba_premultiplied(size, rgba.as_raw()),
+            };
+            pages.push((page_name, color_image));
+        }
+
+        // 4. 解析骨骼数据
+        check_cancel()?;
+        progress(LOAD_READ_SHARE + LOAD_DECODE_SHARE, LoadStage::Parsing);
+        let skeleton_data = if skel_path.extension().map_or(false, |e| e == "skel") {
+            let skeleton_bin = SkeletonBinary::new(atlas.clone());
+            Arc::new(skeleton_bin.read_skeleton_data(&skel_data).map_err(|e| format!("Binary load failed: {}", e))?)
+        } else {
+            let skeleton_json = SkeletonJson::new(atlas.clone());
+            Arc::new(skeleton_json.read_skeleton_data(&skel_data).map_err(|e| format!("JSON load failed: {}", e))?)
+        };
+
+        // 5. 创建动画状态机和骨架实例
+        let (obj, anim_names) = Self::from_skeleton_data(skeleton_data, initial_anim);
+        Ok((obj, pages, anim_names, skel_bytes))
+    }
+
+    /// 从 zip 压缩包加载：把 atlas 所在目录解压到临时目录后走普通加载流程，结束后删除临时文件
+    /// 路径未指定包内 atlas 时，包内必须恰好只有一个 atlas
+    fn load_from_zip(path_str: &str, initial_anim: Option<&str>, alpha: TextureAlpha, cancel: &AtomicBool, progress: &dyn Fn(f32, LoadStage)) -> Result<(Self, Vec<(String, egui::ColorImage)>, Vec<String>, usize), LoadError> {
+        let (archive, entry) = split_zip_path(path_str).ok_or("Invalid zip path")?;
+        if !std::path::Path::new(archive).exists() {
+            return Err(LoadError::permanent(format!("文件不存在: {}", archive)));
+        }
+        let atlas_entry = match entry {
+            Some(e) => e.to_string(),
+            None => {
+                let mut entries = zip_atlas_entries(archive)?;
+                match entries.len() {
+                    0 => return Err(LoadError::permanent(format!("压缩包内没有 .atlas 文件: {}", archive))),
+                    1 => entries.remove(0),
+                    _ => return Err(LoadError::permanent(format!("压缩包内有多个立绘，请用 {}{}包内路径 指定其一: {}", archive, ZIP_ENTRY_SEPARATOR, entries.join(", ")))),
+                }
+            }
+        };
+        progress(0.0, LoadStage::Extracting);
+        let (dir, atlas_path) = extract_zip_model(archive, &atlas_entry)?;
+        let result = Self::load_async_no_gpu(&atlas_path.to_string_lossy(), initial_anim, alpha, cancel, progress);
+        let _ = std::fs::remove_dir_all(&dir); // 资源已全部读入内存，临时文件不再需要
+        // 错误信息中的临时目录换回压缩包路径，用户才能找到出错的文件
+        let temp_prefix = format!("{}{}", dir.display(), std::path::MAIN_SEPARATOR);
+        result.map_err(|e| LoadError { message: e.message.replace(&temp_prefix, &format!("{}{}", archive, ZIP_ENTRY_SEPARATOR)), ..e })
+    }
+
+    /// 用已解析的骨架数据创建角色实例（不含纹理），返回实例与动画名列表
+    /// 同一份骨架数据可被多个实例共享（资源缓存复用时不再重新解析）
+    fn from_skeleton_data(skeleton_data: Arc<rusty_spine::SkeletonData>, initial_anim: Option<&str>) -> (Self, Vec<String>) {
+        let state_data = Arc::new(AnimationStateData::new(skeleton_data.clone()));
+        let mut state = AnimationState::new(state_data);
+        let anim_names: Vec<String> = skeleton_data.animations().map(|a| a.name().to_string()).collect();
+        
+        // 优先播放指定的初始动画，否则默认播放第一个动画（避免加载后闪现错误姿势）
+        let anim = initial_anim
+            .and_then(|name| skeleton_data.animations().find(|a| a.name() == name))
+            .or_else(|| skeleton_data.animations().next());
+        if let Some(anim) = anim { 
+            let _ = state.set_animation(0, &anim, true); 
+        }
+
+        let mut obj = Self { 
+            skeleton: Skeleton::new(skeleton_data.clone()), 
+            state, 
+            _textures: Vec::new(), 
+            page_images: Vec::new(),
+            source_pages: Vec::new(),
+            color_filter: ColorFilter::None,
+            texture_ids: Vec::new(), 
+            position: Pos2::ZERO, 
+            scale: FALLBACK_CHARACTER_SCALE, 
+            foot_offset: 0.0,
+            base_scale: FALLBACK_CHARACTER_SCALE,
+            setup_bounds: None,
+            layout_x: 0.5,
+            flip_x: false,
+            user_scale: 1.0,
+            offset: Vec2::ZERO,
+            bounce_elapsed: None,
+            opacity: 1.0,
+            depth: 0.0,
+            fade: None,
+            enter: None,
+            blink_anim: None,
+            blink_timer: 0.0,
+            bone_overrides: Vec::new(),
+            attachment_overrides: Vec::new(),
+            pose_dirty: true,
+            settled: false,
+            diagnostics: RefCell::default(),
+            bounds_cache: std::cell::Cell::new(None),
+            bounds_age: std::cell::Cell::new(f32::INFINITY),
+            skeleton_data 
+        };
+        
+        // 用 setup pose 的包围盒得到脚底位置（供排列时对齐基线）与归一化缩放
+        obj.skeleton.update_world_transform();
+        obj.setup_bounds = obj.skeleton_bounds();
+        if let Some(bounds) = obj.setup_bounds {
+            obj.foot_offset = bounds.min.y;
+            if bounds.height() > 1.0 {
+                obj.base_scale = BASE_HEIGHT * CHARACTER_HEIGHT_RATIO / bounds.height();
+            }
+        }
+
+        (obj, anim_names)
+    }
+
+    /// 推进说话弹跳，返回当前的向上偏移量（像素，720p 基准）
+    /// 偏移曲线为快速衰减的正弦，只作用于渲染位置，不影响骨骼与排列
+    fn update_bounce(&mut self, dt: f32, cfg: &TalkBounce) -> f32 {
+        let t = match &mut self.bounce_elapsed {
+            Some(t) => { *t += dt; *t }
+            None => return 0.0,
+        };
+        if t >= cfg.duration {
+            self.bounce_elapsed = None;
+            return 0.0;
+        }
+        let p = t / cfg.duration;
+        cfg.amplitude * (1.0 - p).powi(2) * (p * std::f32::consts::TAU).sin().abs()
+    }
+
+    /// 开始把不透明度渐变到目标值（时长为 0 时立即生效）
+    fn start_fade(&mut self, target: f32, duration: f32) {
+        let target = target.clamp(0.0, 1.0);
+        if duration <= 0.0 {
+            self.opacity = target;
+            self.fade = None;
+        } else {
+            self.fade = Some(CharacterFade { from: self.opacity, to: target, duration, elapsed: 0.0 });
+        }
+    }
+
+    /// 推进不透明度渐变
+    fn update_fade(&mut self, dt: f32) {
+        if let Some(fade) = &mut self.fade {
+            fade.elapsed += dt;
+            let t = (fade.elapsed / fade.duration).min(1.0);
+            self.opacity = fade.from + (fade.to - fade.from) * t;
+            if t >= 1.0 {
+                self.fade = None;
+            }
+        }
+    }
+
+    /// 开始入场滑入（`delay` 秒后开始）
+    fn start_enter(&mut self, delay: f32) {
+        self.enter = Some(CharacterEnter { delay: delay.max(0.0), elapsed: 0.0 });
+    }
+
+    /// 推进入场滑入，返回当前的横向偏移（屏幕宽度的比例）
+    /// 站在左半边的角色从左侧滑入，右半边的从右侧滑入
+    fn update_enter(&mut self, dt: f32) -> f32 {
+        let p = match &mut self.enter {
+            Some(enter) => { enter.elapsed += dt; enter.progress() }
+            None => return 0.0,
+        };
+        if p >= 1.0 {
+            self.enter = None;
+            return 0.0;
+        }
+        let side = if self.layout_x < 0.5 { -1.0 } else { 1.0 };
+        side * ENTER_SLIDE_DISTANCE * (1.0 - p)
+    }
+
+    /// 实际绘制用的不透明度：手动不透明度乘以入场淡入
+    fn visible_opacity(&self) -> f32 {
+        self.opacity * self.enter.as_ref().map_or(1.0, |e| e.progress())
+    }
+
+    /// 当前姿势在屏幕上的包围盒（用于拾取）
+    fn screen_bounds(&self) -> Option<Rect> {
+        self.skeleton_bounds().map(|b| self.skeleton_rect_to_screen(b))
+    }
+
+    /// 把骨骼坐标（Y 轴向上）按当前位置、缩放与朝向换算到屏幕坐标（Y 轴向下）
+    fn skeleton_to_screen(&self, p: Pos2) -> Pos2 {
+        let flip = if self.flip_x { -1.0 } else { 1.0 };
+        Pos2::new(p.x * self.scale * flip + self.position.x, -p.y * self.scale + self.position.y)
+    }
+
+    /// 把骨骼坐标系中的矩形按当前位置、缩放与朝向换算到屏幕坐标
+    fn skeleton_rect_to_screen(&self, b: Rect) -> Rect {
+        Rect::from_two_pos(self.skeleton_to_screen(b.min), self.skeleton_to_screen(b.max))
+    }
+
+    /// 指定骨骼（名字不区分大小写）当前的屏幕坐标，骨骼不存在时返回 None
+    /// 使用最近一次更新的世界变换，可用于在角色头顶绘制名字标签、对话气泡等
+    fn bone_screen_pos(&self, name: &str) -> Option<Pos2> {
+        let bone = match self.skeleton.find_bone(name) {
+            Some(b) => b,
+            None => self.skeleton.find_bone(&self.find_bone_name(name)?)?,
+        };
+        Some(self.skeleton_to_screen(Pos2::new(bone.world_x(), bone.world_y())))
+    }
+
+    /// 是否完全在 `screen` 之外：用缓存的包围盒判断（移动、缩放只影响换算到屏幕的位置，缓存仍然有效），
+    /// 判定在外且姿势被改动过或缓存已超过 `BOUNDS_REFRESH_SECS` 时，才按当前姿势重新计算，
+    /// 被剔除的角色不必每帧遍历全部附件；动画让肢体伸回画面时最多晚这么久出现
+    fn is_offscreen(&self, screen: Rect) -> bool {
+        let visible = |b: Option<Rect>| b.map_or(false, |b| self.skeleton_rect_to_screen(b).intersects(screen));
+        if visible(self.bounds_cache.get()) {
+            return false;
+        }
+        if self.bounds_age.get() < BOUNDS_REFRESH_SECS {
+            return true;
+        }
+        let exact = self.skeleton_bounds();
+        self.store_bounds(exact);
+        !visible(exact)
+    }
+
+    /// 记录当前姿势的包围盒，重新开始计时
+    fn store_bounds(&self, bounds: Option<Rect>) {
+        self.bounds_cache.set(bounds.filter(|b| b.is_positive()));
+        self.bounds_age.set(0.0);
+    }
+
+    /// 计算当前姿势下所有可见附件的包围盒（骨骼坐标系，Y轴向上）
+    fn skeleton_bounds(&self) -> Option<Rect> {
+        let mut geometry = AttachmentGeometry::default();
+        let mut bounds = Rect::NOTHING;
+        
+        for slot in self.skeleton.draw_order() {
+            let attachment = match slot.attachment() { 
+                Some(a) => a, 
+                None => continue 
+            };
+            
+            if let Some(region) = attachment.as_region() {
+                geometry.load_region(&slot, &region);
+            } else if let Some(mesh_att) = attachment.as_mesh() {
+                if !geometry.load_mesh(&slot, &mesh_att) {
+                    continue;
+                }
+            } else {
+                continue;
+            }
+            
+            geometry.extend_bounds(&mut bounds);
+        }
+        
+        if bounds.is_positive() { Some(bounds) } else { None }
+    }
+
+    /// 获取所有动画名称列表
+    fn get_anim_names(&self) -> Vec<String> { 
+        self.skeleton_data.animations().map(|a| a.name().to_string()).collect() 
+    }
+    
+    /// 按名称设置动画
+    fn set_animation_by_name(&mut self, anim_name: &str, loop_anim: bool) -> bool {
+        if let Some(anim) = self.skeleton_data.animations().find(|a| a.name() == anim_name) {
+            let _ = self.state.set_animation(0, &anim, loop_anim); 
+            self.pose_dirty = true;
+            true
+        } else { 
+            false 
+        }
+    }
+    
+    /// 设置自动眨眼：按名称（不区分大小写）查找眨眼动画，找不到或传入 None 时禁用
+    fn setup_blink(&mut self, anim_name: Option<&str>) {
+        self.blink_anim = anim_name.and_then(|name| {
+            self.skeleton_data.animations()
+                .find(|a| a.name().eq_ignore_ascii_case(name))
+                .map(|a| a.name().to_string())
+        });
+        self.blink_timer = next_blink_delay();
+        if self.blink_anim.is_none() {
+            self.state.clear_track(BLINK_TRACK);
+            self.pose_dirty = true;
+        }
+    }
+
+    /// 在表情轨道上播放指定动画（叠加在身体动作之上），找不到时返回 false
+    fn set_expression(&mut self, anim_name: &str) -> bool {
+        match self.skeleton_data.animations().find(|a| a.name() == anim_name) {
+            Some(anim) => { 
+                let _ = self.state.set_animation(EXPRESSION_TRACK, &anim, true); 
+                self.pose_dirty = true;
+                true 
+            }
+            None => false,
+        }
+    }
+
+    /// 按名字查找骨骼（不区分大小写），返回骨架数据中的原名
+    fn find_bone_name(&self, name: &str) -> Option<String> {
+        self.skeleton.bones()
+            .map(|b| b.data().name().to_string())
+            .find(|n| n.eq_ignore_ascii_case(name))
+    }
+
+    /// 覆盖骨骼的本地旋转（度），每帧动画应用后生效；找不到骨骼时返回 false
+    fn set_bone_rotation(&mut self, name: &str, deg: f32) -> bool {
+        self.put_bone_override(name, deg, false)
+    }
+
+    /// 在动画结果之上叠加骨骼旋转（度），保留动画本身的摆动
+    fn offset_bone_rotation(&mut self, name: &str, deg: f32) -> bool {
+        self.put_bone_override(name, deg, true)
+    }
+
+    fn put_bone_override(&mut self, name: &str, degrees: f32, additive: bool) -> bool {
+        let bone = match self.find_bone_name(name) {
+            Some(b) => b,
+            None => return false,
+        };
+        self.bone_overrides.retain(|o| o.bone != bone);
+        self.bone_overrides.push(BoneOverride { bone, degrees, additive });
+        self.pose_dirty = true;
+        true
+    }
+
+    /// 取消对某根骨骼的手动控制，恢复由动画驱动
+    fn clear_bone_override(&mut self, name: &str) {
+        self.bone_overrides.retain(|o| !o.bone.eq_ignore_ascii_case(name));
+        self.pose_dirty = true;
+    }
+
+    /// 计算让指定骨骼朝向屏幕上某点需要的偏转角度（度，限制在 ±LOOK_AT_MAX_DEG 内）
+    /// 以水平朝向为基准：目标在骨骼上方时抬头，下方时低头
+    fn look_at_angle(&self, name: &str, target: Pos2) -> Option<f32> {
+        let bone = self.skeleton.find_bone(name)?;
+        let flip = if self.flip_x { -1.0 } else { 1.0 };
+        // 屏幕坐标 → 骨骼坐标（Y 轴向上）
+        let tx = (target.x - self.position.x) / (self.scale * flip);
+        let ty = -(target.y - self.position.y) / self.scale;
+        let (dx, dy) = (tx - bone.world_x(), ty - bone.world_y());
+        Some(dy.atan2(dx.abs()).to_degrees().clamp(-LOOK_AT_MAX_DEG, LOOK_AT_MAX_DEG))
+    }
+
+    /// 获取当前播放的动画名称
+    fn current_anim_name(&self) -> Option<String> {
+        self.state.track_at_index(0).map(|entry| entry.animation().name().to_string())
+    }
+
+    /// 获取当前动画的播放时间与总时长（循环动画的时间按周期取余）
+    fn track_time(&self) -> Option<(f32, f32)> {
+        let entry = self.state.track_at_index(0)?;
+        let duration = entry.animation().duration();
+        let time = if entry.looping() && duration > 0.0 { entry.track_time() % duration } else { entry.track_time().min(duration) };
+        Some((time, duration))
+    }
+
+    /// 把当前动画定位到指定时间，并立即应用一次姿势（不推进时间）
+    fn seek(&mut self, time: f32) {
+        if let Some(mut entry) = self.state.track_at_index_mut(0) {
+            entry.set_track_time(time.max(0.0));
+        }
+        self.pose_dirty = true;
+        self.update_parallel(0.0);
+    }
+
+    /// 获取所有皮肤名称列表
+    fn get_skin_names(&self) -> Vec<String> {
+        self.skeleton_data.skins().map(|s| s.name().to_string()).collect()
+    }
+
+    /// 获取当前皮肤名称
+    fn current_skin(&self) -> Option<String> {
+        self.skeleton.skin().map(|s| s.name().to_string())
+    }
+
+    /// 列出每个插槽可用的附件名（来自默认皮肤与当前皮肤），按插槽顺序，只含有附件的插槽
+    fn slot_attachment_options(&self) -> Vec<(String, Vec<String>)> {
+        let current = self.current_skin();
+        let mut options: Vec<(String, Vec<String>)> = self.skeleton_data.slots().map(|s| (s.name().to_string(), Vec::new())).collect();
+        for skin in self.skeleton_data.skins().filter(|s| s.name() == "default" || Some(s.name()) == current.as_deref()) {
+            for entry in skin.attachments() {
+                if let Some((_, names)) = options.get_mut(entry.slot_index as usize) {
+                    let name = entry.attachment.name().to_string();
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+        options.retain(|(_, names)| !names.is_empty());
+        options
+    }
+
+    /// 插槽当前显示的附件名（隐藏时为 None）
+    fn slot_attachment(&self, slot_name: &str) -> Option<String> {
+        self.skeleton.slots()
+            .find(|s| s.data().name() == slot_name)
+            .and_then(|s| s.attachment().map(|a| a.name().to_string()))
+    }
+
+    /// 设置插槽显示的附件（None 为隐藏），之后每帧在动画应用后保持；插槽或附件不存在时返回 false
+    fn set_slot_attachment(&mut self, slot_name: &str, attachment: Option<&str>) -> bool {
+        if !self.skeleton.set_attachment(slot_name, attachment) {
+            return false;
+        }
+        self.attachment_overrides.retain(|(slot, _)| slot != slot_name);
+        self.attachment_overrides.push((slot_name.to_string(), attachment.map(str::to_string)));
+        self.pose_dirty = true;
+        true
+    }
+
+    /// 用一幕记录的附件覆盖替换当前全部覆盖（不存在的插槽/附件直接跳过）
+    fn set_attachment_overrides(&mut self, overrides: &[(String, Option<String>)]) {
+        self.attachment_overrides.clear();
+        self.skeleton.set_slots_to_setup_pose();
+        for (slot, attachment) in overrides {
+            self.set_slot_attachment(slot, attachment.as_deref());
+        }
+        self.pose_dirty = true;
+    }
+
+    /// 按名称切换皮肤
+    fn set_skin(&mut self, name: &str) -> bool {
+        if self.skeleton.set_skin_by_name(name).is_ok() {
+            self.skeleton.update_cache(); // 皮肤可能带有额外的骨骼与约束，切换后重建更新顺序
+            self.skeleton.set_slots_to_setup_pose(); // 让插槽附件刷新为新皮肤
+            self.pose_dirty = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 并行更新动画状态（由调度器调用）
+    fn update_parallel(&mut self, dt: f32) {
+        let dt = dt.min(MAX_DT); // 限制最大时间步长
+        // 自动眨眼：倒计时到点后在眨眼轨道上播放一次，再随机等待下一次
+        if let Some(blink) = &self.blink_anim {
+            self.blink_timer -= dt;
+            if self.blink_timer <= 0.0 {
+                if let Some(anim) = self.skeleton_data.animations().find(|a| a.name() == blink.as_str()) {
+                    let _ = self.state.set_animation(BLINK_TRACK, &anim, false);
+                    self.pose_dirty = true;
+                }
+                self.blink_timer = next_blink_delay();
+            }
+        }
+        // 动画、皮肤、附件或骨骼覆盖被改动时，屏幕外剔除用的包围盒立即作废，否则随时间老化
+        let age = if self.pose_dirty { f32::INFINITY } else { self.bounds_age.get() + dt };
+        self.bounds_age.set(age);
+        // 静止的角色（暂停中，或动画已播完）姿势没被改动时跳过骨骼重算，沿用缓存的世界变换
+        if (dt <= 0.0 || self.settled) && !self.pose_dirty {
+            return;
+        }
+        self.pose_dirty = false;
+        self.state.update(dt);
+        self.skeleton.set_to_setup_pose(); // 重置到绑定姿势
+        // 附件覆盖在动画应用前先换上：网格变形（FFD）时间轴只写入插槽上当前挂着的网格，
+        // 若等应用完再换附件，插槽的变形数据会被清空，覆盖后的网格就只剩绑定姿势的形状
+        self.apply_attachment_overrides();
+        let _ = self.state.apply(&mut self.skeleton);
+        for o in &self.bone_overrides {
+            if let Some(mut bone) = self.skeleton.find_bone_mut(&o.bone) {
+                let base = if o.additive { bone.rotation() } else { 0.0 };
+                bone.set_rotation(base + o.degrees);
+            }
+        }
+        // 动画关键帧中的附件切换可能又换掉了覆盖，这里再压回去（附件未变时不会清空变形数据）
+        self.apply_attachment_overrides();
+        // 世界顶点在绘制时才由 compute_world_vertices 结合插槽的变形数据算出，这里只需更新骨骼
+        self.skeleton.update_world_transform(); // 更新世界变换
+        self.settled = !self.is_animating();
+    }
+
+    /// 把手动设置的附件覆盖套到插槽上
+    fn apply_attachment_overrides(&mut self) {
+        for (slot, attachment) in &self.attachment_overrides {
+            self.skeleton.set_attachment(slot, attachment.as_deref());
+        }
+    }
+
+    /// 是否还有轨道在播放：循环动画、尚未播完的动画或正在混合过渡的动画
+    fn is_animating(&self) -> bool {
+        (0..=BLINK_TRACK)
+            .filter_map(|i| self.state.track_at_index(i))
+            .any(|entry| entry.looping() || entry.track_time() < entry.animation().duration() || entry.mix_time() < entry.mix_duration())
+    }
+    
+    /// 查找附件所属 atlas 页的纹理，找不到时回退到第一页
+    fn page_texture(&self, renderer: rusty_spine::RendererObject) -> Option<TextureId> {
+        // 必要性证明：附件的 rendererObject 是 C 运行时挂上的裸指针，只能通过 unsafe 读取
+        // 安全边界：附件均由 atlas 加载器创建，其 rendererObject 必定指向所属 atlas 的区域
+        let region = unsafe { renderer.get_atlas_region() };
+        region
+            .and_then(|r| {
+                let page = r.page();
+                self.texture_ids.iter().find(|(name, _)| name == page.name()).map(|(_, id)| *id)
+            })
+            .or_else(|| self.texture_ids.first().map(|(_, id)| *id))
+    }
+
+    /// 渲染Spine对象，按附件所属页的纹理把顶点追加到批次中（可与同纹理角色合批）
+    /// `geometry` 为调用方持有的复用缓冲区，避免每帧重新分配；`tint` 与所有顶点颜色相乘
+    fn paint(&self, batcher: &mut MeshBatcher, geometry: &mut AttachmentGeometry, tint: Color32) {
+        let mut bounds = Rect::NOTHING; // 顺带记录包围盒，供下一帧的屏幕外剔除使用
+        // 遍历所有绘制顺序的插槽
+        for slot in self.skeleton.draw_order() {
+            self.paint_slot(&slot, batcher, geometry, tint, &mut bounds);
+        }
+        self.store_bounds(Some(bounds));
+    }
+
+    /// 绘制单个插槽当前的附件，并把它的顶点范围并入 `bounds`
+    fn paint_slot(&self, slot: &Slot, batcher: &mut MeshBatcher, geometry: &mut AttachmentGeometry, tint: Color32, bounds: &mut Rect) {
+        let attachment = match slot.attachment() { 
+            Some(a) => a, 
+            None => return 
+        };
+        
+        // 处理区域附件（RegionAttachment，普通图片）
+        if let Some(region) = attachment.as_region() {
+            let tex_id = match self.page_texture(region.renderer_object()) { 
+                Some(id) => id, 
+                None => return 
+            };
+            geometry.load_region(slot, &region);
+            self.push_to_mesh(batcher.mesh_for(tex_id), geometry, slot, region.color(), tint);
+            geometry.extend_bounds(bounds);
+        } 
+        // 处理网格附件（MeshAttachment，变形网格）
+        else if let Some(mesh_att) = attachment.as_mesh() {
+            let tex_id = match self.page_texture(mesh_att.renderer_object()) { 
+                Some(id) => id, 
+                None => return 
+            };
+            // 数据不一致的网格直接跳过，不让脏数据进入渲染批次
+            if geometry.load_mesh(slot, &mesh_att) {
+                self.push_to_mesh(batcher.mesh_for(tex_id), geometry, slot, mesh_att.color(), tint);
+                geometry.extend_bounds(bounds);
+            }
+        }
+        // 其他类型（点、包围盒、路径、裁剪等）不参与渲染：调试构建下记录下来，便于定位部件缺失
+        else if cfg!(debug_assertions) {
+            let info = format!("插槽 {} 的附件 {} 类型为 {:?}", slot.data().name(), attachment.name(), attachment.attachment_type());
+            let mut diag = self.diagnostics.borrow_mut();
+            if diag.seen.insert(info.clone()) {
+                diag.pending.push(info);
+            }
+        }
+    }
+
+    /// 交错绘制两个角色（用于手搭肩等角色间穿插的构图）
+    /// 各插槽按它在本角色绘制顺序中的相对位置（0~1）排成统一深度，`front` 的深度再加上 `bias`
+    /// （正值整体靠前）；深度相同时 `back` 的插槽先画
+    fn paint_interleaved(back: &SpineObject, back_tint: Color32, front: &SpineObject, front_tint: Color32, bias: f32, batcher: &mut MeshBatcher, geometry: &mut AttachmentGeometry) {
+        let back_slots: Vec<_> = back.skeleton.draw_order().collect();
+        let front_slots: Vec<_> = front.skeleton.draw_order().collect();
+        let depth = |i: usize, n: usize| (i as f32 + 0.5) / n.max(1) as f32;
+        let (mut back_bounds, mut front_bounds) = (Rect::NOTHING, Rect::NOTHING);
+        let (mut i, mut j) = (0, 0);
+        while i < back_slots.len() || j < front_slots.len() {
+            let back_first = j >= front_slots.len()
+                || (i < back_slots.len() && depth(i, back_slots.len()) <= depth(j, front_slots.len()) + bias);
+            if back_first {
+                back.paint_slot(&back_slots[i], batcher, geometry, back_tint, &mut back_bounds);
+                i += 1;
+            } else {
+                front.paint_slot(&front_slots[j], batcher, geometry, front_tint, &mut front_bounds);
+                j += 1;
+            }
+        }
+        back.store_bounds(Some(back_bounds));
+        front.store_bounds(Some(front_bounds));
+    }
+
+    /// 在脚底绘制半透明椭圆阴影（中心最浓、边缘渐隐），大小随当前缩放（含景深）变化
+    /// 与立绘走同一个批次，先于本角色调用即可压在本角色之下、更靠后的角色之上
+    fn paint_shadow(&self, batcher: &mut MeshBatcher, cfg: &CharacterShadow, offset_px: f32) {
+        let bounds = match self.setup_bounds.filter(|b| b.is_positive()) {
+            Some(b) => b,
+            None => return,
+        };
+        let flip = if self.flip_x { -1.0 } else { 1.0 };
+        let center = Pos2::new(
+            self.position.x + bounds.center().x * self.scale * flip,
+            self.position.y - bounds.min.y * self.scale + offset_px
+        );
+        let rx = bounds.height() * SHADOW_WIDTH_RATIO * self.scale / 2.0;
+        let ry = rx * SHADOW_ASPECT;
+        let alpha = (cfg.opacity.clamp(0.0, 1.0) * self.visible_opacity() * 255.0) as u8;
+
+        let mesh = batcher.mesh_for(TextureId::default());
+        let base = mesh.vertices.len() as u32;
+        mesh.vertices.push(Vertex { pos: center, uv: egui::epaint::WHITE_UV, color: Color32::from_black_alpha(alpha) });
+        for k in 0..SHADOW_SEGMENTS {
+            let angle = k as f32 / SHADOW_SEGMENTS as f32 * std::f32::consts::TAU;
+            let pos = center + Vec2::new(angle.cos() * rx, angle.sin() * ry);
+            mesh.vertices.push(Vertex { pos, uv: egui::epaint::WHITE_UV, color: Color32::TRANSPARENT });
+        }
+        for k in 0..SHADOW_SEGMENTS {
+            mesh.add_triangle(base, base + 1 + k, base + 1 + (k + 1) % SHADOW_SEGMENTS);
+        }
+    }
+
+    /// 换上后台按新的贴图分辨率与颜色滤镜处理好的各页（`prepared` 为（原图, 处理后的贴图））
+    /// `uploaded` 记录本轮已上传的页，其他槽位共用同一份贴图时直接复用句柄，不重复上传
+    /// 有页不在 `prepared` 中（处理期间才装入、已按当前设置上传）时保持原样
+    fn replace_textures(&mut self, ctx: &egui::Context, options: egui::TextureOptions, color_filter: ColorFilter, prepared: &[(Arc<egui::ColorImage>, Arc<egui::ColorImage>)], uploaded: &mut Vec<(Arc<egui::ColorImage>, TextureHandle)>) {
+        let pages: Option<Vec<_>> = self.source_pages.iter()
+            .map(|w| prepared.iter().find(|(source, _)| Arc::as_ptr(source) == w.as_ptr()))
+            .collect();
+        let pages = match pages {
+            Some(pages) => pages,
+            None => return,
+        };
+        let mut textures = Vec::with_capacity(pages.len());
+        for ((name, id), (source, image)) in self.texture_ids.iter_mut().zip(pages) {
+            let handle = match uploaded.iter().find(|(img, _)| Arc::ptr_eq(img, source)) {
+                Some((_, handle)) => handle.clone(),
+                None => {
+                    let handle = ctx.load_texture(name.as_str(), image.clone(), options);
+                    uploaded.push((source.clone(), handle.clone()));
+                    handle
+                }
+            };
+            *id = handle.id();
+            textures.push(handle);
+        }
+        self._textures = textures;
+        self.color_filter = color_filter;
+    }
+
+    /// 各页原尺寸贴图（任意一页已被资源缓存卸载时为 None）
+    fn full_pages(&self) -> Option<Vec<Arc<egui::ColorImage>>> {
+        self.source_pages.iter().map(|w| w.upgrade()).collect()
+    }
+
+    /// 取出尚未输出的附件诊断信息
+    fn take_diagnostics(&self) -> Vec<String> {
+        std::mem::take(&mut self.diagnostics.borrow_mut().pending)
+    }
+    
+    /// 当前姿势的缩略图光栅化任务：等比缩放居中，背景透明
+    /// 直接在 CPU 上光栅化角色自己的网格，不需要截全屏再裁剪
+    fn thumbnail_job(&self, size: [usize; 2]) -> Option<RasterJob> {
+        let src = self.screen_bounds()?;
+        Some(self.region_job(src, size))
+    }
+
+    /// 把当前姿势中屏幕区域 `src` 等比缩放居中渲染为 `size` 大小的透明背景图片
+    fn render_region(&self, src: Rect, size: [usize; 2]) -> egui::ColorImage {
+        self.region_job(src, size).run()
+    }
+
+    /// 收集 `render_region` 所需的网格与贴图，可交给后台线程光栅化
+    fn region_job(&self, src: Rect, size: [usize; 2]) -> RasterJob {
+        let mut batcher = MeshBatcher::default();
+        self.paint(&mut batcher, &mut AttachmentGeometry::default(), Color32::WHITE);
+        
+        // 把屏幕坐标映射到缩略图坐标
+        let target = Vec2::new(size[0] as f32, size[1] as f32);
+        let scale = (target.x / src.width()).min(target.y / src.height());
+        let pad = (target - src.size() * scale) / 2.0;
+        for mesh in &mut batcher.meshes {
+            for v in &mut mesh.vertices {
+                v.pos = Pos2::ZERO + (v.pos - src.min) * scale + pad;
+            }
+        }
+        
+        let pages = self.texture_ids.iter()
+            .zip(&self.page_images)
+            .map(|((_, id), img)| (*id, img.clone()))
+            .collect();
+        RasterJob { meshes: batcher.meshes, pages, size, fill: SceneBackground::Image(None) }
+    }
+
+    /// 复制一个只用于离屏渲染的实例：共享骨架数据与贴图 CPU 副本，不持有 GPU 纹理
+    /// 可以移交到后台线程逐帧渲染，不影响槽位中的原角色；原尺寸贴图仍在缓存中时用原图，否则退回低分辨率副本
+    fn offscreen_copy(&self, anim: &str) -> Self {
+        let (mut copy, _) = Self::from_skeleton_data(self.skeleton_data.clone(), Some(anim));
+        copy.texture_ids = self.texture_ids.clone();
+        copy.page_images = self.full_pages().unwrap_or_else(|| self.page_images.clone());
+        copy.source_pages = self.source_pages.clone();
+        copy.flip_x = self.flip_x;
+        if let Some(skin) = self.current_skin() {
+            copy.set_skin(&skin);
+        }
+        copy
+    }
+
+    /// 将提取好的附件顶点推送到Mesh（长度与索引已由 `AttachmentGeometry` 校验）
+    fn push_to_mesh(&self, mesh: &mut Mesh, geometry: &AttachmentGeometry, slot: &Slot, att_c: rusty_spine::Color, tint: Color32) {
+        let s_c = slot.color(); // 插槽颜色（叠加附件颜色与环境光）
+        let [t_r, t_g, t_b, t_a] = tint.to_array();
+        // 顶点颜色同样必须是预乘的：RGB 先乘以 Alpha，否则半透明插槽会被加亮而出现白边
+        let alpha = s_c.a * att_c.a * self.visible_opacity();
+        let color = self.color_filter.apply_tint(Color32::from_rgba_premultiplied(
+            (s_c.r * att_c.r * alpha * t_r as f32) as u8, 
+            (s_c.g * att_c.g * alpha * t_g as f32) as u8,
+            (s_c.b * att_c.b * alpha * t_b as f32) as u8, 
+            (alpha * t_a as f32) as u8,
+        ));
+        
+        // 添加顶点
+        let idx_offset = mesh.vertices.len() as u32;
+        
+        // 水平翻转只镜像顶点的世界位置，UV 原样保留：贴图仍按附件原本的区域采样，
+        // 效果与 Spine 中把骨架 scaleX 设为 -1 一致（只改变朝向，不会错位或采样到别的区域）
+        let flip = if self.flip_x { -1.0 } else { 1.0 };
+        for (p, uv) in geometry.positions().iter().zip(geometry.uvs()) {
+            let pos = Pos2::new(
+                p[0] * self.scale * flip + self.position.x, 
+                -p[1] * self.scale + self.position.y // Y轴翻转（Spine使用向上为负的坐标系）
+            );
+            mesh.vertices.push(Vertex { 
+                pos, 
+                uv: Pos2::new(uv[0], uv[1]), 
+                color 
+            });
+        }
+        
+        // 添加三角形索引；镜像会让三角形绕序反转，翻转时交换后两个顶点，
+        // 保证无论朝向如何三角形都是同一绕序（开启背面剔除的后端也能正确绘制）
+        for tri in geometry.indices().chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| idx_offset + i as u32);
+            if self.flip_x {
+                mesh.indices.extend_from_slice(&[a, c, b]);
+            } else {
+                mesh.indices.extend_from_slice(&[a, b, c]);
+            }
+        }
+    }
+}
+
+// ============================================================================
+// 主应用程序逻辑
+// ============================================================================
+
+struct AefrApp {
+    scheduler: AefrScheduler,            // 绅士调度器
+    is_auto_enabled: bool,               // 自动播放开关
+    skip_mode: bool,                     // 快进开关（按住 Ctrl 时也会临时快进）
+    auto_delay: f32,                     // 自动播放推进延时（秒）
+    auto_timer: f32,                     // 自动播放计时器
+    scene_timer: f32,                    // 进入当前幕后的计时（幕级定时推进）
+    animation_paused: bool,              // 全局动画暂停（冻结画面）
+    step_requested: bool,                // 暂停时请求单帧步进
+    offline_render: Option<OfflineRender>, // 离线渲染状态（None 表示未在渲染）
+    default_transition: TransitionConfig, // 全局默认切幕过渡
+    transition: Option<ActiveTransition>, // 进行中的切幕过渡
+    show_dialogue: bool,                 // 对话框显示开关
+    scenario: Scenario,                   // 当前剧本
+    current_scene_idx: usize,            // 当前场景索引
+    target_chars: Vec<char>,             // 目标文本字符（打字机效果）
+    visible_count: usize,                // 当前可见字符数
+    segment: (usize, usize),             // 正在显示的（幕索引, 对话片段索引）
+    type_timer: f32,                     // 打字机计时器
+    console_open: bool,                  // 控制台面板开关
+    panels: CreatorPanels,               // 创作者面板各子面板的开关
+    selected_slot: usize,                // 当前选中的角色槽位（多选时为最后点选的主槽位）
+    selected_slots: HashSet<usize>,      // 所有选中的槽位（总是包含主槽位，批量操作作用于全部）
+    console_input: String,               // 控制台输入缓冲
+    console_logs: Vec<String>,           // 控制台日志
+    log_tx: Sender<String>,              // 日志文件写入线程的发送端
+    show_anim_preview: bool,             // 动画预览面板开关
+    show_settings: bool,                 // 设置面板开关
+    show_stats: bool,                    // 剧本统计面板开关
+    show_perf: bool,                     // 性能面板开关
+    show_preview_window: bool,           // 独立预览窗口（第二屏幕/投影仪）开关
+    solo_preview: bool,                  // 单人预览：只显示选中槽位的立绘，居中并适配视口，隐藏对话框
+    culled_count: usize,                 // 本帧因完全在屏幕外而跳过绘制的角色数
+    show_gif_export: bool,               // GIF 导出窗口开关
+    gif_export: GifExport,               // GIF 导出窗口中的参数
+    resource_cache: ResourceCache,       // 已解析立绘资源缓存（同一立绘再次加载时复用）
+    cache_limit_mb: u32,                 // 资源缓存内存上限（MB），超出时按 LRU 卸载（优先卸载未使用的资源）
+    show_storyboard: bool,               // 分镜视图窗口开关
+    show_new_wizard: bool,               // 新建剧本向导窗口开关
+    new_template: ScenarioTemplate,      // 向导中选中的模板
+    scene_thumbnails: HashMap<usize, SceneThumbnail>, // 各幕的画面缩略图（未浏览的幕先用背景占位，剧本结构变化时清空）
+    storyboard_timer: f32,               // 当前幕缩略图刷新计时
+    font_defs: FontDefinitions,          // 当前字体定义（注册外部字体时在此基础上追加）
+    custom_fonts: Vec<String>,           // 运行时注册的外部字体名
+    dialogue_fonts: DialogueFonts,       // 对话框名字/内容字体
+    dialogue_style: DialogueStyle,       // 对话框外观样式
+    name_box: Option<TextureHandle>,     // 名字框背景贴图（None 时使用默认渐变）
+    name_box_path: Option<String>,       // 名字框贴图路径
+    name_box_border: f32,                // 名字框贴图九宫格边宽（贴图像素）
+    anim_filter: String,                 // 动作窗口动画列表的搜索关键词
+    characters: Vec<Option<SpineObject>>, // 5个角色槽位
+    geometry: AttachmentGeometry,        // 附件顶点提取缓冲区（跨帧复用）
+    background: Option<TextureHandle>,   // 背景纹理
+    background_image: Option<Arc<egui::ColorImage>>, // 背景的低分辨率 CPU 副本（离屏渲染分镜缩略图用）
+    slot_paths: Vec<Option<String>>,     // 各槽位已请求加载的资源路径
+    current_bg_path: Option<String>,     // 当前背景路径
+    foreground: Option<TextureHandle>,   // 前景纹理
+    foreground_image: Option<Arc<egui::ColorImage>>, // 前景的低分辨率 CPU 副本
+    current_fg_path: Option<String>,     // 当前前景路径
+    current_bgm_path: Option<String>,    // 当前BGM路径
+    voice_pending: bool,                 // 当前幕语音正在读取（自动播放等待）
+    voice_request: u64,                  // 最近一次语音请求的编号（切幕后旧请求读完的数据被丢弃）
+    texture_request: u64,                // 最近一次重新处理立绘贴图的编号（被更新的请求取代时丢弃旧结果）
+    voice_duration: Option<f32>,         // 当前语音时长（用于匹配打字速度，未知时为 None）
+    layout_preset: LayoutPreset,         // 当前立绘排列预设
+    depth_mode: bool,                    // 景深排列：按角色深度缩放、压暗并由远到近绘制
+    interleave: Option<InterleavePair>,  // 穿插绘制的两个角色（None 为各角色独立绘制）
+    facing_pair: Option<(usize, usize)>, // 对话朝向：这两个槽位的角色始终面对面（None 为不干预朝向）
+    layout_snapshots: HashMap<String, LayoutSnapshot>, // 命名构图预设（持久化到 LAYOUTS_FILE）
+    snapshot_name: String,               // 保存构图时输入的名称
+    gesture_consumed: bool,              // 本次按下已被触摸手势占用（不再当作对话点击）
+    talk_bounce: TalkBounce,             // 说话弹跳配置
+    shadow: CharacterShadow,             // 立绘脚底阴影配置
+    scenario_base_dir: Option<std::path::PathBuf>, // 剧本文件所在目录（解析相对路径用）
+    current_file: Option<std::path::PathBuf>, // 当前剧本文件路径（未保存过为 None）
+    recent_file: Option<std::path::PathBuf>, // 最近打开/保存的剧本（跨次启动保留）
+    project: Option<Project>,            // 当前打开的多章节项目（None 为单个剧本）
+    project_file: Option<std::path::PathBuf>, // 项目文件路径（未保存过为 None）
+    chapter_idx: usize,                  // 当前剧本在项目中的章节索引
+    window_size: Option<Vec2>,           // 当前窗口内部尺寸（退出时持久化）
+    dirty: bool,                         // 剧本有未保存的修改
+    window_title: String,                // 上次设置的窗口标题（变化时才发送命令）
+    show_exit_confirm: bool,             // 显示未保存退出确认对话框
+    zip_choice: Option<ZipChoice>,       // 等待用户选择的压缩包立绘
+    allow_close: bool,                   // 已确认退出，不再拦截关闭请求
+    read_only: bool,                     // 只读演示模式（隐藏编辑界面，Esc 或右上角按钮退出）
+    resource_report: Option<Vec<MissingResource>>, // 资源检查结果（Some 时显示报告窗口）
+    scenario_diff: Option<ScenarioDiffView>, // 剧本对比窗口（Some 时显示）
+    undo_stack: Vec<Scenario>,           // 剧本结构修改前的快照（撤销用）
+    merge_after_current: bool,           // 追加剧本时插入到当前幕之后（否则追加到末尾）
+    bgm_playlist: BgmPlaylist,           // BGM 播放列表
+    texture_alpha: TextureAlpha,         // 立绘贴图的 Alpha 存储方式
+    texture_filter: egui::TextureFilter, // 立绘贴图的缩放过滤方式（平滑/像素风）
+    texture_scale: f32,                  // 立绘贴图分辨率（相对原图，<1 省显存）
+    color_filter: ColorFilter,           // 已上传贴图所用的颜色滤镜（跟随当前幕）
+    applied_texture_scale: f32,          // 已上传贴图所用的分辨率（滑块拖动结束后同步）
+    command_queue: VecDeque<String>,     // 待执行的批处理命令
+    variables: HashMap<String, String>,  // 对话变量（文本中的 {key} 替换为对应值）
+    thumbnails: Vec<Option<TextureHandle>>, // 各槽位角色的缩略图缓存（头像/预览用）
+    show_speaker_avatar: bool,           // 对话框中显示说话者头像
+    type_sound: TypeSound,               // 打字机音效配置
+    bgm_duck: BgmDuck,                   // 语音/音效播放时压低 BGM 的配置
+    auto_expression: bool,               // 按对话关键词自动切换说话者表情
+    auto_blink: bool,                    // 自动眨眼开关
+    blink_anim_name: String,             // 眨眼动画名（不区分大小写）
+    expression_rules: Vec<ExpressionRule>, // 关键词 → 表情动画规则（按顺序匹配第一条）
+    pending_loads: usize,                // 正在进行的角色加载数（批处理遇到时等待）
+    load_progress: Vec<Option<(f32, LoadStage)>>, // 各槽位加载进度（None 表示未在加载）
+    load_tasks: Vec<Option<LoadTask>>,   // 各槽位进行中的加载任务（取消/超时用）
+    next_load_id: u64,                   // 下一个加载任务的编号
+    load_timeout: f32,                   // 加载超时（秒），0 表示不限
+    load_retry: LoadRetry,               // 加载失败自动重试的次数与间隔
+    load_pool: LoadPool,                 // 按优先级调度的立绘加载线程池
+    preload_next: bool,                  // 进入一幕时在后台预加载下一幕的立绘
+    preloads: Vec<(String, Arc<AtomicBool>)>, // 进行中的预加载（资源路径, 取消标志）
+    hot_reload: bool,                    // 立绘文件修改后自动重新加载
+    look_at_mouse: bool,                 // 角色头部跟随鼠标（骨骼手动控制演示）
+    look_at_bone: String,                // 看向鼠标时控制的骨骼名（不区分大小写）
+    text_effect: TextEffect,             // 对话框文字描边/投影
+    continue_indicator: ContinueIndicator, // 对话框"继续"指示器样式
+    show_spectrum: bool,                 // 画面左上角显示 BGM 频谱
+    spectrum: SpectrumAnalyzer,          // BGM 频谱分析状态
+    auto_stagger: f32,                   // 切幕同时加载多个新角色时的入场间隔（秒），0 表示同时出现
+    pending_enter: Vec<Option<std::time::Instant>>, // 各槽位的入场时刻（切幕时从同一起点按顺序分配，加载完成后等到该时刻入场）
+    dialogue_auto_height: bool,          // 对话框高度按内容行数自适应
+    type_unit: TypeUnit,                 // 打字机推进单位（按字/按词/整段）
+    speaker_label: bool,                 // 在说话者头顶（look_at_bone 骨骼上方）显示名字标签
+    hot_reload_timer: f32,               // 距上次检查修改时间的累计时间
+    slot_mtimes: Vec<Option<std::time::SystemTime>>, // 各槽位立绘文件加载时的最新修改时间
+    hot_reload_pending: Vec<bool>,       // 槽位正在热重载（加载完成后沿用旧角色的位置与缩放）
+    audio_manager: Option<AudioManager>, // 音频管理器（可选）
+    tx: Sender<AppCommand>,              // 命令发送器
+    rx: Receiver<AppCommand>,            // 命令接收器
+}
+
+impl AefrApp {
+    fn new(cc: &eframe::CreationContext) -> Self {
+        let mut app = Self::with_context(&cc.egui_ctx, cc.storage);
+
+        // 启动参数指定了剧本（或项目）时直接加载，并以只读的演示模式从第一幕开始播放
+        if let Some(path) = startup_scenario_path() {
+            if path.extension().map_or(false, |e| e.eq_ignore_ascii_case(PROJECT_EXTENSION)) {
+                match app.load_project_from(path) {
+                    Ok(()) => app.read_only = true,
+                    Err(e) => app.log(format!("[错误] 启动项目加载失败: {}", e)),
+                }
+            } else {
+                match app.load_scenario_from(path) {
+                    Ok(()) => {
+                        app.read_only = true;
+                        app.apply_scene();
+                    }
+                    Err(e) => app.log(format!("[错误] 启动剧本加载失败: {}", e)),
+                }
+            }
+        }
+        app
+    }
+
+    /// 按 egui 上下文与持久化存储创建编辑器状态（不处理启动参数，测试中也用它构造）
+    fn with_context(egui_ctx: &egui::Context, storage: Option<&dyn eframe::Storage>) -> Self {
+        // 初始化配置
+        let font_defs = setup_embedded_font(egui_ctx);
+        egui_extras::install_image_loaders(egui_ctx);
+        let (tx, rx) = channel();
+        let audio_manager = AudioManager::new().ok(); // 音频管理器可能初始化失败
+        
+        // 创建初始场景
+        let startup_text = "AEFR 已启动！\n正在等待指令......";
+        let mut first_scene = Scene::default();
+        first_scene.speaker_name = "OxidizedSchale".into();
+        first_scene.speaker_aff = "AEFR Contributors".into();
+        first_scene.dialogue_content = startup_text.into();
+
+        let mut app = Self {
+            scheduler: AefrScheduler::new(),
+            is_auto_enabled: true, 
+            skip_mode: false,
+            auto_delay: DEFAULT_AUTO_DELAY,
+            auto_timer: 0.0,
+            scene_timer: 0.0,
+            animation_paused: false,
+            step_requested: false,
+            offline_render: None,
+            default_transition: TransitionConfig::default(),
+            transition: None,
+            show_dialogue: true,
+            scenario: Scenario { scenes: vec![first_scene] },
+            current_scene_idx: 0,
+            target_chars: startup_text.chars().collect(), 
+            visible_count: 0, 
+            segment: (0, 0),
+            type_timer: 0.0,
+            console_open: false,
+            panels: CreatorPanels::default(),
+            selected_slot: 0,
+            selected_slots: HashSet::from([0]),
+            console_input: String::new(),
+            console_logs: Vec::new(),
+            log_tx: spawn_log_writer(LOG_FILE),
+            show_anim_preview: false,
+            show_settings: false,
+            show_stats: false,
+            show_perf: false,
+            show_preview_window: false,
+            solo_preview: false,
+            culled_count: 0,
+            show_gif_export: false,
+            gif_export: GifExport::default(),
+            resource_cache: ResourceCache::default(),
+            cache_limit_mb: DEFAULT_CACHE_LIMIT_MB,
+            show_storyboard: false,
+            show_new_wizard: false,
+            new_template: ScenarioTemplate::default(),
+            scene_thumbnails: HashMap::new(),
+            storyboard_timer: 0.0,
+            font_defs,
+            custom_fonts: Vec::new(),
+            dialogue_fonts: DialogueFonts::default(),
+            dialogue_style: DialogueStyle::default(),
+            name_box: None,
+            name_box_path: None,
+            name_box_border: NAME_BOX_BORDER,
+            anim_filter: String::new(),
+            characters: (0..5).map(|_| None).collect(), // 初始化5个空槽位
+            geometry: AttachmentGeometry::default(),
+            background: None,
+            background_image: None,
+            slot_paths: vec![None; 5],
+            current_bg_path: None,
+            foreground: None,
+            foreground_image: None,
+            current_fg_path: None,
+            current_bgm_path: None,
+            voice_pending: false,
+            voice_request: 0,
+            texture_request: 0,
+            voice_duration: None,
+            layout_preset: LayoutPreset::default(),
+            depth_mode: false,
+            interleave: None,
+            facing_pair: None,
+            layout_snapshots: load_layout_snapshots(),
+            snapshot_name: String::new(),
+            gesture_consumed: false,
+            talk_bounce: TalkBounce::default(),
+            shadow: CharacterShadow::default(),
+            scenario_base_dir: None,
+            current_file: None,
+            recent_file: None,
+            project: None,
+            project_file: None,
+            chapter_idx: 0,
+            window_size: None,
+            dirty: false,
+            window_title: String::new(),
+            show_exit_confirm: false,
+            zip_choice: None,
+            allow_close: false,
+            read_only: false,
+            resource_report: None,
+            scenario_diff: None,
+            undo_stack: Vec::new(),
+            merge_after_current: false,
+            bgm_playlist: BgmPlaylist::default(),
+            texture_alpha: TextureAlpha::default(),
+            texture_filter: egui::TextureFilter::Linear,
+            texture_scale: 1.0,
+            color_filter: ColorFilter::None,
+            applied_texture_scale: 1.0,
+            command_queue: VecDeque::new(),
+            variables: HashMap::new(),
+            thumbnails: vec![None; 5],
+            show_speaker_avatar: false,
+            type_sound: TypeSound::default(),
+            bgm_duck: BgmDuck::default(),
+            auto_expression: true,
+            auto_blink: true,
+            blink_anim_name: "blink".into(),
+            expression_rules: default_expression_rules(),
+            pending_loads: 0,
+            load_progress: vec![None; 5],
+            load_tasks: (0..5).map(|_| None).collect(),
+            next_load_id: 0,
+            load_timeout: DEFAULT_LOAD_TIMEOUT,
+            load_retry: LoadRetry::default(),
+            load_pool: LoadPool::new(std::thread::available_parallelism().map_or(2, |n| n.get().clamp(2, MAX_LOAD_WORKERS))),
+            preload_next: true,
+            preloads: Vec::new(),
+            hot_reload: true,
+            look_at_mouse: false,
+            look_at_bone: "head".into(),
+            speaker_label: false,
+            text_effect: TextEffect::default(),
+            continue_indicator: ContinueIndicator::default(),
+            show_spectrum: false,
+            spectrum: SpectrumAnalyzer::default(),
+            auto_stagger: 0.0,
+            pending_enter: vec![None; 5],
+            dialogue_auto_height: true,
+            type_unit: TypeUnit::default(),
+            hot_reload_timer: 0.0,
+            slot_mtimes: vec![None; 5],
+            hot_reload_pending: vec![false; 5],
+            audio_manager,
+            tx, rx,
+        };
+        app.log("[系统] 编辑器就绪。".into());
+
+        // 恢复上次的窗口尺寸与面板状态
+        if let Some(state) = storage.and_then(|s| eframe::get_value::<PersistedState>(s, PERSIST_KEY)) {
+            if let Some([w, h]) = state.window_size {
+                egui_ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(Vec2::new(w, h)));
+            }
+            app.console_open = state.console_open;
+            app.panels = state.panels;
+            app.select_slot(state.selected_slot.min(app.characters.len() - 1));
+            app.recent_file = state.recent_file;
+            if let Some((path, border)) = state.name_box {
+                app.name_box_border = border;
+                let _ = app.tx.send(AppCommand::LoadNameBox(Some(path)));
+            }
+        }
+        app
+    }
+
+    /// 记录一条日志：显示在控制台，同时带时间戳追加写入日志文件
+    /// 所有日志（包括其他线程经 `AppCommand::Log` 回传的）都应经过这里
+    fn log(&mut self, msg: String) {
+        let _ = self.log_tx.send(format!("[{}] {}", format_timestamp(std::time::SystemTime::now()), msg));
+        self.console_logs.push(msg);
+    }
+
+    /// 同步当前场景数据到UI状态
+    /// 打字机按变量替换后的文本逐字显示
+    fn sync_scene_to_ui(&mut self) {
+        if self.segment.0 != self.current_scene_idx {
+            self.segment = (self.current_scene_idx, 0);
+        }
+        if let Some(scene) = self.scenario.scenes.get(self.current_scene_idx) {
+            let segments = dialogue_segments(&scene.dialogue_content);
+            let text = segments.get(self.segment.1).or(segments.last()).copied().unwrap_or_default();
+            self.target_chars = substitute_variables(text, &self.variables).chars().collect();
+        }
+    }
+
+    /// 当前幕在当前片段之后是否还有片段
+    fn has_more_segments(&self) -> bool {
+        self.scenario.scenes.get(self.current_scene_idx)
+            .map_or(false, |s| self.segment.1 + 1 < dialogue_segments(&s.dialogue_content).len())
+    }
+
+    /// 推进对话：先显示本幕的下一片段，全部片段显示完后再进入下一幕
+    fn advance_dialogue(&mut self) -> bool {
+        if !self.has_more_segments() {
+            return self.advance_scene();
+        }
+        if self.transition.is_some() {
+            return false;
+        }
+        self.segment.1 += 1;
+        self.sync_scene_to_ui();
+        self.visible_count = 0;
+        self.type_timer = 0.0;
+        self.auto_timer = 0.0;
+        true
+    }
+
+    /// 替换当前幕的背景；换成图片时立即加载（`apply_scene` 之后再改背景不会自动重新加载）
+    fn set_scene_background(&mut self, background: SceneBackground) {
+        if let Some(path) = background.image_path().filter(|p| Some(*p) != self.current_bg_path.as_ref()) {
+            self.send_playback(AppCommand::LoadBackground(path.clone()));
+        }
+        self.scenario.scenes[self.current_scene_idx].background = background;
+    }
+
+    /// 发送播放流程的命令（不标记未保存）
+    fn send_playback(&self, cmd: AppCommand) {
+        let _ = self.tx.send(AppCommand::Playback(Box::new(cmd)));
+    }
+
+    /// 应用当前幕：同步文本，并按幕记录恢复背景/BGM/角色
+    fn apply_scene(&mut self) {
+        let scene = match self.scenario.scenes.get(self.current_scene_idx) {
+            Some(s) => s.clone(),
+            None => return,
+        };
+        self.segment = (self.current_scene_idx, 0); // 进入一幕总是从第一段开始
+        self.sync_scene_to_ui();
+        self.visible_count = 0; // 重新播放打字机
+        self.type_timer = 0.0;
+        self.auto_timer = 0.0;
+        self.scene_timer = 0.0;
+        self.trigger_speaker_bounce();
+        self.apply_expression_rules();
+
+        // 背景与BGM只在与当前不同时才重新加载
+        if scene.background.is_image() && scene.background.image_path() != self.current_bg_path.as_ref() {
+            if let Some(path) = scene.background.image_path().cloned() {
+                self.send_playback(AppCommand::LoadBackground(path));
+            }
+        }
+        if scene.foreground_path != self.current_fg_path {
+            match scene.foreground_path.clone() {
+                Some(path) => self.send_playback(AppCommand::LoadForeground(path)),
+                None => self.send_playback(AppCommand::ClearForeground),
+            }
+        }
+        // 语音每幕都重新播放（上一幕没播完的语音被打断，尚未读完的作废）
+        self.voice_duration = None;
+        self.voice_pending = false;
+        self.voice_request += 1;
+        match scene.voice_path.clone() {
+            Some(path) => { let _ = self.tx.send(AppCommand::PlayVoice(path)); }
+            None => {
+                if let Some(mgr) = &self.audio_manager { mgr.stop_voice(); }
+            }
+        }
+        if scene.bgm_path != self.current_bgm_path {
+            match scene.bgm_path.clone() {
+                Some(path) => self.send_playback(AppCommand::PlayBgm(path)),
+                None => self.send_playback(AppCommand::StopBgm),
+            }
+        }
+
+        // 一次要加载多个新角色时按槽位顺序错开入场：入场时刻都从切幕这一刻算起，与各自的加载耗时无关
+        let entering: Vec<usize> = (0..self.characters.len())
+            .filter(|&i| scene.char_paths[i].is_some() && scene.char_paths[i] != self.slot_paths[i])
+            .collect();
+        self.pending_enter = vec![None; self.characters.len()];
+        if self.auto_stagger > 0.0 && entering.len() > 1 {
+            let start = std::time::Instant::now();
+            for (k, &i) in entering.iter().enumerate() {
+                self.pending_enter[i] = Some(start + std::time::Duration::from_secs_f32(k as f32 * self.auto_stagger));
+            }
+        }
+
+        // 角色：资源不同则重新加载，相同则直接恢复动画和皮肤
+        for i in 0..self.characters.len() {
+            if scene.char_paths[i] != self.slot_paths[i] {
+                match scene.char_paths[i].clone() {
+                    Some(path) => { 
+                        self.send_playback(AppCommand::RequestLoad { 
+                            slot_idx: i, 
+                            path, 
+                            initial_anim: scene.char_anims[i].clone(),
+                            priority: LoadPriority::Current,
+                        }); 
+                    }
+                    None => self.send_playback(AppCommand::RemoveCharacter(i)),
+                }
+            } else if let Some(char) = &mut self.characters[i] {
+                if let Some(anim) = &scene.char_anims[i] {
+                    char.set_animation_by_name(anim, true);
+                }
+                if let Some(skin) = &scene.char_skins[i] {
+                    char.set_skin(skin);
+                }
+                char.set_attachment_overrides(&scene.char_attachments[i]);
+            }
+        }
+        self.schedule_preloads();
+    }
+
+    /// 在后台预加载下一幕要换上的立绘（放入资源缓存，不占用槽位），排在当前幕的加载之后
+    /// 切到别的幕后不再需要的预加载会被取消
+    fn schedule_preloads(&mut self) {
+        let wanted: Vec<String> = match self.scenario.scenes.get(self.current_scene_idx + 1) {
+            Some(next) if self.preload_next => next.char_paths.iter().flatten()
+                .filter(|path| !self.slot_paths.contains(&Some(path.to_string())))
+                .filter(|path| !matches!(split_zip_path(path), Some((_, None)))) // 需要用户选择的压缩包不预加载
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.preloads.retain(|(path, cancel)| {
+            let keep = wanted.contains(path) && !cancel.load(Ordering::Relaxed);
+            if !keep {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            keep
+        });
+        for path in wanted {
+            if self.resource_cache.entries.contains_key(&path) || self.preloads.iter().any(|(p, _)| *p == path) {
+                continue;
+            }
+            let cancel = Arc::new(AtomicBool::new(false));
+            self.preloads.push((path.clone(), cancel.clone()));
+            let tx_cb = self.tx.clone();
+            let alpha = self.texture_alpha;
+            let file = self.resolve_path(&path).to_string_lossy().into_owned();
+            self.load_pool.submit(LoadPriority::Preload, move || {
+                if cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+                let result = SpineObject::load_async_no_gpu(&file, None, alpha, &cancel, &|_, _| {});
+                cancel.store(true, Ordering::Relaxed); // 标记结束，之后的调度不再等待它
+                if let Ok((obj, pages, _, skel_bytes)) = result {
+                    let _ = tx_cb.send(AppCommand::PreloadReady(path, Box::new(obj), pages, skel_bytes));
+                }
+            });
+        }
+    }
+
+    /// 绘制所有角色：连续使用同一纹理的部件（包括相邻角色之间）合并为一个Mesh，减少 draw call
+    /// 只合并相邻的部件，保证前后遮挡顺序不变
+    fn paint_characters(&mut self, ui: &mut egui::Ui) {
+        let mut batcher = MeshBatcher::default();
+        let ambient = self.current_ambient();
+        let screen = ui.clip_rect();
+        let shadow_offset = self.shadow.offset * screen.height() / BASE_HEIGHT;
+        self.culled_count = 0;
+        let order = self.draw_order();
+        // 穿插绘制的两个角色：在较后画的那个的位置上一起交错绘制，较先画的那个跳过
+        let interleaved = self.interleave
+            .filter(|p| p.first != p.second && order.contains(&p.first) && order.contains(&p.second))
+            .map(|p| {
+                let pos = |slot| order.iter().position(|&i| i == slot).unwrap_or(0);
+                if pos(p.first) < pos(p.second) { (p.first, p.second, p.bias) } else { (p.second, p.first, p.bias) }
+            });
+        for i in order {
+            if let Some((back_idx, front_idx, bias)) = interleaved {
+                if i == back_idx {
+                    continue;
+                }
+                if i == front_idx {
+                    if let (Some(back), Some(front)) = (&self.characters[back_idx], &self.characters[front_idx]) {
+                        if self.shadow.enabled {
+                            back.paint_shadow(&mut batcher, &self.shadow, shadow_offset);
+                            front.paint_shadow(&mut batcher, &self.shadow, shadow_offset);
+                        }
+                        let (back_tint, front_tint) = (self.character_tint(back, ambient), self.character_tint(front, ambient));
+                        SpineObject::paint_interleaved(back, back_tint, front, front_tint, bias, &mut batcher, &mut self.geometry);
+                    }
+                    continue;
+                }
+            }
+            if let Some(char) = &self.characters[i] {
+                // 等待入场或完全透明的角色不绘制
+                if char.visible_opacity() <= 0.0 {
+                    continue;
+                }
+                // 完全在屏幕外的角色（如滑出后）不提交顶点，骨骼照常更新
+                if char.is_offscreen(screen) {
+                    self.culled_count += 1;
+                    continue;
+                }
+                if self.shadow.enabled {
+                    char.paint_shadow(&mut batcher, &self.shadow, shadow_offset);
+                }
+                let tint = self.character_tint(char, ambient);
+                char.paint(&mut batcher, &mut self.geometry, tint);
+            }
+        }
+        for mesh in batcher.meshes {
+            ui.painter().add(Shape::mesh(mesh));
+        }
+        if cfg!(debug_assertions) {
+            let found: Vec<(usize, String)> = self.characters.iter().enumerate()
+                .filter_map(|(i, c)| c.as_ref().map(|c| (i, c)))
+                .flat_map(|(i, c)| c.take_diagnostics().into_iter().map(move |info| (i, info)))
+                .collect();
+            for (i, info) in found {
+                self.log(format!("[诊断] 槽位 {}: {}，未渲染", i, info));
+            }
+        }
+    }
+
+    /// 拾取指定屏幕坐标下的角色（后绘制的在上层，优先命中）
+    fn pick_character(&self, pos: Pos2) -> Option<usize> {
+        self.draw_order().into_iter().rev()
+            .find(|&i| self.characters[i].as_ref().and_then(|c| c.screen_bounds()).map_or(false, |b| b.contains(pos)))
+    }
+
+    /// 已加载角色的绘制顺序（先画的在后面）：默认按槽位，景深排列时由远到近
+    fn draw_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.characters.len())
+            .filter(|&i| self.characters[i].is_some() && (!self.solo_preview || i == self.selected_slot))
+            .collect();
+        if self.depth_mode {
+            let depth = |i: usize| self.characters[i].as_ref().map_or(0.0, |c| c.depth);
+            order.sort_by(|&a, &b| depth(b).total_cmp(&depth(a))); // 稳定排序：深度相同时保持槽位顺序
+        }
+        order
+    }
+
+    /// 角色的绘制色调：环境光，景深排列时再按深度压暗
+    fn character_tint(&self, char: &SpineObject, ambient: Color32) -> Color32 {
+        if self.depth_mode {
+            dim_color(ambient, 1.0 - DEPTH_MAX_DIM * char.depth.clamp(0.0, 1.0))
+        } else {
+            ambient
+        }
+    }
+
+    /// 鼠标悬停在角色包围盒上时显示其信息（槽位、资源文件、动画、缩放）
+    /// 只在指针直接位于画面上时显示，被面板等窗口遮挡时不显示
+    fn show_character_tooltip(&self, ui: &egui::Ui) {
+        let pos = match ui.input(|i| i.pointer.hover_pos()) {
+            Some(p) => p,
+            None => return,
+        };
+        if ui.ctx().layer_id_at(pos) != Some(ui.layer_id()) {
+            return;
+        }
+        let idx = match self.pick_character(pos) {
+            Some(i) => i,
+            None => return,
+        };
+        let char = match &self.characters[idx] {
+            Some(c) => c,
+            None => return,
+        };
+        let file = self.slot_paths[idx].as_deref()
+            .map(|p| std::path::Path::new(p).file_name().map_or(p.to_string(), |n| n.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        egui::show_tooltip_at_pointer(ui.ctx(), egui::Id::new("character_tooltip"), |ui| {
+            ui.label(format!("槽位: [{}]", idx));
+            ui.label(format!("资源: {}", file));
+            ui.label(format!("动画: {}", char.current_anim_name().unwrap_or_else(|| "无".into())));
+            ui.label(format!("缩放: {:.2} (手动 ×{:.2})", char.scale, char.user_scale));
+        });
+    }
+
+    /// 处理触摸手势：双指捏合缩放 / 平移选中角色，单指长按选中角色
+    fn handle_touch_gestures(&mut self, ctx: &egui::Context, scale_factor: f32) {
+        let (multi_touch, any_down, any_released, press_origin, press_start, dragging, now) = ctx.input(|i| (
+            i.multi_touch(),
+            i.pointer.any_down(),
+            i.pointer.any_released(),
+            i.pointer.press_origin(),
+            i.pointer.press_start_time(),
+            i.pointer.is_decidedly_dragging(),
+            i.time,
+        ));
+        
+        // 手指全部抬起后的下一帧才复位，保证松手那一帧的点击仍被屏蔽
+        if !any_down && !any_released {
+            self.gesture_consumed = false;
+        }
+
+        // 双指：捏合缩放与平移作用于当前选中角色
+        if let Some(touch) = multi_touch {
+            self.gesture_consumed = true;
+            if let Some(Some(char)) = self.characters.get_mut(self.selected_slot) {
+                char.user_scale = (char.user_scale * touch.zoom_delta).clamp(0.2, 5.0);
+                char.offset += touch.translation_delta / scale_factor;
+            }
+            return;
+        }
+
+        // 单指长按（未拖动）：选中手指下的角色
+        if any_down && !dragging && !self.gesture_consumed {
+            if let (Some(origin), Some(start)) = (press_origin, press_start) {
+                if now - start >= LONG_PRESS_SECS {
+                    self.gesture_consumed = true;
+                    if let Some(idx) = self.pick_character(origin) {
+                        self.select_slot(idx);
+                        self.log(format!("[系统] 已选中槽位 {}", idx));
+                    }
+                }
+            }
+        }
+    }
+
+    /// 打字机每字间隔：有语音且时长已知时，让文字在语音结束前（约 90% 处）刚好显示完
+    fn type_interval(&self) -> f32 {
+        match self.voice_duration {
+            Some(d) if !self.target_chars.is_empty() => {
+                (d * 0.9 / self.target_chars.len() as f32).clamp(VOICE_TYPE_INTERVAL.0, VOICE_TYPE_INTERVAL.1)
+            }
+            _ => TYPE_INTERVAL,
+        }
+    }
+
+    /// 当前幕语音是否仍在读取或播放
+    fn voice_playing(&self) -> bool {
+        self.voice_pending || self.audio_manager.as_ref().map_or(false, |m| !m.voice_finished())
+    }
+
+    /// 单选一个槽位（清除其他选中）
+    fn select_slot(&mut self, idx: usize) {
+        self.selected_slot = idx;
+        self.selected_slots = HashSet::from([idx]);
+    }
+
+    /// Ctrl+点击：切换槽位的选中状态，至少保留一个选中槽位
+    fn toggle_slot_selection(&mut self, idx: usize) {
+        if !self.selected_slots.insert(idx) {
+            if self.selected_slots.len() == 1 {
+                return;
+            }
+            self.selected_slots.remove(&idx);
+            if self.selected_slot == idx {
+                self.selected_slot = *self.selected_slots.iter().min().unwrap();
+            }
+        } else {
+            self.selected_slot = idx;
+        }
+    }
+
+    /// 所有选中槽位的已加载角色共有的动画名（保持主槽位中的顺序）
+    fn common_selected_anims(&self) -> Vec<String> {
+        let mut anims = match self.characters.get(self.selected_slot) {
+            Some(Some(char)) => char.get_anim_names(),
+            _ => return Vec::new(),
+        };
+        for &idx in &self.selected_slots {
+            if let Some(Some(char)) = self.characters.get(idx) {
+                let names = char.get_anim_names();
+                anims.retain(|a| names.contains(a));
+            }
+        }
+        anims
+    }
+
+    /// 用角色当前姿势在后台重新生成指定槽位的缩略图
+    fn refresh_thumbnail(&self, idx: usize) {
+        if let Some(job) = self.characters.get(idx).and_then(|c| c.as_ref()).and_then(|c| c.thumbnail_job([THUMBNAIL_SIZE; 2])) {
+            self.spawn_thumbnail(ThumbnailTarget::Slot(idx), job);
+        }
+    }
+
+    /// 在后台线程光栅化缩略图，完成后经 `ThumbnailReady` 回传
+    fn spawn_thumbnail(&self, target: ThumbnailTarget, job: RasterJob) {
+        let tx_cb = self.tx.clone();
+        thread::spawn(move || {
+            let _ = tx_cb.send(AppCommand::ThumbnailReady(target, job.run()));
+        });
+    }
+
+    /// 收集当前画面（背景 + 立绘 + 前景）的缩略图光栅化任务，与立绘缩略图共用 CPU 光栅化
+    fn scene_thumbnail_job(&self, screen: Rect, size: [usize; 2]) -> RasterJob {
+        let mut batcher = MeshBatcher::default();
+        let mut pages: Vec<(TextureId, Arc<egui::ColorImage>)> = Vec::new();
+        let full_uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+        let fill = self.current_background().clone();
+        if let (SceneBackground::Image(_), Some(tex), Some(img)) = (&fill, &self.background, &self.background_image) {
+            batcher.mesh_for(tex.id()).add_rect_with_uv(cover_rect(screen, tex.size_vec2()), full_uv, Color32::WHITE);
+            pages.push((tex.id(), img.clone()));
+        }
+        let ambient = self.current_ambient();
+        let mut geometry = AttachmentGeometry::default();
+        for char in self.draw_order().into_iter().filter_map(|i| self.characters[i].as_ref()) {
+            char.paint(&mut batcher, &mut geometry, self.character_tint(char, ambient));
+            pages.extend(char.texture_ids.iter().zip(&char.page_images).map(|((_, id), img)| (*id, img.clone())));
+        }
+        if let (Some(tex), Some(img)) = (&self.foreground, &self.foreground_image) {
+            batcher.mesh_for(tex.id()).add_rect_with_uv(cover_rect(screen, tex.size_vec2()), full_uv, Color32::WHITE);
+            pages.push((tex.id(), img.clone()));
+        }
+
+        // 屏幕坐标 → 缩略图坐标
+        let scale = Vec2::new(size[0] as f32 / screen.width(), size[1] as f32 / screen.height());
+        for mesh in &mut batcher.meshes {
+            for v in &mut mesh.vertices {
+                v.pos = Pos2::ZERO + (v.pos - screen.min) * scale;
+            }
+        }
+        RasterJob { meshes: batcher.meshes, pages, size, fill }
+    }
+
+    /// 当前幕的背景
+    fn current_background(&self) -> &SceneBackground {
+        const NONE: &SceneBackground = &SceneBackground::Image(None);
+        self.scenario.scenes.get(self.current_scene_idx).map_or(NONE, |s| &s.background)
+    }
+
+    /// 分镜视图打开时定期用当前画面刷新当前幕的缩略图（过渡中不截取，避免混入上一幕），
+    /// 其他还没有缩略图的幕先生成只画背景的占位图
+    fn update_storyboard(&mut self, ctx: &egui::Context, dt: f32) {
+        if !self.show_storyboard {
+            return;
+        }
+        self.request_backdrop_thumbnails();
+        if self.transition.is_some() {
+            return;
+        }
+        self.storyboard_timer += dt;
+        if self.storyboard_timer < STORYBOARD_REFRESH_SECS {
+            return;
+        }
+        self.storyboard_timer = 0.0;
+        let job = self.scene_thumbnail_job(ctx.screen_rect(), STORYBOARD_THUMB_SIZE);
+        self.spawn_thumbnail(ThumbnailTarget::Scene(self.current_scene_idx), job);
+    }
+
+    /// 为还没有缩略图的幕在后台生成背景占位图：立绘要加载完才能画，未浏览的幕只画背景，
+    /// 浏览到该幕时换成完整画面；所有幕在同一个线程中依次处理
+    fn request_backdrop_thumbnails(&mut self) {
+        let mut jobs = Vec::new();
+        for (idx, scene) in self.scenario.scenes.iter().enumerate() {
+            if self.scene_thumbnails.contains_key(&idx) {
+                continue;
+            }
+            self.scene_thumbnails.insert(idx, SceneThumbnail::Pending);
+            let file = scene.background.image_path().map(|p| self.resolve_path(p));
+            jobs.push((idx, scene.background.clone(), file, scene.color_filter));
+        }
+        if jobs.is_empty() {
+            return;
+        }
+        let tx_cb = self.tx.clone();
+        thread::spawn(move || {
+            for (idx, background, file, filter) in jobs {
+                if let Some(img) = backdrop_thumbnail(&background, file.as_deref(), filter, STORYBOARD_THUMB_SIZE) {
+                    let _ = tx_cb.send(AppCommand::ThumbnailReady(ThumbnailTarget::SceneBackdrop(idx, background), img));
+                }
+            }
+        });
+    }
+
+    /// 交给加载线程池解析立绘资源，完成后回传 `LoadSuccess` / `LoadFailed`
+    /// 调用前该槽位不应有进行中的加载（由调用方先 `cancel_load`）；被取消的任务不再回传结果，排队中被取消的任务不会开始
+    fn spawn_character_load(&mut self, slot_idx: usize, path: String, initial_anim: Option<String>, priority: LoadPriority) {
+        let load_id = self.next_load_id;
+        self.next_load_id += 1;
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.load_tasks[slot_idx] = Some(LoadTask { id: load_id, cancel: cancel.clone(), started: std::time::Instant::now() });
+        self.pending_loads += 1;
+        self.load_progress[slot_idx] = Some((0.0, LoadStage::Queued));
+        CharacterLoad {
+            slot_idx,
+            load_id,
+            file: self.resolve_path(&path).to_string_lossy().into_owned(),
+            path,
+            initial_anim,
+            alpha: self.texture_alpha,
+            retry: self.load_retry,
+            priority,
+            attempt: 0,
+            started: std::time::Instant::now(),
+            cancel,
+            tx: self.tx.clone(),
+            pool: self.load_pool.clone(),
+        }.submit();
+    }
+
+    /// 结束槽位上编号为 `load_id` 的加载：清理进度与计数
+    /// 返回 false 表示这是已被取消或取代的任务迟到的结果，应当丢弃
+    fn finish_load(&mut self, idx: usize, load_id: u64) -> bool {
+        match self.load_tasks.get(idx) {
+            Some(Some(task)) if task.id == load_id => {}
+            _ => return false,
+        }
+        self.load_tasks[idx] = None;
+        self.pending_loads = self.pending_loads.saturating_sub(1);
+        self.load_progress[idx] = None;
+        true
+    }
+
+    /// 取消槽位上进行中的加载（用户取消、超时、槽位被重新加载或移除），没有时返回 false
+    /// 加载线程在下一块读取或下一阶段开始前退出，不再回传结果
+    fn cancel_load(&mut self, idx: usize) -> bool {
+        let task = match self.load_tasks.get_mut(idx).and_then(|t| t.take()) {
+            Some(task) => task,
+            None => return false,
+        };
+        task.cancel.store(true, Ordering::Relaxed);
+        self.pending_loads = self.pending_loads.saturating_sub(1);
+        self.load_progress[idx] = None;
+        self.hot_reload_pending[idx] = false;
+        true
+    }
+
+    /// 放弃超过 `load_timeout` 仍未完成的加载
+    fn check_load_timeouts(&mut self) {
+        if self.load_timeout <= 0.0 {
+            return;
+        }
+        for idx in 0..self.load_tasks.len() {
+            let timed_out = self.load_tasks[idx].as_ref()
+                .map_or(false, |t| t.started.elapsed().as_secs_f32() > self.load_timeout);
+            if timed_out && self.cancel_load(idx) {
+                self.log(format!("[错误] 槽位 {} 加载超过 {:.0} 秒，已放弃（可在设置中调整超时）", idx, self.load_timeout));
+            }
+        }
+    }
+
+    /// 按当前过滤方式、贴图分辨率与颜色滤镜重新上传所有角色的贴图
+    /// 缩放与滤镜在后台线程处理，完成后经 `CharacterPagesReady` 换上（期间先显示旧贴图，切幕过渡不卡顿）
+    fn reupload_character_textures(&mut self, ctx: &egui::Context) {
+        let (scale, filter) = (self.texture_scale, self.color_filter);
+        let max_side = ctx.input(|i| i.max_texture_side);
+        let mut sources: Vec<Arc<egui::ColorImage>> = Vec::new();
+        let mut evicted = Vec::new();
+        for (idx, char) in self.characters.iter().enumerate() {
+            let char = match char {
+                Some(c) => c,
+                None => continue,
+            };
+            match char.full_pages() {
+                Some(pages) => {
+                    for page in pages {
+                        if !sources.iter().any(|s| Arc::ptr_eq(s, &page)) {
+                            sources.push(page);
+                        }
+                    }
+                }
+                None => evicted.push((idx, char.current_anim_name())),
+            }
+        }
+        self.applied_texture_scale = scale;
+        self.texture_request += 1;
+        let request = self.texture_request;
+        let tx_cb = self.tx.clone();
+        thread::spawn(move || {
+            let pages = sources.into_iter()
+                .map(|source| {
+                    let prepared = prepare_page(&source, scale, filter, max_side);
+                    (source, prepared)
+                })
+                .collect();
+            let _ = tx_cb.send(AppCommand::CharacterPagesReady(request, filter, pages));
+        });
+        // 原图已被缓存卸载的角色重新读取（与热重载相同，保留摆放），新贴图按当前设置上传
+        for (idx, anim) in evicted {
+            if self.load_tasks[idx].is_some() {
+                continue;
+            }
+            if let Some(path) = self.slot_paths[idx].clone() {
+                self.hot_reload_pending[idx] = true;
+                self.spawn_character_load(idx, path, anim, LoadPriority::Current);
+            }
+        }
+    }
+
+    /// 当前幕的颜色滤镜与已上传贴图所用的不同时（切幕、编辑或命令修改），重新上传立绘、背景与前景
+    fn sync_color_filter(&mut self, ctx: &egui::Context) {
+        let wanted = self.scenario.scenes.get(self.current_scene_idx).map_or(ColorFilter::None, |s| s.color_filter);
+        if wanted == self.color_filter {
+            return;
+        }
+        self.color_filter = wanted;
+        self.reupload_character_textures(ctx);
+        // 背景与前景不保留原图，在后台重新读取并套用新滤镜（完成前先显示旧的）
+        if self.background.is_some() {
+            match self.current_bg_path.clone().filter(|_| self.current_background().is_image()) {
+                Some(path) => self.send_playback(AppCommand::LoadBackground(path)),
+                None => self.current_bg_path = None, // 之后切到图片背景的幕时按新滤镜重新加载
+            }
+        }
+        if let Some(path) = self.current_fg_path.clone().filter(|_| self.foreground.is_some()) {
+            self.send_playback(AppCommand::LoadForeground(path));
+        }
+    }
+
+    /// 其他槽位已上传的同一份贴图（原图为同一个 `Arc`）的纹理句柄与低分辨率副本
+    /// 句柄本身带引用计数：多个槽位共用同一 GPU 纹理，最后一个持有者被移除时才释放
+    fn shared_page(&self, image: &Arc<egui::ColorImage>) -> Option<(TextureHandle, Arc<egui::ColorImage>)> {
+        self.characters.iter().flatten().find_map(|c| {
+            c.source_pages.iter().zip(&c._textures).zip(&c.page_images)
+                .find(|((source, _), _)| std::sync::Weak::as_ptr(source) == Arc::as_ptr(image))
+                .map(|((_, handle), raster)| (handle.clone(), raster.clone()))
+        })
+    }
+
+    /// 把解析好的角色放入槽位：上传各页纹理、恢复皮肤/眨眼/热重载前的摆放并生成缩略图
+    /// 异步加载完成与缓存复用共用此流程；同一立绘已在其他槽位时共用骨架数据与纹理
+    fn install_character(&mut self, ctx: &egui::Context, idx: usize, mut loaded: SpineObject, pages: Vec<(String, Arc<egui::ColorImage>)>) {
+        // 逐页加载纹理到GPU（其他槽位已上传的页直接共用）
+        let options = texture_options(self.texture_filter);
+        // 角色只保留低分辨率副本与指向原图的弱引用，原图由资源缓存按内存上限管理
+        for (page_name, color_image) in pages {
+            let (handle, raster) = self.shared_page(&color_image).unwrap_or_else(|| (
+                upload_page(ctx, &page_name, &color_image, options, self.texture_scale, self.color_filter),
+                Arc::new(downsample_image(&color_image, RASTER_PAGE_MAX_SIDE)),
+            ));
+            loaded.texture_ids.push((page_name, handle.id()));
+            loaded._textures.push(handle);
+            loaded.page_images.push(raster);
+            loaded.source_pages.push(Arc::downgrade(&color_image));
+        }
+        loaded.color_filter = self.color_filter;
+        // 恢复当前幕记录的皮肤
+        if let Some(skin) = &self.scenario.scenes[self.current_scene_idx].char_skins[idx] {
+            loaded.set_skin(skin);
+        }
+        loaded.set_attachment_overrides(&self.scenario.scenes[self.current_scene_idx].char_attachments[idx]);
+        loaded.setup_blink(Some(&self.blink_anim_name).filter(|_| self.auto_blink).map(|s| s.as_str()));
+        if let Some(at) = self.pending_enter.get_mut(idx).and_then(|d| d.take()) {
+            loaded.start_enter(at.saturating_duration_since(std::time::Instant::now()).as_secs_f32()); // 已过了入场时刻则立即入场
+        }
+        // 热重载：沿用旧角色的手动位置、缩放与不透明度
+        if std::mem::take(&mut self.hot_reload_pending[idx]) {
+            if let Some(old) = &self.characters[idx] {
+                loaded.user_scale = old.user_scale;
+                loaded.offset = old.offset;
+                loaded.opacity = old.opacity;
+            }
+        }
+        self.slot_mtimes[idx] = self.slot_paths[idx].as_deref()
+            .and_then(|path| latest_mtime(&character_files(&self.resolve_path(path).to_string_lossy(), &loaded.texture_ids)));
+        // 用初始动画第一帧的姿势在后台生成缩略图
+        loaded.update_parallel(0.0);
+        self.thumbnails[idx] = None;
+        if let Some(job) = loaded.thumbnail_job([THUMBNAIL_SIZE; 2]) {
+            self.spawn_thumbnail(ThumbnailTarget::Slot(idx), job);
+        }
+        self.characters[idx] = Some(loaded);
+        self.apply_layout(self.layout_preset); // 角色数量变化后重新排列
+        self.trim_resource_cache(); // 被替换的旧角色可能释放了资源
+    }
+
+    /// 缓存超过内存上限时按最久未使用的顺序卸载没有槽位在用的资源
+    fn trim_resource_cache(&mut self) {
+        let limit = self.cache_limit_mb as usize * 1024 * 1024;
+        for path in self.resource_cache.evict(limit) {
+            self.log(format!("[缓存] 超出内存上限，已卸载 {}", path));
+        }
+    }
+
+    /// 定期检查已加载立绘的 atlas/skel/json/png 修改时间，有变化时重新加载该槽位并保持当前动画
+    /// 不经过 `RequestLoad`，因此不会改动剧本记录
+    fn poll_hot_reload(&mut self, dt: f32) {
+        if !self.hot_reload {
+            return;
+        }
+        self.hot_reload_timer += dt;
+        if self.hot_reload_timer < HOT_RELOAD_POLL_SECS {
+            return;
+        }
+        self.hot_reload_timer = 0.0;
+        for idx in 0..self.characters.len() {
+            let (char, path) = match (&self.characters[idx], &self.slot_paths[idx]) {
+                (Some(char), Some(path)) => (char, path.clone()),
+                _ => continue,
+            };
+            if self.hot_reload_pending[idx] || self.load_tasks[idx].is_some() {
+                continue;
+            }
+            let mtime = latest_mtime(&character_files(&self.resolve_path(&path).to_string_lossy(), &char.texture_ids));
+            if mtime.is_some() && mtime > self.slot_mtimes[idx] {
+                let anim = char.current_anim_name();
+                self.slot_mtimes[idx] = mtime; // 写入未完成导致加载失败时，等下次修改再试
+                self.hot_reload_pending[idx] = true;
+                self.log(format!("[热重载] 槽位 {} 的资源已修改，重新加载", idx));
+                self.spawn_character_load(idx, path, anim, LoadPriority::Current);
+            }
+        }
+    }
+
+    /// 看向鼠标：按上一帧的骨骼位置计算每个角色头部骨骼的偏转，叠加到动画之上
+    fn update_look_at(&mut self, ctx: &egui::Context) {
+        if !self.look_at_mouse {
+            return;
+        }
+        let pointer = match ctx.pointer_hover_pos() {
+            Some(p) => p,
+            None => return,
+        };
+        for char in self.characters.iter_mut().flatten() {
+            if let Some(bone) = char.find_bone_name(&self.look_at_bone) {
+                if let Some(deg) = char.look_at_angle(&bone, pointer) {
+                    char.offset_bone_rotation(&bone, deg);
+                }
+            }
+        }
+    }
+
+    /// 绘制演出画面本身（背景、立绘、前景、对话框与选项、过渡遮罩），主窗口与预览窗口共用
+    fn draw_stage(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, rect: Rect) {
+        // 绘制背景：图片（如有）或纯色/渐变
+        match self.current_background() {
+            SceneBackground::Image(_) => {
+                if let Some(bg) = &self.background {
+                    draw_background(ui, rect, bg, Color32::WHITE);
+                }
+            }
+            fill => fill.paint(ui.painter(), rect, self.color_filter),
+        }
+        // 交叉溶解：旧背景叠在新背景上逐渐淡出
+        if let Some(t) = &self.transition {
+            if let Some(old_bg) = &t.old_background {
+                draw_background(ui, rect, old_bg, Color32::WHITE.gamma_multiply(1.0 - t.progress()));
+            }
+        }
+        
+        // 绘制所有角色
+        self.paint_characters(ui);
+
+        // 前景覆盖在所有立绘之上（背景 → 立绘 → 前景）
+        if let Some(fg) = &self.foreground {
+            draw_background(ui, rect, fg, Color32::WHITE);
+        }
+
+        // 说话者头顶名字标签
+        if self.speaker_label && !self.solo_preview {
+            self.draw_speaker_label(ui);
+        }
+
+        // 左上角随 BGM 跳动的频谱条
+        if self.show_spectrum {
+            let scale = rect.height() / BASE_HEIGHT;
+            let spectrum_rect = Rect::from_min_size(rect.min + Vec2::new(12.0, 48.0) * scale, Vec2::new(180.0, 48.0) * scale);
+            draw_spectrum(ui.painter(), spectrum_rect, &self.spectrum.bars);
+        }
+        
+        // 右上角按钮
+        draw_top_right_buttons(ui, rect, &mut self.is_auto_enabled, &mut self.skip_mode);
+        
+        // 🌟 对话框渲染逻辑：只有当【已提交】的对话内容不为空时，才显示对话框（单人预览时隐藏）
+        if self.show_dialogue && !self.solo_preview {
+            // 对话框之上的交互控件（右上角按钮、分支选项）所占区域：
+            // 落在其中的点击只交给控件本身，既不跳过打字机也不推进剧情
+            let choices_visible = !self.scenario.scenes[self.current_scene_idx].choices.is_empty()
+                && self.visible_count >= self.target_chars.len()
+                && !self.has_more_segments();
+            let mut control_rects = top_right_button_rects(rect).to_vec();
+            if self.read_only {
+                control_rects.push(exit_button_rect(rect));
+            }
+            if choices_visible {
+                control_rects.extend(choice_rects(rect, self.scenario.scenes[self.current_scene_idx].choices.len()));
+            }
+            let on_control = ctx.input(|i| i.pointer.interact_pos())
+                .map_or(false, |p| control_rects.iter().any(|r| r.contains(p)));
+
+            let committed_text: String = self.target_chars.iter().collect();
+            if !committed_text.trim().is_empty() {
+                let scene = &self.scenario.scenes[self.current_scene_idx];
+                let text: String = self.target_chars.iter().take(self.visible_count).collect();
+                let is_finished = self.visible_count >= self.target_chars.len();
+                let avatar = scene.speaker_slot
+                    .filter(|_| self.show_speaker_avatar)
+                    .and_then(|i| self.thumbnails.get(i))
+                    .and_then(|t| t.as_ref());
+                let skin = DialogueSkin {
+                    fonts: &self.dialogue_fonts,
+                    avatar,
+                    name_box: self.name_box.as_ref().map(|texture| NameBoxSkin { texture, border: self.name_box_border }),
+                    effect: &self.text_effect,
+                    indicator: &self.continue_indicator,
+                    auto_height: self.dialogue_auto_height,
+                };
+                let name = substitute_variables(&scene.speaker_name, &self.variables);
+                let affiliation = substitute_variables(&scene.speaker_aff, &self.variables);
+                let content = DialogueContent { name: &name, affiliation: &affiliation, content: &text, full_content: &committed_text };
+                if self.dialogue_style.draw(ui, rect, skin, content, is_finished) && !self.gesture_consumed && !on_control { 
+                    if is_finished {
+                        self.advance_dialogue(); // 文本已显示完则推进到下一段/下一幕
+                    } else {
+                        self.visible_count = self.target_chars.len(); // 点击跳过打字机
+                    }
+                }
+            }
+
+            // 分支选项：文本显示完毕后在对话框上方显示
+            let choices = &self.scenario.scenes[self.current_scene_idx].choices;
+            if choices_visible {
+                if let Some(target) = draw_choices(ui, rect, choices) {
+                    self.go_to_scene(target);
+                }
+            }
+        }
+        
+        // 淡入淡出遮罩：覆盖在画面最上层
+        if let Some(t) = &self.transition {
+            let alpha = (t.overlay_alpha() * 255.0) as u8;
+            match t.config.kind {
+                TransitionKind::FadeBlack => ui.painter().rect_filled(rect, 0.0, Color32::from_black_alpha(alpha)),
+                TransitionKind::FadeWhite => ui.painter().rect_filled(rect, 0.0, Color32::from_white_alpha(alpha)),
+                _ => {}
+            }
+        }
+    }
+
+    /// 独立预览窗口（投影仪/第二屏幕）：只显示演出画面，不含编辑 UI，与主窗口实时同步
+    /// 使用即时视口而非延迟视口：延迟视口的回调须为 'static + Send + Sync，
+    /// 而立绘对象持有 Spine 原生指针与 RefCell，无法跨视口共享，即时视口则可直接借用 &mut self
+    fn show_preview_viewport(&mut self, ctx: &egui::Context) {
+        let main = ctx.screen_rect();
+        let id = egui::ViewportId::from_hash_of("aefr_preview");
+        let builder = egui::ViewportBuilder::default()
+            .with_title("AEFR 预览（F11 全屏）")
+            .with_inner_size([1280.0, 720.0]);
+        ctx.show_viewport_immediate(id, builder, |ctx, class| {
+            let mut draw = |ui: &mut egui::Ui| {
+                // 画面按主窗口的比例等比缩放并居中，多余部分留黑边
+                let area = ui.max_rect();
+                let s = (area.width() / main.width()).min(area.height() / main.height());
+                let stage = Rect::from_center_size(area.center(), main.size() * s);
+                ui.set_clip_rect(stage);
+                // 立绘位置按主窗口计算，绘制期间临时映射到预览画面
+                let saved: Vec<Option<(Pos2, f32)>> = self.characters.iter().map(|c| c.as_ref().map(|c| (c.position, c.scale))).collect();
+                for char in self.characters.iter_mut().flatten() {
+                    char.position = stage.min + (char.position - main.min) * s;
+                    char.scale *= s;
+                }
+                self.draw_stage(ctx, ui, stage);
+                for (char, saved) in self.characters.iter_mut().zip(saved) {
+                    if let (Some(char), Some((position, scale))) = (char, saved) {
+                        char.position = position;
+                        char.scale = scale;
+                    }
+                }
+            };
+            if class == egui::ViewportClass::Embedded {
+                // 平台不支持多窗口（如 Android）时退化为主窗口内的子窗口
+                let mut open = true;
+                egui::Window::new("📺 预览")
+                    .open(&mut open)
+                    .default_size([480.0, 270.0])
+                    .show(ctx, |ui| {
+                        let (rect, _) = ui.allocate_exact_size(ui.available_size(), egui::Sense::hover());
+                        ui.painter().rect_filled(rect, 0.0, Color32::BLACK);
+                        draw(&mut ui.child_ui(rect, *ui.layout()));
+                    });
+                self.show_preview_window = open;
+                return;
+            }
+            egui::CentralPanel::default()
+                .frame(egui::Frame::none().fill(Color32::BLACK))
+                .show(ctx, |ui| draw(ui));
+            if ctx.input(|i| i.key_pressed(egui::Key::F11)) {
+                let fullscreen = ctx.input(|i| i.viewport().fullscreen.unwrap_or(false));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(!fullscreen));
+            }
+            if ctx.input(|i| i.viewport().close_requested()) {
+                self.show_preview_window = false;
+            }
+        });
+    }
+
+    /// 在当前幕说话者的头顶骨骼上方绘制名字标签，骨骼不存在时退回包围盒顶部中点
+    fn draw_speaker_label(&self, ui: &egui::Ui) {
+        let scene = &self.scenario.scenes[self.current_scene_idx];
+        let char = match scene.speaker_slot.and_then(|i| self.characters.get(i)).and_then(|c| c.as_ref()) {
+            Some(c) => c,
+            None => return,
+        };
+        let name = substitute_variables(&scene.speaker_name, &self.variables);
+        if name.trim().is_empty() {
+            return;
+        }
+        let anchor = match char.bone_screen_pos(&self.look_at_bone).or_else(|| char.screen_bounds().map(|b| b.center_top())) {
+            Some(p) => p,
+            None => return,
+        };
+        let font = egui::FontId::proportional((ui.max_rect().height() / BASE_HEIGHT * 18.0).max(12.0));
+        let galley = ui.painter().layout_no_wrap(name, font, Color32::WHITE);
+        // 头部骨骼原点通常在脖子处，标签再往上抬约一个头的高度（按立绘显示高度估算），避免挡住脸
+        let lift = char.setup_bounds.map_or(0.0, |b| b.height() * char.scale * SPEAKER_LABEL_LIFT);
+        let size = galley.size() + Vec2::new(16.0, 6.0);
+        let label_rect = Rect::from_center_size(anchor - Vec2::new(0.0, size.y / 2.0 + lift), size);
+        ui.painter().rect_filled(label_rect, size.y / 2.0, Color32::from_black_alpha(160));
+        ui.painter().galley(label_rect.center() - galley.size() / 2.0, galley, Color32::WHITE);
+    }
+
+    /// 关闭看向鼠标或更换骨骼时，撤销之前施加的偏转
+    fn clear_look_at(&mut self) {
+        for char in self.characters.iter_mut().flatten() {
+            char.clear_bone_override(&self.look_at_bone);
+        }
+    }
+
+    /// 按当前幕记录重新加载所有已加载的角色（加载参数变化后使其生效）
+    fn reload_characters(&mut self) {
+        self.resource_cache.clear(); // 缓存的贴图按旧参数解码，不能复用
+        let scene = &self.scenario.scenes[self.current_scene_idx];
+        for (slot_idx, path) in self.slot_paths.iter().enumerate() {
+            if let Some(path) = path {
+                let _ = self.tx.send(AppCommand::Playback(Box::new(AppCommand::RequestLoad { 
+                    slot_idx, 
+                    path: path.clone(), 
+                    initial_anim: scene.char_anims[slot_idx].clone(),
+                    priority: LoadPriority::Current,
+                })));
+            }
+        }
+    }
+
+    /// 处理拖放到窗口的文件：按扩展名加载角色 / 背景 / BGM
+    /// 角色优先加载到落点下的角色所在槽位，否则加载到当前选中槽位；一次拖入多个角色时依次占用后续槽位
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let (files, drop_pos) = ctx.input(|i| (i.raw.dropped_files.clone(), i.pointer.latest_pos()));
+        if files.is_empty() {
+            return;
+        }
+        let mut next_slot = drop_pos.and_then(|p| self.pick_character(p)).unwrap_or(self.selected_slot);
+        for file in files {
+            let path = match file.path {
+                Some(p) => p.display().to_string(),
+                None => continue, // Web 端只有文件内容，没有路径
+            };
+            let ext = std::path::Path::new(&path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            match ext.as_str() {
+                "atlas" | "zip" => {
+                    if next_slot >= self.characters.len() {
+                        self.log(format!("[错误] 没有空余槽位: {}", path));
+                        continue;
+                    }
+                    let _ = self.tx.send(AppCommand::RequestLoad { slot_idx: next_slot, path, initial_anim: None, priority: LoadPriority::Current });
+                    self.select_slot(next_slot);
+                    next_slot += 1;
+                }
+                "png" | "jpg" | "jpeg" | "webp" => { let _ = self.tx.send(AppCommand::LoadBackground(path)); }
+                "mp3" | "wav" | "ogg" => { let _ = self.tx.send(AppCommand::PlayBgm(path)); }
+                _ => self.log(format!("[错误] 无法识别的拖入文件: {}", path)),
+            }
+        }
+    }
+
+    /// 按两个角色当前的横向位置设置朝向，让他们面对面：靠左的保持原朝向（面向右），靠右的翻转
+    /// 任一槽位未加载或两者位置重合时不改动，返回是否设置成功
+    fn face_each_other(&mut self, slot_a: usize, slot_b: usize) -> bool {
+        let x = |slot: usize| self.characters.get(slot).and_then(|c| c.as_ref()).map(|c| c.position.x);
+        let (xa, xb) = match (x(slot_a), x(slot_b)) {
+            (Some(xa), Some(xb)) if slot_a != slot_b && xa != xb => (xa, xb),
+            _ => return false,
+        };
+        for (slot, flip) in [(slot_a, xa > xb), (slot_b, xb > xa)] {
+            if let Some(Some(char)) = self.characters.get_mut(slot) {
+                char.flip_x = flip;
+            }
+        }
+        true
+    }
+
+    /// 按预设重新计算所有已加载角色的横向位置与朝向
+    fn apply_layout(&mut self, preset: LayoutPreset) {
+        self.layout_preset = preset;
+        let n = self.characters.iter().flatten().count();
+        let loaded = self.characters.iter_mut().enumerate().filter_map(|(i, c)| c.as_mut().map(|c| (i, c)));
+        for (k, (slot_idx, char)) in loaded.enumerate() {
+            let (x, flip) = preset.place(slot_idx, k, n);
+            char.layout_x = x;
+            char.flip_x = flip;
+        }
+    }
+
+    /// 把当前所有角色的摆放保存为命名构图并写入磁盘
+    fn save_layout_snapshot(&mut self, name: String) {
+        let slots = self.characters.iter().map(|c| c.as_ref().map(|char| SlotTransform {
+            layout_x: char.layout_x,
+            offset: [char.offset.x, char.offset.y],
+            user_scale: char.user_scale,
+            flip_x: char.flip_x,
+            depth: char.depth,
+        })).collect();
+        self.layout_snapshots.insert(name.clone(), LayoutSnapshot { slots });
+        self.persist_layout_snapshots();
+        self.log(format!("[系统] 已保存构图: {}", name));
+    }
+
+    /// 把命名构图中的摆放写回对应槽位（快照中为空或当前未加载的槽位保持不变）
+    fn apply_layout_snapshot(&mut self, name: &str) {
+        let snapshot = match self.layout_snapshots.get(name) {
+            Some(s) => s,
+            None => return,
+        };
+        for (char, t) in self.characters.iter_mut().zip(&snapshot.slots) {
+            if let (Some(char), Some(t)) = (char, t) {
+                char.layout_x = t.layout_x;
+                char.offset = Vec2::new(t.offset[0], t.offset[1]);
+                char.user_scale = t.user_scale;
+                char.flip_x = t.flip_x;
+                char.depth = t.depth;
+            }
+        }
+    }
+
+    /// 删除命名构图
+    fn delete_layout_snapshot(&mut self, name: &str) {
+        if self.layout_snapshots.remove(name).is_some() {
+            self.persist_layout_snapshots();
+            self.log(format!("[系统] 已删除构图: {}", name));
+        }
+    }
+
+    fn persist_layout_snapshots(&mut self) {
+        let result = serde_json::to_string_pretty(&self.layout_snapshots).map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(LAYOUTS_FILE, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            self.log(format!("[错误] 构图保存失败: {}", e));
+        }
+    }
+
+    /// 在后台线程导出 GIF：用槽位角色的离屏副本渲染，不影响当前画面
+    fn start_gif_export(&mut self, job: GifExport) {
+        let obj = match self.characters.get(job.slot_idx) {
+            Some(Some(char)) => char.offscreen_copy(&job.anim),
+            _ => {
+                self.log(format!("[错误] 槽位 {} 没有角色", job.slot_idx));
+                return;
+            }
+        };
+        self.log(format!("[GIF] 开始导出 {} 到 {}", job.anim, job.path));
+        let tx_cb = self.tx.clone();
+        thread::spawn(move || {
+            let msg = match export_gif(obj, &job) {
+                Ok(frames) => format!("[GIF] 已导出 {} 帧到 {}", frames, job.path),
+                Err(e) => format!("[错误] GIF 导出失败: {}", e),
+            };
+            let _ = tx_cb.send(AppCommand::Log(msg));
+        });
+    }
+
+    /// 在后台线程把剧本导出为 HTML：对话文本先在主线程代入变量，背景图片在后台读取并内嵌
+    fn start_html_export(&mut self, path: String) {
+        let scenes: Vec<HtmlScene> = self.scenario.scenes.iter().map(|scene| HtmlScene {
+            bg: scene.background.image_path().map(|p| self.resolve_path(p)),
+            fill: scene.background.css(),
+            name: substitute_variables(&scene.speaker_name, &self.variables),
+            affiliation: substitute_variables(&scene.speaker_aff, &self.variables),
+            segments: dialogue_segments(&scene.dialogue_content).iter()
+                .map(|seg| substitute_variables(seg.trim(), &self.variables))
+                .collect(),
+            choices: scene.choices.iter().map(|(text, target)| (substitute_variables(text, &self.variables), *target)).collect(),
+        }).collect();
+        let title = self.current_file.as_ref()
+            .and_then(|p| p.file_stem())
+            .map_or("AEFR".to_string(), |n| n.to_string_lossy().into_owned());
+        self.log(format!("[HTML] 开始导出 {} 幕到 {}", scenes.len(), path));
+        let tx_cb = self.tx.clone();
+        thread::spawn(move || {
+            let msg = match export_html(&scenes, &title, &path) {
+                Ok(missing) if missing.is_empty() => format!("[HTML] 已导出到 {}", path),
+                Ok(missing) => format!("[HTML] 已导出到 {}（{} 张背景读取失败，已略过: {}）", path, missing.len(), missing.join(", ")),
+                Err(e) => format!("[错误] HTML 导出失败: {}", e),
+            };
+            let _ = tx_cb.send(AppCommand::Log(msg));
+        });
+    }
+
+    /// 开始离线渲染：从第一幕开始按固定帧率逐帧推进剧情并截图保存
+    fn start_offline_render(&mut self, out_dir: String, fps: f32) {
+        let out_dir = std::path::PathBuf::from(out_dir);
+        if let Err(e) = std::fs::create_dir_all(&out_dir) {
+            self.log(format!("[错误] 无法创建输出目录: {}", e));
+            return;
+        }
+        self.log(format!("[渲染] 开始导出帧序列到 {} ({} FPS)", out_dir.display(), fps));
+        self.offline_render = Some(OfflineRender { out_dir, fps: fps.max(1.0), frame_idx: 0, awaiting_capture: false });
+        self.animation_paused = false;
+        self.current_scene_idx = 0;
+        self.apply_scene();
+    }
+
+    /// 结束离线渲染
+    fn stop_offline_render(&mut self, reason: &str) {
+        if let Some(render) = self.offline_render.take() {
+            self.log(format!("[渲染] {}，共导出 {} 帧", reason, render.frame_idx));
+        }
+    }
+
+    /// 处理离线渲染的截图回传：保存收到的帧，并返回本帧应使用的固定时间步长
+    fn poll_offline_render(&mut self, ctx: &egui::Context) -> Option<f32> {
+        let render = self.offline_render.as_mut()?;
+        
+        // 收到上一帧的截图：后台线程编码保存，避免阻塞UI
+        let shot = ctx.input(|i| i.raw.events.iter().find_map(|e| match e {
+            egui::Event::Screenshot { image, .. } => Some(image.clone()),
+            _ => None,
+        }));
+        if let Some(image) = shot {
+            render.frame_idx += 1;
+            render.awaiting_capture = false;
+            let path = render.out_dir.join(format!("frame_{:04}.png", render.frame_idx));
+            let tx_cb = self.tx.clone();
+            thread::spawn(move || {
+                let [w, h] = image.size;
+                let rgba: Vec<u8> = image.pixels.iter().flat_map(|c| c.to_array()).collect();
+                let saved = image::RgbaImage::from_raw(w as u32, h as u32, rgba)
+                    .ok_or_else(|| "帧数据尺寸不匹配".to_string())
+                    .and_then(|img| img.save(&path).map_err(|e| e.to_string()));
+                if let Err(e) = saved {
+                    let _ = tx_cb.send(AppCommand::Log(format!("[错误] 保存帧失败 {}: {}", path.display(), e)));
+                }
+            });
+        }
+
+        // 等待截图期间冻结剧情，否则推进一帧并请求截图
+        if render.awaiting_capture {
+            Some(0.0)
+        } else {
+            render.awaiting_capture = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Screenshot);
+            Some(1.0 / render.fps)
+        }
+    }
+
+    /// 从文件加载剧本（替换当前剧本），成功后记为当前文件并停在第一幕
+    fn load_scenario_from(&mut self, path: std::path::PathBuf) -> Result<(), String> {
+        let mut scenario = read_scenario_file(&path)?;
+        if scenario.scenes.is_empty() {
+            return Err("剧本中没有任何幕".into());
+        }
+        for warning in scenario.sanitize() {
+            self.log(format!("[警告] {}", warning));
+        }
+        self.scenario = scenario;
+        self.scene_thumbnails.clear();
+        self.scenario_base_dir = path.parent().map(|d| d.to_path_buf());
+        self.recent_file = Some(path.clone());
+        self.current_file = Some(path);
+        self.dirty = false;
+        self.current_scene_idx = 0;
+        self.sync_scene_to_ui();
+        self.visible_count = self.target_chars.len();
+        Ok(())
+    }
+
+    /// 从文件加载多章节项目，并打开第一章
+    fn load_project_from(&mut self, path: std::path::PathBuf) -> Result<(), String> {
+        let data = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let project = serde_json::from_str::<Project>(&data).map_err(|e| e.to_string())?;
+        if project.chapters.is_empty() {
+            return Err("项目中没有任何章节".into());
+        }
+        self.project = Some(project);
+        self.project_file = Some(path);
+        self.open_chapter(0)
+    }
+
+    /// 把项目保存到指定文件；章节路径尽量改写为相对项目文件所在目录，方便整个目录一起移动
+    fn save_project_to(&mut self, path: std::path::PathBuf) -> Result<(), String> {
+        let chapters = (0..self.project.as_ref().map_or(0, |p| p.chapters.len()))
+            .filter_map(|i| self.chapter_path(i))
+            .map(|c| match path.parent() {
+                Some(base) => c.strip_prefix(base).map(|r| r.to_path_buf()).unwrap_or(c),
+                None => c,
+            })
+            .collect();
+        let project = Project { chapters };
+        let json = serde_json::to_string_pretty(&project).map_err(|e| e.to_string())?;
+        std::fs::write(&path, json).map_err(|e| e.to_string())?;
+        self.project = Some(project);
+        self.project_file = Some(path);
+        Ok(())
+    }
+
+    /// 项目中第 idx 章剧本文件的实际路径
+    fn chapter_path(&self, idx: usize) -> Option<std::path::PathBuf> {
+        let chapter = self.project.as_ref()?.chapters.get(idx)?;
+        Some(match self.project_file.as_ref().and_then(|p| p.parent()) {
+            Some(base) if chapter.is_relative() => base.join(chapter),
+            _ => chapter.clone(),
+        })
+    }
+
+    /// 打开项目中的指定章节，从其第一幕开始播放
+    fn open_chapter(&mut self, idx: usize) -> Result<(), String> {
+        let path = self.chapter_path(idx).ok_or("章节不存在")?;
+        self.load_scenario_from(path)?;
+        self.chapter_idx = idx;
+        self.apply_scene();
+        Ok(())
+    }
+
+    /// 本章最后一幕播完后接着打开项目的下一章（连续播放）
+    /// 当前剧本不是项目中的这一章（另行打开了其他剧本）或有未保存修改时不切换，以免丢失编辑
+    fn advance_chapter(&mut self) -> bool {
+        let next = self.chapter_idx + 1;
+        if self.dirty || self.chapter_path(next).is_none() || self.current_file != self.chapter_path(self.chapter_idx) {
+            return false;
+        }
+        match self.open_chapter(next) {
+            Ok(()) => {
+                self.log(format!("[系统] 进入第 {} 章", next + 1));
+                true
+            }
+            Err(e) => {
+                self.log(format!("[错误] 第 {} 章加载失败: {}", next + 1, e));
+                false
+            }
+        }
+    }
+
+    /// 用模板替换当前剧本（视为未保存过的新文件），清空上一剧本的画面与撤销记录
+    fn new_scenario(&mut self, template: ScenarioTemplate) {
+        self.scenario = template.build();
+        self.current_file = None;
+        self.scenario_base_dir = None;
+        self.dirty = false;
+        self.undo_stack.clear();
+        self.scene_thumbnails.clear();
+        self.background = None;
+        self.background_image = None;
+        self.current_bg_path = None;
+        self.current_scene_idx = 0;
+        self.apply_scene();
+        self.log(format!("[系统] 已按「{}」模板新建剧本", template.label()));
+    }
+
+    /// 把剧本保存到指定文件，成功后记为当前文件并清除未保存标记
+    fn save_scenario_to(&mut self, path: std::path::PathBuf) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&self.scenario).map_err(|e| e.to_string())?;
+        std::fs::write(&path, json).map_err(|e| e.to_string())?;
+        self.scenario_base_dir = path.parent().map(|d| d.to_path_buf());
+        self.recent_file = Some(path.clone());
+        self.current_file = Some(path);
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// 窗口标题随当前剧本文件名与未保存状态更新（"文件名* - AEFR Editor"）
+    fn update_window_title(&mut self, ctx: &egui::Context) {
+        let name = self.current_file.as_ref()
+            .and_then(|p| p.file_name())
+            .map_or("未命名剧本".to_string(), |n| n.to_string_lossy().into_owned());
+        let title = format!("{}{} - AEFR Editor", name, if self.dirty { "*" } else { "" });
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+
+    /// 解析剧本中的资源路径：相对路径基于剧本文件所在目录（资源检查与各加载流程共用）
+    fn resolve_path(&self, path: &str) -> std::path::PathBuf {
+        let p = std::path::Path::new(path);
+        match &self.scenario_base_dir {
+            Some(base) if p.is_relative() => base.join(p),
+            _ => p.to_path_buf(),
+        }
+    }
+
+    /// 检查剧本所有幕引用的背景/BGM/角色资源是否存在
+    fn check_resources(&self) -> Vec<MissingResource> {
+        let mut missing = Vec::new();
+        for (scene_idx, scene) in self.scenario.scenes.iter().enumerate() {
+            let mut refs: Vec<(String, &String)> = Vec::new();
+            if let Some(p) = scene.background.image_path() { refs.push(("背景".into(), p)); }
+            if let Some(p) = &scene.foreground_path { refs.push(("前景".into(), p)); }
+            if let Some(p) = &scene.bgm_path { refs.push(("BGM".into(), p)); }
+            if let Some(p) = &scene.voice_path { refs.push(("语音".into(), p)); }
+            for (i, p) in scene.char_paths.iter().enumerate() {
+                if let Some(p) = p { refs.push((format!("角色[{}]", i), p)); }
+            }
+            for (kind, path) in refs {
+                // 压缩包内的立绘只检查压缩包本身
+                let file = split_zip_path(path).map_or(path.as_str(), |(archive, _)| archive);
+                if !self.resolve_path(file).exists() {
+                    missing.push(MissingResource { scene_idx, kind, path: path.clone() });
+                }
+            }
+        }
+        missing
+    }
+
+    /// 打字机每推进一步调用一次（`prev` 为推进前已显示的字符数）
+    /// 逐字时按间隔播放音效；按词/整段时每步播一次；全是标点空白的一步不播
+    fn play_type_sound(&self, prev: usize) {
+        let cfg = &self.type_sound;
+        if !cfg.enabled || (self.type_unit == TypeUnit::Char && self.visible_count % cfg.every.max(1) != 0) {
+            return;
+        }
+        let step = self.target_chars.get(prev..self.visible_count).unwrap_or(&[]);
+        if let Some(mgr) = &self.audio_manager {
+            if step.iter().any(|&c| !is_typing_pause(c)) {
+                mgr.play_blip(cfg.volume);
+            }
+        }
+    }
+
+    /// 读取并播放播放列表中的指定曲目
+    fn play_playlist_track(&mut self, idx: usize) {
+        let path = match self.bgm_playlist.tracks.get(idx) {
+            Some(p) => p.clone(),
+            None => return,
+        };
+        self.bgm_playlist.current = idx;
+        self.bgm_playlist.loading = true;
+        self.log(format!("[音频] 播放列表: {}", path));
+        let tx_cb = self.tx.clone();
+        let file = self.resolve_path(&path);
+        thread::spawn(move || {
+            // 读取失败也回传空数据，解码失败后播放列表会跳过该曲目
+            let data = std::fs::read(&file).unwrap_or_else(|e| {
+                let _ = tx_cb.send(AppCommand::Log(format!("[错误] BGM 读取失败 {}: {}", path, e)));
+                Vec::new()
+            });
+            let _ = tx_cb.send(AppCommand::AudioReady(path, data, true));
+        });
+    }
+
+    /// 播放列表中的曲目播完后自动切到下一首
+    fn update_playlist(&mut self) {
+        let finished = self.audio_manager.as_ref().map_or(false, |m| m.bgm_finished());
+        if self.bgm_playlist.active && !self.bgm_playlist.loading && finished {
+            let next = self.bgm_playlist.next_index();
+            self.play_playlist_track(next);
+        }
+    }
+
+    /// 剧本中出现过的所有说话者（姓名, 所属），按最近出现的幕优先排列并去重
+    fn known_speakers(&self) -> Vec<(String, String)> {
+        let mut speakers: Vec<(String, String)> = Vec::new();
+        // 从当前幕往前找，再接上后面的幕，离当前幕越近越靠前
+        let (before, after) = self.scenario.scenes.split_at((self.current_scene_idx + 1).min(self.scenario.scenes.len()));
+        for scene in before.iter().rev().chain(after) {
+            let pair = (scene.speaker_name.clone(), scene.speaker_aff.clone());
+            if !pair.0.trim().is_empty() && !speakers.contains(&pair) {
+                speakers.push(pair);
+            }
+        }
+        speakers
+    }
+
+    /// 记录当前剧本快照，供之后撤销
+    fn push_undo(&mut self) {
+        self.dirty = true;
+        self.scene_thumbnails.clear(); // 幕的增删会让按索引缓存的缩略图错位
+        self.undo_stack.push(self.scenario.clone());
+        if self.undo_stack.len() > MAX_UNDO_STEPS {
+            self.undo_stack.remove(0);
+        }
+    }
+
+    /// 撤销最近一次剧本结构修改（增删幕、合并剧本）
+    fn undo(&mut self) {
+        match self.undo_stack.pop() {
+            Some(scenario) => {
+                self.scenario = scenario;
+                self.scene_thumbnails.clear();
+                self.dirty = true;
+                self.current_scene_idx = self.current_scene_idx.min(self.scenario.scenes.len() - 1);
+                self.sync_scene_to_ui();
+                self.visible_count = self.target_chars.len();
+                self.log("[系统] 已撤销".into());
+            }
+            None => self.log("[系统] 没有可撤销的操作".into()),
+        }
+    }
+
+    /// 把另一个剧本的所有幕插入到 `insert_at` 处，返回插入的幕数
+    /// 导入剧本的相对路径基于它自己的目录，这里改写成对当前剧本依然有效的路径；
+    /// 分支选项的目标幕索引随插入位置平移，两边的跳转关系都保持不变
+    fn merge_scenario(&mut self, other: Scenario, other_dir: Option<&std::path::Path>, insert_at: usize) -> usize {
+        let insert_at = insert_at.min(self.scenario.scenes.len());
+        let count = other.scenes.len();
+        let base_dir = self.scenario_base_dir.clone();
+        let rebase = |path: &mut String| {
+            let (dir, p) = match other_dir {
+                Some(dir) if std::path::Path::new(path.as_str()).is_relative() => (dir, std::path::Path::new(path.as_str())),
+                _ => return,
+            };
+            let abs = dir.join(p);
+            // 能表示为当前剧本目录下的相对路径时保持相对，否则使用绝对路径
+            let rebased = base_dir.as_ref()
+                .and_then(|base| abs.strip_prefix(base).ok().map(|r| r.to_path_buf()))
+                .unwrap_or(abs);
+            *path = rebased.display().to_string();
+        };
+        
+        // 当前剧本中指向插入点之后的选项整体后移
+        for scene in &mut self.scenario.scenes {
+            for (_, target) in &mut scene.choices {
+                if *target >= insert_at { *target += count; }
+            }
+        }
+        let mut scenes = other.scenes;
+        for scene in &mut scenes {
+            let bg_path = match &mut scene.background {
+                SceneBackground::Image(path) => path.as_mut(),
+                _ => None,
+            };
+            let scene_paths = bg_path.into_iter()
+                .chain(scene.foreground_path.iter_mut())
+                .chain(scene.bgm_path.iter_mut())
+                .chain(scene.voice_path.iter_mut())
+                .chain(scene.char_paths.iter_mut().flatten());
+            for p in scene_paths {
+                rebase(p);
+            }
+            for (_, target) in &mut scene.choices { *target += insert_at; }
+        }
+        self.scenario.scenes.splice(insert_at..insert_at, scenes);
+        count
+    }
+
+    /// 按规则表扫描当前幕的对话内容，为说话者切换匹配到的表情；没有匹配时保持当前表情
+    fn apply_expression_rules(&mut self) {
+        if !self.auto_expression {
+            return;
+        }
+        let scene = match self.scenario.scenes.get(self.current_scene_idx) {
+            Some(s) => s,
+            None => return,
+        };
+        let rule = self.expression_rules.iter()
+            .find(|r| !r.keyword.is_empty() && scene.dialogue_content.contains(&r.keyword));
+        if let (Some(rule), Some(slot)) = (rule, scene.speaker_slot) {
+            if let Some(Some(char)) = self.characters.get_mut(slot) {
+                if !char.set_expression(&rule.anim_name) {
+                    let msg = format!("[提示] 槽位 {} 没有表情动画: {}", slot, rule.anim_name);
+                    self.log(msg);
+                }
+            }
+        }
+    }
+
+    /// 让当前幕的说话者弹跳一次
+    fn trigger_speaker_bounce(&mut self) {
+        if !self.talk_bounce.enabled {
+            return;
+        }
+        let slot = self.scenario.scenes.get(self.current_scene_idx).and_then(|s| s.speaker_slot);
+        if let Some(Some(char)) = slot.and_then(|i| self.characters.get_mut(i)) {
+            char.bounce_elapsed = Some(0.0);
+        }
+    }
+
+    /// 推进到下一幕（当前幕有分支选项时等待玩家选择），最后一幕之后进入项目的下一章
+    fn advance_scene(&mut self) -> bool {
+        let has_choices = self.scenario.scenes.get(self.current_scene_idx).map_or(false, |s| !s.choices.is_empty());
+        if has_choices || self.transition.is_some() {
+            return false;
+        }
+        if self.current_scene_idx + 1 >= self.scenario.scenes.len() {
+            return self.advance_chapter();
+        }
+        self.go_to_scene(self.current_scene_idx + 1);
+        true
+    }
+
+    /// 切换到指定幕，按目标幕（或全局默认）的配置播放过渡
+    fn go_to_scene(&mut self, idx: usize) {
+        let idx = idx.min(self.scenario.scenes.len() - 1);
+        let config = self.scenario.scenes[idx].transition.unwrap_or(self.default_transition);
+        if config.kind == TransitionKind::Cut || config.duration <= 0.0 {
+            self.current_scene_idx = idx;
+            self.apply_scene();
+            return;
+        }
+        
+        let mut transition = ActiveTransition { 
+            config, 
+            elapsed: 0.0, 
+            target_scene: idx, 
+            switched: false, 
+            old_background: None,
+            from_ambient: self.current_ambient(),
+        };
+        // 交叉溶解立即切幕，旧背景保留在新背景之上逐渐淡出（纯色/渐变背景没有贴图，直接切换）
+        if config.kind == TransitionKind::Dissolve {
+            transition.old_background = self.background.clone().filter(|_| self.current_background().is_image());
+            transition.switched = true;
+            self.current_scene_idx = idx;
+            self.apply_scene();
+        }
+        self.transition = Some(transition);
+    }
+
+    /// 当前立绘环境光：过渡期间从切幕前的环境光平滑插值到目标幕
+    fn current_ambient(&self) -> Color32 {
+        let scene_ambient = |idx: usize| self.scenario.scenes.get(idx).map_or(Color32::WHITE, |s| s.ambient_tint);
+        match &self.transition {
+            Some(t) => lerp_color(t.from_ambient, scene_ambient(t.target_scene), t.progress()),
+            None => scene_ambient(self.current_scene_idx),
+        }
+    }
+
+    /// 推进切幕过渡；淡入淡出在进度过半（画面完全遮住）时才真正切幕
+    fn update_transition(&mut self, dt: f32) {
+        let (switch_to, finished) = match &mut self.transition {
+            Some(t) => {
+                t.elapsed += dt;
+                let switch_to = if !t.switched && t.elapsed >= t.config.duration / 2.0 {
+                    t.switched = true;
+                    Some(t.target_scene)
+                } else {
+                    None
+                };
+                (switch_to, t.elapsed >= t.config.duration)
+            }
+            None => return,
+        };
+        if let Some(idx) = switch_to {
+            self.current_scene_idx = idx;
+            self.apply_scene();
+        }
+        if finished {
+            self.transition = None;
+        }
+    }
+
+    /// 把多行文本按行加入批处理队列（单行输入同样走队列，保证与批处理的先后顺序）
+    fn queue_commands(&mut self, text: &str) {
+        self.command_queue.extend(text.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from));
+    }
+
+    /// 依次执行批处理队列中的命令
+    /// 角色加载是异步的，执行 load 后本帧停止，等加载完成（成功或失败）后再继续，
+    /// 这样脚本里紧随其后的 anim/skin 等命令总能作用到已加载的角色上
+    fn run_queued_commands(&mut self) {
+        while self.pending_loads == 0 {
+            let line = match self.command_queue.pop_front() {
+                Some(l) => l,
+                None => return,
+            };
+            self.parse_and_send_command(&line);
+            // load 命令发出的 RequestLoad 下一帧才会被处理并计入 pending_loads
+            let is_load = tokenize_command(&line).first().map_or(false, |c| c.eq_ignore_ascii_case("load"));
+            if is_load {
+                return;
+            }
+        }
+    }
+
+    /// 解析并发送控制台命令（先词法分析，再按命令名分派）
+    fn parse_and_send_command(&mut self, input: &str) {
+        let input_trimmed = input.trim();
+        if input_trimmed.is_empty() { return; }
+        // 注释行不当作命令：原样以灰色显示在日志中（方便看出脚本执行到哪一段），不报未知命令
+        if is_comment_line(input_trimmed) {
+            self.log(input_trimmed.to_string());
+            return;
+        }
+        self.log(format!("> {}", input_trimmed));
+        let tx = self.tx.clone();
+        
+        let tokens = tokenize_command(input_trimmed);
+        let cmd = match tokens.first() {
+            Some(c) => c.to_lowercase(),
+            None => return,
+        };
+        let args = &tokens[1..];
+        // 未加引号的路径可能被空格切开，这里把剩余参数按空格拼回
+        let join_from = |i: usize| args.get(i..).map(|rest| rest.join(" ")).filter(|s| !s.is_empty());
+
+        // 命令分派逻辑
+        match cmd.as_str() {
+            "load" => { // load [槽位] [路径] [初始动画]
+                // 路径以 .atlas 结尾的参数为止，其后为初始动画名
+                let path_end = args.iter().skip(1)
+                    .position(|a| a.to_lowercase().ends_with(".atlas"))
+                    .map_or(args.len(), |i| i + 2);
+                let path = args.get(1..path_end).map(|p| p.join(" ")).filter(|p| !p.is_empty());
+                match (args.first().and_then(|s| s.parse::<usize>().ok()), path) {
+                    (Some(idx), Some(path)) => { 
+                        let _ = tx.send(AppCommand::RequestLoad { 
+                            slot_idx: idx, 
+                            path, 
+                            initial_anim: args.get(path_end).cloned(),
+                            priority: LoadPriority::Current,
+                        }); 
+                    }
+                    _ => self.log("[用法] load [槽位] [路径] [初始动画]".into()),
+                }
+            }
+            "anim" => { // anim [槽位] [动画名] [循环]
+                match (args.first().and_then(|s| s.parse::<usize>().ok()), args.get(1)) {
+                    (Some(idx), Some(anim_name)) => {
+                        let loop_anim = args.get(2).map_or(true, |s| s.to_lowercase() == "true");
+                        let _ = tx.send(AppCommand::SetAnimation { 
+                            slot_idx: idx, 
+                            anim_name: anim_name.clone(), 
+                            loop_anim 
+                        });
+                    }
+                    _ => self.log("[用法] anim [槽位] [动画名] [循环]".into()),
+                }
+            }
+            "skin" => { // skin [槽位] [皮肤名]
+                match (args.first().and_then(|s| s.parse::<usize>().ok()), join_from(1)) {
+                    (Some(idx), Some(skin_name)) => {
+                        let _ = tx.send(AppCommand::SetSkin { slot_idx: idx, skin_name });
+                    }
+                    _ => self.log("[用法] skin [槽位] [皮肤名]".into()),
+                }
+            }
+            "attach" => { // attach [槽位] [插槽名] [附件名|none]
+                match (args.first().and_then(|s| s.parse::<usize>().ok()), args.get(1), join_from(2)) {
+                    (Some(idx), Some(slot_name), Some(attachment)) => {
+                        let attachment = Some(attachment).filter(|a| !a.eq_ignore_ascii_case("none"));
+                        let _ = tx.send(AppCommand::SetAttachment { slot_idx: idx, slot_name: slot_name.clone(), attachment });
+                    }
+                    _ => self.log("[用法] attach [槽位] [插槽名] [附件名|none]".into()),
+                }
+            }
+            "bgm" => match join_from(0) { // bgm [路径]
+                Some(path) => { let _ = tx.send(AppCommand::PlayBgm(path)); }
+                None => self.log("[用法] bgm [路径]".into()),
+            },
+            "se" => match join_from(0) { // se [路径]
+                Some(path) => { let _ = tx.send(AppCommand::PlaySe(path)); }
+                None => self.log("[用法] se [路径]".into()),
+            },
+            "bg" => match join_from(0) { // bg [路径]
+                Some(path) => { let _ = tx.send(AppCommand::LoadBackground(path)); }
+                None => self.log("[用法] bg [路径]".into()),
+            },
+            "bgcolor" => { // bgcolor [颜色] [渐变终点颜色] [h]，bgcolor off 恢复为最近显示的背景图片
+                let colors: Vec<Color32> = args.iter().filter_map(|s| parse_hex_color(s)).collect();
+                let horizontal = args.iter().any(|s| s.eq_ignore_ascii_case("h"));
+                let background = match colors.as_slice() {
+                    _ if args.first().map_or(false, |s| s.eq_ignore_ascii_case("off")) => Some(SceneBackground::Image(self.current_bg_path.clone())),
+                    [c] => Some(SceneBackground::Solid(*c)),
+                    [start, end] => Some(SceneBackground::Gradient { start: *start, end: *end, horizontal }),
+                    _ => None,
+                };
+                match background {
+                    Some(background) => {
+                        self.log(format!("[系统] 本幕背景: {}", background.label()));
+                        self.set_scene_background(background);
+                        self.dirty = true;
+                    }
+                    None => self.log("[用法] bgcolor [#RRGGBB] [#RRGGBB] [h] 或 bgcolor off".into()),
+                }
+            }
+            "fg" => match join_from(0) { // fg [路径]（不带路径则移除前景）
+                Some(path) => { let _ = tx.send(AppCommand::LoadForeground(path)); }
+                None => { let _ = tx.send(AppCommand::ClearForeground); }
+            },
+            "playlist" => { // playlist [路径1] [路径2] ...（带空格的路径需加引号；不带参数则停止）
+                let _ = tx.send(AppCommand::SetBgmPlaylist(args.to_vec()));
+            }
+            "stop" => { // stop
+                let _ = tx.send(AppCommand::StopBgm);
+            }
+            "gif" => { // gif [槽位] [动画名] [输出路径]，帧率、尺寸与循环次数沿用导出窗口的设置
+                let idx = args.first().and_then(|s| s.parse::<usize>().ok());
+                match (idx, args.get(1), join_from(2)) {
+                    (Some(slot_idx), Some(anim), Some(path)) => {
+                        let job = GifExport { slot_idx, anim: anim.to_string(), path, ..self.gif_export.clone() };
+                        let _ = tx.send(AppCommand::ExportGif(job));
+                    }
+                    _ => self.log("[用法] gif [槽位] [动画名] [输出路径]".into()),
+                }
+            }
+            "html" => match join_from(0) { // html [输出路径]
+                Some(path) => { let _ = tx.send(AppCommand::ExportHtml(path)); }
+                None => self.log("[用法] html [输出路径]".into()),
+            },
+            "render" => { // render [输出目录] [帧率]
+                // 最后一个参数是数字时视为帧率，其余拼回目录路径
+                let fps = args.last().and_then(|s| s.parse::<f32>().ok()).filter(|_| args.len() > 1);
+                let dir_end = if fps.is_some() { args.len() - 1 } else { args.len() };
+                match args.get(..dir_end).map(|p| p.join(" ")).filter(|p| !p.is_empty()) {
+                    Some(out_dir) => {
+                        let _ = tx.send(AppCommand::StartOfflineRender { out_dir, fps: fps.unwrap_or(DEFAULT_RENDER_FPS) });
+                    }
+                    None => self.log("[用法] render [输出目录] [帧率]".into()),
+                }
+            }
+            "set" => { // set [变量名] [值]（不带参数时列出所有变量）
+                match (args.first(), join_from(1)) {
+                    (Some(key), Some(value)) => {
+                        self.log(format!("[系统] {{{}}} = {}", key, value));
+                        self.variables.insert(key.clone(), value);
+                        self.sync_scene_to_ui(); // 当前显示的对话立即使用新值
+                    }
+                    (None, _) => {
+                        let mut vars: Vec<_> = self.variables.iter().map(|(k, v)| format!("{{{}}} = {}", k, v)).collect();
+                        vars.sort();
+                        self.log(if vars.is_empty() { "[系统] 尚未定义变量".into() } else { format!("[系统] {}", vars.join(", ")) });
+                    }
+                    _ => self.log("[用法] set [变量名] [值]".into()),
+                }
+            }
+            "run" => match join_from(0) { // run [脚本文件路径]
+                Some(path) => { let _ = tx.send(AppCommand::RunScript(path)); }
+                None => self.log("[用法] run [脚本文件路径]".into()),
+            },
+            "font" => match join_from(0) { // font [字体文件路径]
+                Some(path) => { let _ = tx.send(AppCommand::LoadFont(path)); }
+                None => self.log("[用法] font [字体文件路径]".into()),
+            },
+            "emojifont" => match join_from(0) { // emojifont [字体文件路径]
+                Some(path) => { let _ = tx.send(AppCommand::LoadFallbackFont(path)); }
+                None => self.log("[用法] emojifont [字体文件路径]".into()),
+            },
+            "namebox" => { // namebox [图片路径]，不带路径则恢复默认渐变
+                let _ = tx.send(AppCommand::LoadNameBox(join_from(0)));
+            }
+            "interleave" => { // interleave [槽位A] [槽位B] [前移量] / interleave off
+                let a = args.first().and_then(|s| s.parse::<usize>().ok());
+                let b = args.get(1).and_then(|s| s.parse::<usize>().ok());
+                match (a, b) {
+                    _ if args.first().map_or(false, |s| s.eq_ignore_ascii_case("off")) => {
+                        self.interleave = None;
+                        self.log("[系统] 已关闭穿插绘制".into());
+                    }
+                    (Some(first), Some(second)) if first < self.characters.len() && second < self.characters.len() && first != second => {
+                        let bias = args.get(2).and_then(|s| s.parse::<f32>().ok()).unwrap_or(0.0).clamp(-1.0, 1.0);
+                        self.interleave = Some(InterleavePair { first, second, bias });
+                        self.log(format!("[系统] 槽位 {} 与槽位 {} 穿插绘制", first, second));
+                    }
+                    _ => self.log("[用法] interleave [槽位A] [槽位B] [前移量] 或 interleave off".into()),
+                }
+            }
+            "chapter" => match args.first().and_then(|s| s.parse::<usize>().ok()) { // chapter [章节序号]（从 1 开始）
+                Some(n) if n >= 1 && self.chapter_path(n - 1).is_some() => {
+                    if let Err(e) = self.open_chapter(n - 1) {
+                        self.log(format!("[错误] 第 {} 章加载失败: {}", n, e));
+                    }
+                }
+                _ => {
+                    let count = self.project.as_ref().map_or(0, |p| p.chapters.len());
+                    self.log(format!("[用法] chapter [1~{}]（需先打开项目）", count));
+                }
+            },
+            "filter" => { // filter [none|gray|sepia|invert]，记录到当前幕
+                let filter = match args.first().map(|s| s.to_ascii_lowercase()).as_deref() {
+                    Some("none") | Some("off") => Some(ColorFilter::None),
+                    Some("gray") | Some("grey") | Some("grayscale") => Some(ColorFilter::Grayscale),
+                    Some("sepia") => Some(ColorFilter::Sepia),
+                    Some("invert") => Some(ColorFilter::Invert),
+                    _ => None,
+                };
+                match filter {
+                    Some(filter) => {
+                        self.scenario.scenes[self.current_scene_idx].color_filter = filter;
+                        self.dirty = true;
+                        self.log(format!("[系统] 本幕滤镜: {}", filter.label()));
+                    }
+                    None => self.log("[用法] filter [none|gray|sepia|invert]".into()),
+                }
+            }
+            "face" => { // face [槽位A] [槽位B] / face off
+                let a = args.first().and_then(|s| s.parse::<usize>().ok());
+                let b = args.get(1).and_then(|s| s.parse::<usize>().ok());
+                match (a, b) {
+                    _ if args.first().map_or(false, |s| s.eq_ignore_ascii_case("off")) => {
+                        self.facing_pair = None;
+                        self.log("[系统] 已关闭对话朝向".into());
+                    }
+                    (Some(a), Some(b)) if a < self.characters.len() && b < self.characters.len() && a != b => {
+                        self.facing_pair = Some((a, b));
+                        self.log(format!("[系统] 槽位 {} 与槽位 {} 面对面", a, b));
+                    }
+                    _ => self.log("[用法] face [槽位A] [槽位B] 或 face off".into()),
+                }
+            }
+            "stagger" => { // stagger [每人间隔秒数]
+                let delay_per_char = args.first().and_then(|s| s.parse::<f32>().ok()).unwrap_or(DEFAULT_STAGGER_DELAY);
+                let _ = tx.send(AppCommand::StaggerEnter { delay_per_char });
+            }
+            "fade" => { // fade [槽位] [目标透明度] [时长]
+                let idx = args.first().and_then(|s| s.parse::<usize>().ok());
+                let target = args.get(1).and_then(|s| s.parse::<f32>().ok());
+                match (idx, target) {
+                    (Some(slot_idx), Some(target_opacity)) => {
+                        let duration = args.get(2).and_then(|s| s.parse::<f32>().ok()).unwrap_or(0.5);
+                        let _ = tx.send(AppCommand::FadeCharacter { slot_idx, target_opacity, duration });
+                    }
+                    _ => self.log("[用法] fade [槽位] [目标透明度] [时长]".into()),
+                }
+            }
+            "bone" => { // bone [槽位] [骨骼名] [角度|reset]
+                let idx = args.first().and_then(|s| s.parse::<usize>().ok());
+                match (idx, args.get(1), args.get(2)) {
+                    (Some(slot_idx), Some(bone), Some(value)) if value.eq_ignore_ascii_case("reset") => {
+                        let _ = tx.send(AppCommand::SetBoneRotation { slot_idx, bone: bone.to_string(), degrees: None });
+                    }
+                    (Some(slot_idx), Some(bone), Some(value)) if value.parse::<f32>().is_ok() => {
+                        let degrees = value.parse::<f32>().ok();
+                        let _ = tx.send(AppCommand::SetBoneRotation { slot_idx, bone: bone.to_string(), degrees });
+                    }
+                    _ => self.log("[用法] bone [槽位] [骨骼名] [角度|reset]".into()),
+                }
+            }
+            "pause" => { // pause
+                let _ = tx.send(AppCommand::SetPaused(true));
+            }
+            "resume" => { // resume
+                let _ = tx.send(AppCommand::SetPaused(false));
+            }
+            "talk" => { // talk [姓名]|[所属]|[内容]|[说话者槽位]（内容原样保留，不做分词）
+                let raw = input_trimmed.split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim_start());
+                let p: Vec<&str> = raw.split('|').collect();
+                let speaker_slot = p.get(3).and_then(|s| s.trim().parse::<usize>().ok());
+                if p.len() == 3 || (p.len() == 4 && speaker_slot.is_some()) {
+                    let _ = tx.send(AppCommand::Dialogue { 
+                        name: p[0].into(), 
+                        affiliation: p[1].into(), 
+                        content: p[2].into(),
+                        speaker_slot
+                    });
+                } else {
+                    self.log("[用法] talk [姓名]|[所属]|[内容]|[说话者槽位]".into());
+                }
+            }
+            _ => self.log(format!("[错误] 未知命令: {}", cmd)),
+        }
+    }
+
+    /// 控制台补全候选：返回（补全后的完整输入, 提示文本）
+    /// 输入命令名时按前缀匹配注册表；`anim`/`skin` 的第三个参数补全为该槽位角色的动画名/皮肤名
+    fn command_completions(&self, input: &str) -> Vec<(String, String)> {
+        let input = input.trim_start();
+        let (cmd, rest) = match input.split_once(' ') {
+            Some((cmd, rest)) => (cmd.to_lowercase(), rest),
+            None => {
+                let prefix = input.to_lowercase();
+                if prefix.is_empty() {
+                    return Vec::new();
+                }
+                return CONSOLE_COMMANDS.iter()
+                    .filter(|(name, _)| name.starts_with(&prefix))
+                    .map(|(name, usage)| (format!("{} ", name), format!("{} {}", name, usage)))
+                    .collect();
+            }
+        };
+        // 已输入完命令名：默认只提示参数格式（补全结果即原输入）
+        let usage = match CONSOLE_COMMANDS.iter().find(|(name, _)| *name == cmd) {
+            Some((name, usage)) => vec![(input.to_string(), format!("{} {}", name, usage))],
+            None => return Vec::new(),
+        };
+        if cmd != "anim" && cmd != "skin" {
+            return usage;
+        }
+        // 第二个参数（槽位）未输入完时不补全名称
+        let (slot, partial) = match rest.split_once(' ') {
+            Some((slot, partial)) if !partial.contains(' ') => (slot, partial),
+            _ => return usage,
+        };
+        let names = match slot.parse::<usize>().ok().and_then(|i| self.characters.get(i)) {
+            Some(Some(char)) if cmd == "anim" => char.get_anim_names(),
+            Some(Some(char)) => char.get_skin_names(),
+            _ => return usage,
+        };
+        let lower = partial.to_lowercase();
+        names.into_iter()
+            .filter(|n| n.to_lowercase().starts_with(&lower))
+            .map(|n| (format!("{} {} {}", cmd, slot, n), n))
+            .collect()
+    }
+
+    /// 处理异步事件（从其他线程接收）
+    fn handle_async_events(&mut self, ctx: &egui::Context) {
+        while let Ok(cmd) = self.rx.try_recv() {
+            // 播放流程发出的命令拆包后照常处理，只是不标记未保存（否则仅仅观看剧本也会变成"已修改"）
+            let (cmd, from_playback) = match cmd {
+                AppCommand::Playback(inner) => (*inner, true),
+                cmd => (cmd, false),
+            };
+            // 槽位越界（如手打的 load 99 x）：记录后跳过，避免后面按槽位下标访问时 panic
+            if let Some(idx) = cmd.slot_index().filter(|&i| i >= self.characters.len()) {
+                self.log(format!("[错误] 槽位 {} 超出范围（0~{}），命令已忽略", idx, self.characters.len() - 1));
+                continue;
+            }
+            if !from_playback && cmd.modifies_scenario() {
+                self.dirty = true;
+            }
+            match cmd {
+                // 更新对话内容
+                AppCommand::Dialogue { name, affiliation, content, speaker_slot } => {
+                    let scene = &mut self.scenario.scenes[self.current_scene_idx];
+                    scene.speaker_name = name; 
+                    scene.speaker_aff = affiliation; 
+                    scene.dialogue_content = content;
+                    if speaker_slot.is_some() {
+                        scene.speaker_slot = speaker_slot;
+                    }
+                    self.sync_scene_to_ui(); 
+                    self.visible_count = 0; // 触发打字机效果
+                    self.trigger_speaker_bounce();
+                    self.apply_expression_rules();
+                }
+                // 日志记录
+                AppCommand::Log(msg) => self.log(msg),
+
+                // 读取外部字体文件
+                AppCommand::LoadFont(path) => {
+                    let tx_cb = self.tx.clone();
+                    thread::spawn(move || {
+                        match read_font_file(&path) {
+                            Ok((name, data)) => { let _ = tx_cb.send(AppCommand::FontReady(name, data)); }
+                            Err(e) => { let _ = tx_cb.send(AppCommand::Log(format!("[错误] 字体读取失败: {}", e))); }
+                        }
+                    });
+                }
+
+                // 读取 emoji/符号回退字体
+                AppCommand::LoadFallbackFont(path) => {
+                    let tx_cb = self.tx.clone();
+                    thread::spawn(move || {
+                        match read_font_file(&path) {
+                            Ok((name, data)) => { let _ = tx_cb.send(AppCommand::FallbackFontReady(name, data)); }
+                            Err(e) => { let _ = tx_cb.send(AppCommand::Log(format!("[错误] 回退字体读取失败: {}", e))); }
+                        }
+                    });
+                }
+
+                // 回退字体插在每个字体族的内嵌字体之后：主字体缺字形时 egui 依次向后查找
+                AppCommand::FallbackFontReady(name, data) => {
+                    self.font_defs.font_data.insert(name.clone(), FontData::from_owned(data));
+                    for fonts in self.font_defs.families.values_mut() {
+                        if !fonts.contains(&name) {
+                            let at = fonts.iter().position(|f| f == "my_font").map_or(fonts.len(), |i| i + 1);
+                            fonts.insert(at, name.clone());
+                        }
+                    }
+                    ctx.set_fonts(self.font_defs.clone());
+                    self.log(format!("[系统] 已注册回退字体: {}", name));
+                }
+
+                // 注册外部字体为独立字体族，缺字时依次回退到内嵌字体、回退字体与 egui 自带的 emoji 字体
+                AppCommand::FontReady(name, data) => {
+                    self.font_defs.font_data.insert(name.clone(), FontData::from_owned(data));
+                    let fallback = self.font_defs.families.get(&FontFamily::Proportional).cloned().unwrap_or_default();
+                    self.font_defs.families.insert(
+                        FontFamily::Name(name.clone().into()), 
+                        std::iter::once(name.clone()).chain(fallback.into_iter().filter(|f| *f != name)).collect()
+                    );
+                    ctx.set_fonts(self.font_defs.clone());
+                    if !self.custom_fonts.contains(&name) {
+                        self.custom_fonts.push(name.clone());
+                    }
+                    self.log(format!("[系统] 已注册字体: {}", name));
+                }
+
+                // 读取打字机音效
+                AppCommand::LoadTypeSound(path) => {
+                    self.type_sound.path = Some(path.clone());
+                    let tx_cb = self.tx.clone();
+                    thread::spawn(move || {
+                        match std::fs::read(&path) {
+                            Ok(d) => { let _ = tx_cb.send(AppCommand::TypeSoundReady(d)); }
+                            Err(e) => { let _ = tx_cb.send(AppCommand::Log(format!("[错误] 打字机音效读取失败: {}", e))); }
+                        }
+                    });
+                }
+
+                // 打字机音效数据就绪
+                AppCommand::TypeSoundReady(data) => {
+                    if let Some(mgr) = &mut self.audio_manager {
+                        if let Err(e) = mgr.set_blip(data) {
+                            self.log(format!("[错误] 打字机音效解码失败: {}", e));
+                        }
+                    }
+                }
+
+                // 读取命令脚本
+                AppCommand::RunScript(path) => {
+                    let tx_cb = self.tx.clone();
+                    thread::spawn(move || {
+                        match std::fs::read_to_string(&path) {
+                            Ok(text) => { let _ = tx_cb.send(AppCommand::ScriptReady(text)); }
+                            Err(e) => { let _ = tx_cb.send(AppCommand::Log(format!("[错误] 脚本读取失败: {}", e))); }
+                        }
+                    });
+                }
+
+                // 脚本读取完成：加入批处理队列
+                AppCommand::ScriptReady(text) => {
+                    self.log(format!("[系统] 开始执行脚本（{} 行）", text.lines().count()));
+                    self.queue_commands(&text);
+                }
+
+                // 开始离线渲染
+                AppCommand::StartOfflineRender { out_dir, fps } => self.start_offline_render(out_dir, fps),
+                AppCommand::ExportGif(job) => self.start_gif_export(job),
+                AppCommand::ExportHtml(path) => self.start_html_export(path),
+
+                // 暂停/恢复全部动画
+                AppCommand::SetPaused(paused) => {
+                    self.animation_paused = paused;
+                    self.log(if paused { "[系统] 动画已暂停".into() } else { "[系统] 动画已恢复".into() });
+                }
+                
+                // 异步加载角色资源
+                AppCommand::RequestLoad { slot_idx, path, initial_anim, priority } => {
+                    self.log(format!("[解析] {}", path));
+
+                    // 记录到当前幕；换了新资源时旧的动画/皮肤记录不再适用
+                    if slot_idx < self.slot_paths.len() {
+                        let scene = &mut self.scenario.scenes[self.current_scene_idx];
+                        if scene.char_paths[slot_idx].as_deref() != Some(path.as_str()) {
+                            scene.char_paths[slot_idx] = Some(path.clone());
+                            scene.char_anims[slot_idx] = None;
+                            scene.char_skins[slot_idx] = None;
+                            scene.char_attachments[slot_idx].clear();
+                        }
+                        if initial_anim.is_some() {
+                            scene.char_anims[slot_idx] = initial_anim.clone();
+                        }
+                        self.slot_paths[slot_idx] = Some(path.clone());
+                    }
+                    
+                    // 新的请求取代该槽位上尚未完成的加载
+                    if self.cancel_load(slot_idx) {
+                        self.log(format!("[系统] 槽位 {} 上一次加载已取消", slot_idx));
+                    }
+
+                    // 缓存中已有该资源时直接复用，跳过读取与解析
+                    match self.resource_cache.get(&path) {
+                        Some((skeleton_data, pages)) if slot_idx < self.characters.len() => {
+                            let (obj, _) = SpineObject::from_skeleton_data(skeleton_data, initial_anim.as_deref());
+                            self.install_character(ctx, slot_idx, obj, pages);
+                        }
+                        _ => self.spawn_character_load(slot_idx, path, initial_anim, priority),
+                    }
+                }
+
+                // 预加载完成：放入缓存，切到该幕时直接复用（期间已被正式加载的以缓存中已有的为准）
+                AppCommand::PreloadReady(path, obj, pages, skel_bytes) => {
+                    self.preloads.retain(|(p, _)| *p != path);
+                    if !self.resource_cache.entries.contains_key(&path) {
+                        let pages = pages.into_iter().map(|(name, img)| (name, Arc::new(img))).collect();
+                        self.resource_cache.insert(path.clone(), obj.skeleton_data.clone(), pages, skel_bytes);
+                        self.log(format!("[缓存] 已预加载 {}", path));
+                        self.trim_resource_cache();
+                    }
+                }
+
+                // 角色加载进度（加载已结束时忽略迟到的进度消息）
+                AppCommand::LoadProgress(idx, ratio, stage) => {
+                    if let Some(Some(progress)) = self.load_progress.get_mut(idx) {
+                        *progress = (ratio, stage);
+                    }
+                }
+
+                // 压缩包内有多个立绘：本次加载结束，弹出选择窗口
+                AppCommand::ChooseZipAtlas(choice) => {
+                    if !self.finish_load(choice.slot_idx, choice.load_id) {
+                        continue;
+                    }
+                    self.hot_reload_pending[choice.slot_idx] = false;
+                    self.log(format!("[解析] {} 内有 {} 个立绘，请选择要加载的一个", choice.archive, choice.entries.len()));
+                    self.zip_choice = Some(choice);
+                }
+
+                // 角色加载失败
+                AppCommand::LoadFailed(idx, load_id, e) => {
+                    if !self.finish_load(idx, load_id) {
+                        continue;
+                    }
+                    if let Some(pending) = self.hot_reload_pending.get_mut(idx) {
+                        *pending = false; // 失败时保留旧角色，文件再次修改时重试
+                    }
+                    self.log(format!("[错误] {}", e));
+                }
+                
+                // 角色加载成功回调
+                AppCommand::LoadSuccess(idx, load_id, path, obj, pages, _anims, skel_bytes) => {
+                    if !self.finish_load(idx, load_id) {
+                        continue;
+                    }
+                    if idx < self.characters.len() {
+                        let pages: Vec<(String, Arc<egui::ColorImage>)> = pages.into_iter().map(|(name, img)| (name, Arc::new(img))).collect();
+                        self.resource_cache.insert(path, obj.skeleton_data.clone(), pages.clone(), skel_bytes);
+                        self.install_character(ctx, idx, *obj, pages);
+                    }
+                }
+                
+                // 移除角色：只释放本槽位持有的引用，其他槽位共用的骨架数据与纹理不受影响
+                AppCommand::RemoveCharacter(idx) => { 
+                    self.cancel_load(idx);
+                    self.characters[idx] = None; 
+                    self.trim_resource_cache();
+                    self.thumbnails[idx] = None;
+                    self.slot_mtimes[idx] = None;
+                    self.apply_layout(self.layout_preset);
+                    self.slot_paths[idx] = None;
+                    let scene = &mut self.scenario.scenes[self.current_scene_idx];
+                    scene.char_paths[idx] = None;
+                    scene.char_anims[idx] = None;
+                    scene.char_skins[idx] = None;
+                    scene.char_attachments[idx].clear();
+                }
+                
+                // 加载背景图片
+                AppCommand::LoadBackground(path) => {
+                    let tx_cb = self.tx.clone();
+                    let file = self.resolve_path(&path);
+                    let filter = self.color_filter;
+                    thread::spawn(move || {
+                        if let Ok(img) = image::open(&file) {
+                            let (image, raster) = prepare_backdrop(&img, filter);
+                            let _ = tx_cb.send(AppCommand::LoadBackgroundSuccess(image, raster, filter));
+                        }
+                    });
+                    // 指定了图片即切回图片背景
+                    self.scenario.scenes[self.current_scene_idx].background = SceneBackground::Image(Some(path.clone()));
+                    self.current_bg_path = Some(path);
+                }
+                
+                // 背景加载成功回调
+                // 读取期间滤镜已变化时丢弃，以按新滤镜重新发起的那次为准
+                AppCommand::LoadBackgroundSuccess(image, raster, filter) => {
+                    if filter != self.color_filter {
+                        continue;
+                    }
+                    self.background = Some(ctx.load_texture("bg", image, egui::TextureOptions::LINEAR));
+                    self.background_image = Some(raster);
+                }
+
+                // 加载前景图片（流程与背景相同）
+                AppCommand::LoadForeground(path) => {
+                    let tx_cb = self.tx.clone();
+                    let file = self.resolve_path(&path);
+                    let filter = self.color_filter;
+                    thread::spawn(move || {
+                        match image::open(&file) {
+                            Ok(img) => {
+                                let (image, raster) = prepare_backdrop(&img, filter);
+                                let _ = tx_cb.send(AppCommand::LoadForegroundSuccess(image, raster, filter));
+                            }
+                            Err(e) => { let _ = tx_cb.send(AppCommand::Log(format!("[错误] 前景加载失败: {}", e))); }
+                        }
+                    });
+                    self.scenario.scenes[self.current_scene_idx].foreground_path = Some(path.clone());
+                    self.current_fg_path = Some(path);
+                }
+
+                // 前景加载成功回调
+                AppCommand::LoadForegroundSuccess(image, raster, filter) => {
+                    if filter != self.color_filter {
+                        continue;
+                    }
+                    self.foreground = Some(ctx.load_texture("fg", image, egui::TextureOptions::LINEAR));
+                    self.foreground_image = Some(raster);
+                }
+
+                // 后台处理好的立绘贴图（之后又发起了重新上传时丢弃，以最新的为准）
+                AppCommand::CharacterPagesReady(request, filter, pages) => {
+                    if request != self.texture_request {
+                        continue;
+                    }
+                    let options = texture_options(self.texture_filter);
+                    let mut uploaded = Vec::new();
+                    for char in self.characters.iter_mut().flatten() {
+                        char.replace_textures(ctx, options, filter, &pages, &mut uploaded);
+                    }
+                }
+
+                // 后台光栅化的缩略图（槽位已清空或幕已删除时丢弃）
+                AppCommand::ThumbnailReady(target, img) => match target {
+                    ThumbnailTarget::Slot(idx) => {
+                        if let Some(Some(_)) = self.characters.get(idx) {
+                            self.thumbnails[idx] = Some(ctx.load_texture(format!("thumb_{}", idx), img, egui::TextureOptions::LINEAR));
+                        }
+                    }
+                    ThumbnailTarget::Scene(idx) => {
+                        if idx < self.scenario.scenes.len() {
+                            let tex = ctx.load_texture(format!("storyboard_{}", idx), img, egui::TextureOptions::LINEAR);
+                            self.scene_thumbnails.insert(idx, SceneThumbnail::Full(tex));
+                        }
+                    }
+                    // 占位图不覆盖已截取的完整画面
+                    ThumbnailTarget::SceneBackdrop(idx, background) => {
+                        let pending = matches!(self.scene_thumbnails.get(&idx), Some(SceneThumbnail::Pending));
+                        if pending && self.scenario.scenes.get(idx).map(|s| &s.background) == Some(&background) {
+                            let tex = ctx.load_texture(format!("storyboard_{}", idx), img, egui::TextureOptions::LINEAR);
+                            self.scene_thumbnails.insert(idx, SceneThumbnail::Backdrop(tex));
+                        }
+                    }
+                },
+
+                // 加载名字框贴图（不属于剧本，不记录到幕）
+                AppCommand::LoadNameBox(Some(path)) => {
+                    let tx_cb = self.tx.clone();
+                    thread::spawn(move || {
+                        match image::open(&path) {
+                            Ok(img) => {
+                                let c_img = premultiplied_image([img.width() as _, img.height() as _], img.to_rgba8().as_raw());
+                                let _ = tx_cb.send(AppCommand::LoadNameBoxSuccess(path, c_img));
+                            }
+                            Err(e) => { let _ = tx_cb.send(AppCommand::Log(format!("[错误] 名字框贴图加载失败: {}", e))); }
+                        }
+                    });
+                }
+                AppCommand::LoadNameBox(None) => {
+                    self.name_box = None;
+                    self.name_box_path = None;
+                    self.log("[系统] 名字框已恢复默认渐变".into());
+                }
+
+                // 名字框贴图加载成功回调
+                AppCommand::LoadNameBoxSuccess(path, c_img) => {
+                    self.name_box = Some(ctx.load_texture("name_box", c_img, egui::TextureOptions::LINEAR));
+                    self.log(format!("[系统] 名字框贴图: {}", path));
+                    self.name_box_path = Some(path);
+                }
+
+                // 移除前景
+                AppCommand::ClearForeground => {
+                    self.foreground = None;
+                    self.foreground_image = None;
+                    self.scenario.scenes[self.current_scene_idx].foreground_path = None;
+                    self.current_fg_path = None;
+                }
+                
+                // 设置动画
+                AppCommand::SetAnimation { slot_idx, anim_name, loop_anim } => {
+                     if let Some(Some(char)) = self.characters.get_mut(slot_idx) {
+                         if char.set_animation_by_name(&anim_name, loop_anim) {
+                             self.scenario.scenes[self.current_scene_idx].char_anims[slot_idx] = Some(anim_name);
+                         }
+                     }
+                }
+
+                // 角色淡入淡出
+                AppCommand::FadeCharacter { slot_idx, target_opacity, duration } => {
+                    if let Some(Some(char)) = self.characters.get_mut(slot_idx) {
+                        char.start_fade(target_opacity, duration);
+                    }
+                }
+
+                // 在场角色按从左到右的站位依次滑入
+                AppCommand::StaggerEnter { delay_per_char } => {
+                    let mut present: Vec<&mut SpineObject> = self.characters.iter_mut().flatten().collect();
+                    present.sort_by(|a, b| a.layout_x.total_cmp(&b.layout_x));
+                    for (k, char) in present.into_iter().enumerate() {
+                        char.start_enter(k as f32 * delay_per_char.max(0.0));
+                    }
+                }
+
+                // 手动设置骨骼旋转
+                AppCommand::SetBoneRotation { slot_idx, bone, degrees } => {
+                    if let Some(Some(char)) = self.characters.get_mut(slot_idx) {
+                        match degrees {
+                            Some(deg) => {
+                                if !char.set_bone_rotation(&bone, deg) {
+                                    self.log(format!("[错误] 槽位 {} 中没有骨骼: {}", slot_idx, bone));
+                                }
+                            }
+                            None => char.clear_bone_override(&bone),
+                        }
+                    }
+                }
+
+                // 定位动画时间（时间轴拖拽）
+                AppCommand::SeekAnimation { slot_idx, time } => {
+                    if let Some(Some(char)) = self.characters.get_mut(slot_idx) {
+                        char.seek(time);
+                    }
+                }
+
+                // 切换皮肤（记录到当前幕以便回放）
+                AppCommand::SetSkin { slot_idx, skin_name } => {
+                    if let Some(Some(char)) = self.characters.get_mut(slot_idx) {
+                        if char.set_skin(&skin_name) {
+                            self.scenario.scenes[self.current_scene_idx].char_skins[slot_idx] = Some(skin_name);
+                        } else {
+                            self.log(format!("[错误] 找不到皮肤: {}", skin_name));
+                        }
+                    }
+                }
+                
+                // 设置插槽附件（记录到当前幕以便回放）
+                AppCommand::SetAttachment { slot_idx, slot_name, attachment } => {
+                    if let Some(Some(char)) = self.characters.get_mut(slot_idx) {
+                        if char.set_slot_attachment(&slot_name, attachment.as_deref()) {
+                            let overrides = &mut self.scenario.scenes[self.current_scene_idx].char_attachments[slot_idx];
+                            overrides.retain(|(slot, _)| *slot != slot_name);
+                            overrides.push((slot_name, attachment));
+                        } else {
+                            self.log(format!("[错误] 插槽 {} 上找不到附件 {}", slot_name, attachment.as_deref().unwrap_or("")));
+                        }
+                    }
+                }
+                
+                // 播放BGM（单独指定的BGM会接管播放列表）
+                AppCommand::PlayBgm(path) => {
+                    self.bgm_playlist.active = false;
+                    let tx_cb = self.tx.clone();
+                    let path_clone = path.clone();
+                    let file = self.resolve_path(&path);
+                    thread::spawn(move || { 
+                        match std::fs::read(&file) {
+                            Ok(d) => { let _ = tx_cb.send(AppCommand::AudioReady(path_clone, d, true)); }
+                            Err(e) => { let _ = tx_cb.send(AppCommand::Log(format!("[错误] BGM 读取失败 {}: {}", path_clone, e))); }
+                        }
+                    });
+                    self.scenario.scenes[self.current_scene_idx].bgm_path = Some(path.clone());
+                    self.current_bgm_path = Some(path);
+                }
+
+                // 播放音效
+                AppCommand::PlaySe(path) => {
+                    let tx_cb = self.tx.clone();
+                    let path_clone = path.clone();
+                    let file = self.resolve_path(&path);
+                    thread::spawn(move || { 
+                        match std::fs::read(&file) {
+                            Ok(d) => { let _ = tx_cb.send(AppCommand::AudioReady(path_clone, d, false)); }
+                            Err(e) => { let _ = tx_cb.send(AppCommand::Log(format!("[错误] 音效读取失败 {}: {}", path_clone, e))); }
+                        }
+                    });
+                }
+                
+                // 读取当前幕语音
+                AppCommand::PlayVoice(path) => {
+                    self.voice_pending = true;
+                    self.voice_request += 1;
+                    let request = self.voice_request;
+                    let tx_cb = self.tx.clone();
+                    let file = self.resolve_path(&path);
+                    thread::spawn(move || { 
+                        let data = std::fs::read(&file).unwrap_or_else(|e| {
+                            let _ = tx_cb.send(AppCommand::Log(format!("[错误] 语音读取失败 {}: {}", path, e)));
+                            Vec::new()
+                        });
+                        let _ = tx_cb.send(AppCommand::VoiceReady(request, data)); 
+                    });
+                }
+
+                // 语音数据就绪：播放并记录时长（失败时按无语音处理，自动播放不会卡住）
+                // 读取期间已切幕或又发起了新的请求时丢弃，避免上一句台词在新的一幕里响起
+                AppCommand::VoiceReady(request, data) => {
+                    if request != self.voice_request {
+                        continue;
+                    }
+                    self.voice_pending = false;
+                    if let Some(mgr) = &self.audio_manager {
+                        match mgr.play_voice(data) {
+                            Ok(duration) => self.voice_duration = duration,
+                            Err(e) => {
+                                self.voice_duration = None;
+                                self.log(format!("[错误] 语音解码失败: {}", e));
+                            }
+                        }
+                    }
+                }
+                
+                // 音频数据就绪
+                AppCommand::AudioReady(path, data, is_bgm) => {
+                    if let Some(mgr) = &self.audio_manager { 
+                        let name = std::path::Path::new(&path).file_name().map_or(path.clone(), |n| n.to_string_lossy().into_owned());
+                        if is_bgm { 
+                            // 单独播放的BGM使用当前幕设置的循环起点
+                            let loop_start = self.scenario.scenes.get(self.current_scene_idx)
+                                .filter(|s| s.bgm_path == self.current_bgm_path)
+                                .and_then(|s| s.bgm_loop_start);
+                            let result = mgr.play_bgm(data, !self.bgm_playlist.active, loop_start); 
+                            let ok = result.is_ok();
+                            if let Err(e) = result {
+                                self.log(format!("[错误] 音频解码失败: {} {}", name, e));
+                            }
+                            // 播放列表：解码失败时跳到下一首，整张列表都失败则停止
+                            let list = &mut self.bgm_playlist;
+                            if list.active {
+                                list.loading = false;
+                                if ok {
+                                    list.failures = 0;
+                                } else {
+                                    list.failures += 1;
+                                    if list.failures >= list.tracks.len() {
+                                        list.active = false;
+                                        self.log("[错误] 播放列表中没有可播放的曲目".into());
+                                    }
+                                }
+                            }
+                        } else if let Err(e) = mgr.play_se(data) { 
+                            self.log(format!("[错误] 音频解码失败: {} {}", name, e));
+                        } 
+                    }
+                }
+
+                // 设置BGM播放列表
+                AppCommand::SetBgmPlaylist(tracks) => {
+                    self.bgm_playlist.tracks = tracks;
+                    self.bgm_playlist.failures = 0;
+                    self.bgm_playlist.active = !self.bgm_playlist.tracks.is_empty();
+                    if self.bgm_playlist.active {
+                        let first = if self.bgm_playlist.mode == PlaylistMode::Shuffle { self.bgm_playlist.next_index() } else { 0 };
+                        self.play_playlist_track(first);
+                    } else if let Some(mgr) = &self.audio_manager {
+                        mgr.stop_bgm();
+                    }
+                }
+                
+                // 停止BGM
+                AppCommand::StopBgm => { 
+                    self.bgm_playlist.active = false;
+                    if let Some(mgr) = &self.audio_manager { 
+                        mgr.stop_bgm(); 
+                    } 
+                    self.scenario.scenes[self.current_scene_idx].bgm_path = None;
+                    self.current_bgm_path = None;
+                }
+                
+                _ => {}
+            }
+        }
+    }
+}
+
+/// 启动日志写入线程：按行追加到日志文件，每批写完再刷新，不阻塞 UI 线程
+/// 日志文件无法打开时（如只读目录）静默放弃写文件，控制台日志不受影响
+fn spawn_log_writer(path: &str) -> Sender<String> {
+    use std::io::Write;
+    let (tx, rx) = channel::<String>();
+    let path = path.to_string();
+    thread::spawn(move || {
+        let file = match std::fs::OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(f) => f,
+            Err(_) => return,
+        };
+        let mut writer = std::io::BufWriter::new(file);
+        while let Ok(line) = rx.recv() {
+            let _ = writeln!(writer, "{}", line);
+            // 把已排队的日志一起写完再刷新，减少系统调用
+            while let Ok(line) = rx.try_recv() {
+                let _ = writeln!(writer, "{}", line);
+            }
+            let _ = writer.flush();
+        }
+    });
+    tx
+}
+
+/// 把时间格式化为 "YYYY-MM-DD HH:MM:SS UTC"（没有引入时间库，按公历手动换算）
+fn format_timestamp(time: std::time::SystemTime) -> String {
+    let secs = time.duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs()) as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // 由 1970-01-01 起的天数换算年月日（Howard Hinnant 的 civil_from_days 算法）
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+/// 是否为控制台/脚本中的注释行（以 `#` 或 `//` 开头，调用前已去除首尾空白）
+fn is_comment_line(line: &str) -> bool {
+    line.starts_with('#') || line.starts_with("//")
+}
+
+/// 控制台命令词法分析
+/// 按空白切分参数；双引号包裹的参数保留内部空格，`\"` 表示字面引号。
+/// 其余反斜杠原样保留，因此 Windows 路径（如 `C:\My Folder\a.atlas`）不需要转义。
+fn tokenize_command(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_token = false; // 区分空引号 "" 与没有参数
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'"') => {
+                current.push('"');
+                chars.next();
+                has_token = true;
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                has_token = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_token {
+                    tokens.push(std::mem::take(&mut current));
+                    has_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_token = true;
+            }
+        }
+    }
+    // 未闭合的引号宽松处理：剩余内容作为最后一个参数
+    if has_token {
+        tokens.push(current);
+    }
+    tokens
+}
+
+// ============================================================================
+// 主应用循环实现
+// ============================================================================
+
+impl eframe::App for AefrApp {
+    /// 退出（及定期自动保存）时记录窗口尺寸、面板状态与最近剧本
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        let state = PersistedState {
+            window_size: self.window_size.map(|s| [s.x, s.y]),
+            console_open: self.console_open,
+            selected_slot: self.selected_slot,
+            recent_file: self.recent_file.clone(),
+            name_box: self.name_box_path.clone().map(|path| (path, self.name_box_border)),
+            panels: self.panels,
+        };
+        eframe::set_value(storage, PERSIST_KEY, &state);
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // 处理异步事件
+        self.handle_async_events(ctx);
+        // 记录窗口尺寸（退出时持久化）
+        if let Some(rect) = ctx.input(|i| i.viewport().inner_rect) {
+            self.window_size = Some(rect.size());
+        }
+        // 播放列表自动切歌
+        self.update_playlist();
+        // 窗口标题显示剧本文件名与未保存标记
+        self.update_window_title(ctx);
+        // 有未保存修改时拦截关闭请求，改为弹出确认对话框
+        if ctx.input(|i| i.viewport().close_requested()) && self.dirty && !self.allow_close {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.show_exit_confirm = true;
+        }
+        // 执行批处理命令
+        self.run_queued_commands();
+        // 立绘文件修改后自动重新加载
+        self.poll_hot_reload(ctx.input(|i| i.stable_dt));
+        self.check_load_timeouts();
+        self.sync_color_filter(ctx);
+        // 语音/音效播放时压低 BGM（按真实时间渐变，不受暂停与离线渲染步长影响）
+        if let Some(mgr) = &mut self.audio_manager {
+            mgr.update_duck(ctx.input(|i| i.stable_dt), &self.bgm_duck);
+        }
+        // BGM 频谱（分析在 UI 线程进行，音频线程只负责转交样本）
+        if self.show_spectrum {
+            let samples = self.audio_manager.as_ref().map(|m| m.take_bgm_samples()).unwrap_or_default();
+            self.spectrum.update(samples, ctx.input(|i| i.stable_dt));
+        }
+        // 获取稳定帧时间；离线渲染时改用固定步长以保证确定性
+        let dt = match self.poll_offline_render(ctx) {
+            Some(fixed_dt) => fixed_dt,
+            None => ctx.input(|i| i.stable_dt),
+        };
+        let rendering = self.offline_render.is_some();
+
+        // P 键切换暂停（输入框聚焦时不响应，避免打字误触）
+        if !ctx.wants_keyboard_input() && ctx.input(|i| i.key_pressed(egui::Key::P)) {
+            self.animation_paused = !self.animation_paused;
+        }
+        // Ctrl+Z 撤销剧本结构修改
+        if !self.read_only && !ctx.wants_keyboard_input() && ctx.input(|i| i.modifiers.command && i.key_pressed(egui::Key::Z)) {
+            self.undo();
+        }
+        // Esc 退出只读演示模式，回到编辑界面
+        if self.read_only && ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+            self.read_only = false;
+        }
+        // 暂停时骨骼只在单帧步进时推进一小步，其余时间 dt = 0 保持当前姿势
+        let anim_dt = if !self.animation_paused {
+            dt
+        } else if std::mem::take(&mut self.step_requested) {
+            FRAME_STEP_DT
+        } else {
+            0.0
+        };
+        
+        // 快进：打开 SKIP 开关或按住 Ctrl（编辑、暂停与离线渲染时不生效）
+        let ctrl_held = !ctx.wants_keyboard_input() && ctx.input(|i| i.modifiers.ctrl);
+        let fast_forward = !rendering && !self.console_open && !self.animation_paused && (self.skip_mode || ctrl_held);
+
+        // 切幕过渡（快进时一帧内走完）
+        let transition_dt = match &self.transition {
+            Some(t) if fast_forward => t.config.duration,
+            _ => dt,
+        };
+        self.update_transition(transition_dt);
+        // 分镜视图缩略图
+        self.update_storyboard(ctx, dt);
+        let in_transition = self.transition.is_some();
+
+        // 🌟 打字机效果更新（过渡进行中暂停，快进时瞬间显示完）
+        if fast_forward && !in_transition {
+            self.visible_count = self.target_chars.len();
+        }
+        if self.show_dialogue && !self.animation_paused && !in_transition && self.visible_count < self.target_chars.len() {
+            // 一步显示多个字符（按词）时等待相应的时长，整段模式立即显示
+            let next = self.type_unit.advance(&self.target_chars, self.visible_count);
+            let wait = match self.type_unit {
+                TypeUnit::Paragraph => 0.0,
+                _ => self.type_interval() * (next - self.visible_count) as f32,
+            };
+            self.type_timer += dt;
+            if self.type_timer > wait {
+                let shown = self.visible_count;
+                self.visible_count = next; 
+                self.type_timer = 0.0; 
+                self.play_type_sound(shown);
+            }
+            self.auto_timer = 0.0;
+        }
+
+        // 🌟 自动播放：文本显示完毕后停留一段时间再推进（编辑时不推进，遇到选项时等待选择）
+        // 离线渲染总是自动推进，剧情无法继续时结束渲染
+        let auto_active = rendering || (self.is_auto_enabled && !self.console_open && !self.animation_paused);
+        if fast_forward && !in_transition {
+            // 快进：不等延时与语音，尽快推进，遇到选项或剧本结束时停下
+            self.auto_timer += dt;
+            if self.auto_timer > SKIP_ADVANCE_INTERVAL {
+                self.auto_timer = 0.0;
+                if !self.advance_dialogue() && self.transition.is_none() {
+                    self.skip_mode = false;
+                }
+            }
+        } else if auto_active && !in_transition && self.visible_count >= self.target_chars.len() {
+            self.auto_timer += dt;
+            // 本幕有语音时等语音播完再短暂停顿后推进，否则按固定延时
+            let has_voice = self.scenario.scenes.get(self.current_scene_idx).map_or(false, |s| s.voice_path.is_some());
+            if self.voice_playing() {
+                self.auto_timer = 0.0;
+            }
+            let delay = if has_voice { VOICE_ADVANCE_PAUSE } else { self.auto_delay };
+            if self.auto_timer > delay {
+                self.auto_timer = 0.0;
+                if !self.advance_dialogue() && rendering {
+                    let at_choice = self.scenario.scenes.get(self.current_scene_idx).map_or(false, |s| !s.choices.is_empty());
+                    self.stop_offline_render(if at_choice { "遇到分支选项，渲染中止" } else { "剧本播放完毕，渲染完成" });
+                }
+            }
+        }
+
+        // 🌟 幕级定时推进：与全局 AUTO 无关，进入本幕后计时到点直接进入下一幕（适合无对话的过场）
+        let scene_limit = self.scenario.scenes.get(self.current_scene_idx).and_then(|s| s.auto_advance_after);
+        let scene_timer_active = rendering || (!self.console_open && !self.animation_paused);
+        if let Some(limit) = scene_limit {
+            if scene_timer_active && !in_transition {
+                self.scene_timer += dt;
+                if self.scene_timer > limit {
+                    self.scene_timer = 0.0;
+                    self.advance_scene();
+                }
+            }
+        }
+
+        // 根据屏幕分辨率计算缩放比例
+        let screen = ctx.screen_rect();
+        let scale_factor = screen.height() / BASE_HEIGHT;
+        
+        // 触摸手势（调整选中角色的缩放与位置）
+        self.handle_touch_gestures(ctx, scale_factor);
+        // 拖放文件加载资源（演示模式下不响应）
+        if !self.read_only {
+            self.handle_dropped_files(ctx);
+        }
+        
+        // 更新角色位置（横向按排列预设的百分比，底部对齐，再叠加用户手动调整和说话弹跳）
+        let depth_mode = self.depth_mode;
+        for char in self.characters.iter_mut().flatten() {
+            let depth_scale = if depth_mode { 1.0 - (1.0 - DEPTH_MIN_SCALE) * char.depth.clamp(0.0, 1.0) } else { 1.0 };
+            char.scale = char.base_scale * scale_factor * char.user_scale * depth_scale;
+            let bounce = char.update_bounce(anim_dt, &self.talk_bounce);
+            char.update_fade(anim_dt);
+            let enter_dx = char.update_enter(anim_dt);
+            char.position = Pos2::new(
+                screen.width() * (char.layout_x + enter_dx), 
+                // 从屏幕底部开始，并按脚底偏移把不同模型对齐到同一基线
+                screen.bottom() + (30.0 * scale_factor) + char.foot_offset * char.scale
+            ) + (char.offset - Vec2::new(0.0, bounce)) * scale_factor;
+        }
+
+        // 对话朝向：位置更新后重新判断左右，角色被拖动或重新排列后仍保持面对面
+        if let Some((a, b)) = self.facing_pair {
+            self.face_each_other(a, b);
+        }
+
+        // 单人预览：选中角色忽略排列与手动调整，按初始姿势包围盒居中并缩放到视口的 90%
+        if self.solo_preview {
+            if let Some(char) = self.characters.get_mut(self.selected_slot).and_then(|c| c.as_mut()) {
+                if let Some(b) = char.setup_bounds.filter(|b| b.is_positive()) {
+                    let fit = (screen.width() / b.width()).min(screen.height() / b.height()) * CHARACTER_HEIGHT_RATIO;
+                    let flip = if char.flip_x { -1.0 } else { 1.0 };
+                    char.scale = fit;
+                    char.position = Pos2::new(
+                        screen.center().x - b.center().x * fit * flip,
+                        screen.center().y + b.center().y * fit
+                    );
+                }
+            }
+        }
+
+        // 看向鼠标（骨骼覆盖在下面的动画更新中生效）
+        self.update_look_at(ctx);
+
+        // 🌟 使用调度器并行更新所有角色的骨骼动画
+        self.scheduler.run_parallel(|| {
+            self.characters.par_iter_mut().for_each(|slot| {
+                if let Some(char) = slot { 
+                    char.update_parallel(anim_dt); 
+                }
+            });
+        });
+
+        // 主绘制区域
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none().fill(Color32::BLACK))
+            .show(ctx, |ui| {
+                let rect = ui.max_rect();
+                
+                self.draw_stage(ctx, ui, rect);
+
+                // 演示模式右上角留一个退出按钮：触屏设备没有 Esc 键
+                if self.read_only && !rendering {
+                    if ui.put(exit_button_rect(rect), egui::Button::new("✖")).on_hover_text("退出演示模式（Esc）").clicked() {
+                        self.read_only = false;
+                    }
+                }
+
+                // 离线渲染和演示模式只保留画面本身，不绘制编辑UI
+                if rendering || self.read_only {
+                    return;
+                }
+
+                // 角色加载进度条（顶部居中，每个加载中的槽位一条，右侧按钮取消加载）
+                let mut bar_y = 50.0;
+                let mut cancel_slot = None;
+                for (idx, progress) in self.load_progress.iter().enumerate() {
+                    if let Some((ratio, stage)) = progress {
+                        let bar_rect = Rect::from_center_size(Pos2::new(rect.center().x, bar_y), Vec2::new(320.0, 22.0));
+                        ui.put(bar_rect, egui::ProgressBar::new(*ratio).text(format!("槽位 {}: {} {:.0}%", idx, stage.label(), ratio * 100.0)));
+                        let cancel_rect = Rect::from_min_size(bar_rect.right_top() + Vec2::new(6.0, 0.0), Vec2::splat(22.0));
+                        if ui.put(cancel_rect, egui::Button::new("✖")).on_hover_text("取消加载").clicked() {
+                            cancel_slot = Some(idx);
+                        }
+                        bar_y += 28.0;
+                    }
+                }
+                if let Some(idx) = cancel_slot {
+                    if self.cancel_load(idx) {
+                        self.log(format!("[系统] 已取消槽位 {} 的加载", idx));
+                    }
+                }
+
+                // 文件拖入窗口时提示松手加载
+                if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+                    ui.painter().rect_filled(rect, 0.0, Color32::from_black_alpha(140));
+                    ui.painter().text(
+                        rect.center(), 
+                        egui::Align2::CENTER_CENTER, 
+                        "松开以加载（.atlas → 角色 / 图片 → 背景 / 音频 → BGM）", 
+                        egui::FontId::proportional(22.0), 
+                        Color32::WHITE
+                    );
+                }
+
+                // 控制台开关按钮
+                if ui.put(Rect::from_min_size(Pos2::new(10.0, 10.0), Vec2::new(60.0, 30.0)), 
+                    egui::Button::new("CMD")).clicked() 
+                { 
+                    self.console_open = !self.console_open; 
+                }
+
+                // 暂停状态提示
+                if self.animation_paused {
+                    ui.painter().text(
+                        Pos2::new(80.0, 25.0), 
+                        egui::Align2::LEFT_CENTER, 
+                        "PAUSED", 
+                        egui::FontId::proportional(18.0), 
+                        Color32::from_rgb(255, 215, 0)
+                    );
+                }
+                
+                // 控制台面板
+                if self.console_open { 
+                    self.show_character_tooltip(ui);
+                    draw_creator_panel(ctx, self); 
+                }
+                
+                // 设置面板
+                if self.show_settings {
+                    draw_settings_window(ctx, self);
+                }
+
+                // 未保存退出确认
+                if self.show_exit_confirm {
+                    draw_exit_confirm(ctx, self);
+                }
+
+                // 压缩包立绘选择
+                if self.zip_choice.is_some() {
+                    draw_zip_choice(ctx, self);
+                }
+            });
+
+
+        // 独立预览窗口
+        if self.show_preview_window {
+            self.show_preview_viewport(ctx);
+        }
+        
+        ctx.request_repaint(); // 请求下一帧重绘
+    }
+}
+
+// ============================================================================
+// UI 组件函数
+// ============================================================================
+
+/// GIF 导出参数
+#[derive(Clone)]
+struct GifExport {
+    slot_idx: usize, // 导出的角色槽位
+    anim: String,    // 导出的动画（渲染一个完整周期）
+    path: String,    // 输出文件
+    fps: f32,        // 帧率（GIF 帧间隔精度为 10ms）
+    size: u32,       // 长边像素
+    loops: u16,      // 循环次数（0 为无限循环）
+}
+
+impl Default for GifExport {
+    fn default() -> Self {
+        Self { slot_idx: 0, anim: String::new(), path: String::new(), fps: DEFAULT_GIF_FPS, size: DEFAULT_GIF_SIZE, loops: 0 }
+    }
+}
+
+/// 逐帧离屏渲染一个动画周期并编码为透明背景的 GIF，返回帧数
+/// 先采样所有帧求出包围盒的并集，保证每帧构图一致、动作不会出框
+fn export_gif(mut obj: SpineObject, job: &GifExport) -> Result<usize, String> {
+    use image::codecs::gif::{GifEncoder, Repeat};
+    let fps = job.fps.clamp(1.0, 50.0);
+    let duration = obj.skeleton_data.animations()
+        .find(|a| a.name() == job.anim)
+        .map(|a| a.duration())
+        .ok_or_else(|| format!("找不到动画: {}", job.anim))?;
+    let frame_count = ((duration * fps).round() as usize).max(1);
+
+    obj.position = Pos2::ZERO;
+    obj.scale = 1.0;
+    let mut bounds = Rect::NOTHING;
+    for i in 0..frame_count {
+        obj.seek(i as f32 / fps);
+        if let Some(b) = obj.screen_bounds() {
+            bounds = bounds.union(b);
+        }
+    }
+    if !bounds.is_positive() {
+        return Err("动画没有可见的附件".into());
+    }
+    let scale = job.size.max(16) as f32 / bounds.width().max(bounds.height());
+    let size = [((bounds.width() * scale).round() as usize).max(1), ((bounds.height() * scale).round() as usize).max(1)];
+
+    let file = std::fs::File::create(&job.path).map_err(|e| format!("{}: {}", job.path, e))?;
+    let mut encoder = GifEncoder::new_with_speed(std::io::BufWriter::new(file), 10);
+    encoder.set_repeat(if job.loops == 0 { Repeat::Infinite } else { Repeat::Finite(job.loops) }).map_err(|e| e.to_string())?;
+    let delay = image::Delay::from_saturating_duration(std::time::Duration::from_secs_f32(1.0 / fps));
+    for i in 0..frame_count {
+        obj.seek(i as f32 / fps);
+        let img = obj.render_region(bounds, size);
+        let rgba: Vec<u8> = img.pixels.iter().flat_map(|c| c.to_srgba_unmultiplied()).collect();
+        let buffer = image::RgbaImage::from_raw(size[0] as u32, size[1] as u32, rgba).ok_or("帧缓冲尺寸错误")?;
+        encoder.encode_frame(image::Frame::from_parts(buffer, 0, 0, delay)).map_err(|e| e.to_string())?;
+    }
+    Ok(frame_count)
+}
+
+/// 导出 HTML 时的一幕（已代入变量、背景已解析为绝对路径）
+struct HtmlScene {
+    bg: Option<std::path::PathBuf>,
+    fill: Option<String>,        // 纯色/渐变背景的 CSS
+    name: String,
+    affiliation: String,
+    segments: Vec<String>,       // 依次点击显示的对话片段
+    choices: Vec<(String, usize)>, // 分支选项（选项文本, 目标幕索引）
+}
+
+/// 标准 Base64 编码（带 = 填充），用于把图片内嵌为 data URI
+fn base64_encode(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// 把剧本写成一个自包含的 HTML 文件：背景图片以 base64 内嵌（同一张只嵌一次），纯色/渐变背景写成 CSS，
+/// 附带一个简单的 JS 播放器（点击或按空格推进对话、分支选项可点选）；立绘、音频与特效不导出
+/// 返回读取失败而被略过的背景路径
+fn export_html(scenes: &[HtmlScene], title: &str, path: &str) -> Result<Vec<String>, String> {
+    let mut images: Vec<(std::path::PathBuf, String)> = Vec::new();
+    let mut missing = Vec::new();
+    let mut scene_data = Vec::with_capacity(scenes.len());
+    for scene in scenes {
+        let bg = scene.bg.as_ref().and_then(|bg| {
+            if let Some(i) = images.iter().position(|(p, _)| p == bg) {
+                return Some(i);
+            }
+            let mime = match bg.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+                Some("jpg") | Some("jpeg") => "image/jpeg",
+                Some("webp") => "image/webp",
+                Some("gif") => "image/gif",
+                _ => "image/png",
+            };
+            match std::fs::read(bg) {
+                Ok(data) => {
+                    images.push((bg.clone(), format!("data:{};base64,{}", mime, base64_encode(&data))));
+                    Some(images.len() - 1)
+                }
+                Err(_) => {
+                    missing.push(bg.display().to_string());
+                    None
+                }
+            }
+        });
+        scene_data.push(serde_json::json!({
+            "bg": bg,
+            "fill": scene.fill,
+            "name": scene.name,
+            "aff": scene.affiliation,
+            "segments": scene.segments,
+            "choices": scene.choices.iter().map(|(text, target)| serde_json::json!({ "text": text, "target": target })).collect::<Vec<_>>(),
+        }));
+    }
+    let data = serde_json::json!({
+        "images": images.iter().map(|(_, uri)| uri).collect::<Vec<_>>(),
+        "scenes": scene_data,
+    });
+    // 内嵌在 <script> 中，"</" 需要转义以免提前结束脚本块
+    let json = serde_json::to_string(&data).map_err(|e| e.to_string())?.replace("</", "<\\/");
+    let escaped_title = title.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let html = HTML_PLAYER_TEMPLATE.replace("__AEFR_TITLE__", &escaped_title).replace("__AEFR_DATA__", &json);
+    std::fs::write(path, html).map_err(|e| e.to_string())?;
+    Ok(missing)
+}
+
+/// HTML 播放页模板：`__AEFR_TITLE__` 与 `__AEFR_DATA__`（剧本 JSON）在导出时替换
+const HTML_PLAYER_TEMPLATE: &str = r##"<!DOCTYPE html>
+<html lang="zh-CN">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>__AEFR_TITLE__</title>
+<style>
+  html, body { margin: 0; height: 100%; background: #000; overflow: hidden; font-family: sans-serif; }
+  #stage { position: absolute; inset: 0; margin: auto; aspect-ratio: 16 / 9; max-width: 100vw; max-height: 100vh;
+           background: #1a1d24 center / cover no-repeat; cursor: pointer; user-select: none; }
+  #box { position: absolute; left: 0; right: 0; bottom: 0; min-height: 28%; padding: 2.2% 5% 3%; box-sizing: border-box;
+         background: linear-gradient(to bottom, rgba(10, 15, 30, 0), rgba(10, 15, 30, 0.85) 25%, rgba(10, 15, 30, 0.95)); color: #fff; }
+  #name { font-size: 2.6vmin; font-weight: bold; }
+  #aff { margin-left: 0.8em; font-size: 1.9vmin; color: #80c8ff; }
+  #line { margin: 0.6em 0 0; border-top: 1px solid rgba(255, 255, 255, 0.35); }
+  #text { margin-top: 0.8em; font-size: 2.3vmin; line-height: 1.6; white-space: pre-wrap; }
+  #choices { position: absolute; left: 25%; right: 25%; top: 25%; display: flex; flex-direction: column; gap: 1.2vmin; }
+  #choices button { padding: 1.4vmin; font-size: 2.2vmin; border: none; border-radius: 0.6vmin; background: rgba(255, 255, 255, 0.92); cursor: pointer; }
+  #progress { position: absolute; top: 1.5vmin; right: 2vmin; color: rgba(255, 255, 255, 0.6); font-size: 1.6vmin; }
+</style>
+</head>
+<body>
+<div id="stage">
+  <div id="progress"></div>
+  <div id="choices"></div>
+  <div id="box"><span id="name"></span><span id="aff"></span><div id="line"></div><div id="text"></div></div>
+</div>
+<script>
+const DATA = __AEFR_DATA__;
+let scene = 0, segment = 0;
+const $ = id => document.getElementById(id);
+function show() {
+  const s = DATA.scenes[scene];
+  if (!s) return;
+  $("stage").style.backgroundImage = s.bg !== null ? "url(" + DATA.images[s.bg] + ")" : (s.fill || "none");
+  $("name").textContent = s.name;
+  $("aff").textContent = s.aff;
+  $("text").textContent = s.segments[segment] || "";
+  $("progress").textContent = (scene + 1) + " / " + DATA.scenes.length;
+  const choices = $("choices");
+  choices.innerHTML = "";
+  if (segment >= s.segments.length - 1) {
+    for (const c of s.choices) {
+      const b = document.createElement("button");
+      b.textContent = c.text;
+      b.onclick = e => { e.stopPropagation(); go(c.target); };
+      choices.appendChild(b);
+    }
+  }
+}
+function go(target) {
+  if (target < 0 || target >= DATA.scenes.length) return;
+  scene = target; segment = 0; show();
+}
+function advance() {
+  const s = DATA.scenes[scene];
+  if (!s) return;
+  if (segment < s.segments.length - 1) { segment++; show(); }
+  else if (s.choices.length === 0) go(scene + 1);
+}
+$("stage").addEventListener("click", advance);
+document.addEventListener("keydown", e => {
+  if (e.key === " " || e.key === "Enter" || e.key === "ArrowRight") { e.preventDefault(); advance(); }
+  if (e.key === "ArrowLeft") go(scene - 1);
+});
+show();
+</script>
+</body>
+</html>
+"##;
+
+/// 离屏缩略图的去处
+#[derive(Clone)]
+enum ThumbnailTarget {
+    Slot(usize),  // 槽位立绘缩略图
+    Scene(usize), // 分镜视图中的一幕
+    SceneBackdrop(usize, SceneBackground), // 未浏览的幕只画背景的占位图（生成期间背景被改动时丢弃）
+}
+
+/// 分镜视图中一幕的缩略图
+enum SceneThumbnail {
+    Pending,                 // 已交给后台生成占位图（没有可画的背景时一直保持）
+    Backdrop(TextureHandle), // 尚未浏览：只有背景，不含立绘
+    Full(TextureHandle),     // 浏览时截取的完整画面
+}
+
+/// 只画背景的分镜占位图：图片按保持比例铺满后裁切，纯色/渐变直接填充；未指定背景或读取失败时为 None
+fn backdrop_thumbnail(background: &SceneBackground, file: Option<&std::path::Path>, filter: ColorFilter, size: [usize; 2]) -> Option<egui::ColorImage> {
+    let [w, h] = size;
+    let image = match file {
+        Some(file) => {
+            let img = image::open(file).ok()?.resize_to_fill(w as u32, h as u32, image::imageops::FilterType::Triangle);
+            premultiplied_image([img.width() as _, img.height() as _], img.to_rgba8().as_raw())
+        }
+        None => {
+            let pixels = (0..w * h)
+                .map(|i| background.color_at((i % w) as f32 / w as f32, (i / w) as f32 / h as f32))
+                .collect::<Option<Vec<_>>>()?;
+            egui::ColorImage { size, pixels }
+        }
+    };
+    Some(egui::ColorImage { size: image.size, pixels: image.pixels.iter().map(|&c| filter.apply(c)).collect() })
+}
+
+/// 一次离屏光栅化所需的全部数据（网格、贴图副本与背景填充），可以整体移交给后台线程
+struct RasterJob {
+    meshes: Vec<Mesh>,
+    pages: Vec<(TextureId, Arc<egui::ColorImage>)>,
+    size: [usize; 2],
+    fill: SceneBackground, // 纯色/渐变背景垫在画面之下，图片背景时不填充（图片已在网格中）
+}
+
+impl RasterJob {
+    fn run(self) -> egui::ColorImage {
+        let pages: Vec<(TextureId, &egui::ColorImage)> = self.pages.iter().map(|(id, img)| (*id, img.as_ref())).collect();
+        let [w, h] = self.size;
+        let mut img = rasterize_meshes(&self.meshes, &pages, self.size);
+        if !self.fill.is_image() {
+            // 预乘 Alpha 的 "under" 混合
+            for (i, px) in img.pixels.iter_mut().enumerate() {
+                let (u, v) = ((i % w) as f32 / w as f32, (i / w) as f32 / h as f32);
+                let bg = self.fill.color_at(u, v).unwrap_or(Color32::BLACK).to_array();
+                let [r, g, b, a] = px.to_array();
+                let under = |fg: u8, bg: u8| fg.saturating_add((bg as u32 * (255 - a as u32) / 255) as u8);
+                *px = Color32::from_rgba_premultiplied(under(r, bg[0]), under(g, bg[1]), under(b, bg[2]), under(a, bg[3]));
+            }
+        }
+        img
+    }
+}
+
+/// 按最近邻取样把图片缩到最长边不超过 `max_side`（只给百余像素的缩略图取色，不需要滤波）
+fn downsample_image(image: &egui::ColorImage, max_side: usize) -> egui::ColorImage {
+    let [w, h] = image.size;
+    let step = (w.max(h) + max_side - 1) / max_side.max(1);
+    if step <= 1 {
+        return image.clone();
+    }
+    let size = [(w / step).max(1), (h / step).max(1)];
+    let pixels = (0..size[1])
+        .flat_map(|y| (0..size[0]).map(move |x| (x, y)))
+        .map(|(x, y)| image.pixels[(y * step).min(h - 1) * w + (x * step).min(w - 1)])
+        .collect();
+    egui::ColorImage { size, pixels }
+}
+
+/// 在读取线程中准备背景/前景：预乘、套用滤镜，并生成缩略图用的低分辨率副本
+fn prepare_backdrop(img: &image::DynamicImage, filter: ColorFilter) -> (Arc<egui::ColorImage>, Arc<egui::ColorImage>) {
+    let image = Arc::new(premultiplied_image([img.width() as _, img.height() as _], img.to_rgba8().as_raw()));
+    let raster = Arc::new(downsample_image(&image, RASTER_PAGE_MAX_SIDE));
+    (filter.apply_image(&image), raster)
+}
+
+/// 在 CPU 上把若干 Mesh 光栅化为图片（透明背景，预乘 Alpha 的 "over" 混合）
+/// 纹理按最近邻采样，`pages` 提供各纹理ID对应的像素数据，找不到纹理的三角形跳过
+fn rasterize_meshes(meshes: &[Mesh], pages: &[(TextureId, &egui::ColorImage)], size: [usize; 2]) -> egui::ColorImage {
+    let [w, h] = size;
+    let mut out = egui::ColorImage::new(size, Color32::TRANSPARENT);
+    for mesh in meshes {
+        let tex = match pages.iter().find(|(id, _)| *id == mesh.texture_id) {
+            Some((_, img)) => *img,
+            None => continue,
+        };
+        let [tw, th] = tex.size;
+        for tri in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| &mesh.vertices[i as usize]);
+            let area = (b.pos - a.pos).x * (c.pos - a.pos).y - (b.pos - a.pos).y * (c.pos - a.pos).x;
+            if area.abs() < f32::EPSILON {
+                continue;
+            }
+            let min_x = a.pos.x.min(b.pos.x).min(c.pos.x).floor().max(0.0) as usize;
+            let max_x = (a.pos.x.max(b.pos.x).max(c.pos.x).ceil().max(0.0) as usize).min(w);
+            let min_y = a.pos.y.min(b.pos.y).min(c.pos.y).floor().max(0.0) as usize;
+            let max_y = (a.pos.y.max(b.pos.y).max(c.pos.y).ceil().max(0.0) as usize).min(h);
+            let [tint_r, tint_g, tint_b, tint_a] = a.color.to_array().map(|c| c as u32); // 同一附件的顶点颜色相同
+            for y in min_y..max_y {
+                for x in min_x..max_x {
+                    // 像素中心的重心坐标（两种绕序都接受）
+                    let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    let w0 = ((b.pos - p).x * (c.pos - p).y - (b.pos - p).y * (c.pos - p).x) / area;
+                    let w1 = ((c.pos - p).x * (a.pos - p).y - (c.pos - p).y * (a.pos - p).x) / area;
+                    let w2 = 1.0 - w0 - w1;
+                    if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                        continue;
+                    }
+                    let u = a.uv.x * w0 + b.uv.x * w1 + c.uv.x * w2;
+                    let v = a.uv.y * w0 + b.uv.y * w1 + c.uv.y * w2;
+                    let tx = ((u * tw as f32) as usize).min(tw - 1);
+                    let ty = ((v * th as f32) as usize).min(th - 1);
+                    let [sr, sg, sb, sa] = tex.pixels[ty * tw + tx].to_array().map(|c| c as u32);
+                    let src = [sr * tint_r / 255, sg * tint_g / 255, sb * tint_b / 255, sa * tint_a / 255];
+                    if src[3] == 0 {
+                        continue;
+                    }
+                    let dst = &mut out.pixels[y * w + x];
+                    let d = dst.to_array().map(|c| c as u32);
+                    let blend = |i: usize| (src[i] + d[i] * (255 - src[3]) / 255).min(255) as u8;
+                    *dst = Color32::from_rgba_premultiplied(blend(0), blend(1), blend(2), blend(3));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// 立绘贴图的纹理选项：放大与缩小都使用同一种过滤方式
+fn texture_options(filter: egui::TextureFilter) -> egui::TextureOptions {
+    egui::TextureOptions { magnification: filter, minification: filter, ..Default::default() }
+}
+
+/// 按贴图分辨率与颜色滤镜上传一页立绘贴图：低于 1 时缩小贴图以节省显存与采样带宽（画面仍按原生分辨率绘制），
+/// 缩放后不超过 GPU 支持的最大纹理边长
+/// 贴图为预乘格式，直接插值不会在边缘产生色边；UV 是归一化坐标，缩放不影响网格
+fn upload_page(ctx: &egui::Context, name: &str, image: &Arc<egui::ColorImage>, options: egui::TextureOptions, scale: f32, color_filter: ColorFilter) -> TextureHandle {
+    let max_side = ctx.input(|i| i.max_texture_side);
+    ctx.load_texture(name, prepare_page(image, scale, color_filter, max_side), options)
+}
+
+/// `upload_page` 中与 GPU 无关的部分（套用滤镜并缩放），可在后台线程执行
+fn prepare_page(image: &Arc<egui::ColorImage>, scale: f32, color_filter: ColorFilter, max_side: usize) -> Arc<egui::ColorImage> {
+    let image = color_filter.apply_image(image);
+    let [w, h] = image.size;
+    let scale = scale.min(1.0).min(max_side as f32 / w.max(h).max(1) as f32);
+    if (scale - 1.0).abs() < 0.01 {
+        return image;
+    }
+    let (nw, nh) = (((w as f32 * scale).round() as u32).max(1), ((h as f32 * scale).round() as u32).max(1));
+    let rgba: Vec<u8> = image.pixels.iter().flat_map(|c| c.to_array()).collect();
+    match image::RgbaImage::from_raw(w as u32, h as u32, rgba) {
+        Some(buffer) => {
+            let resized = image::imageops::resize(&buffer, nw, nh, image::imageops::FilterType::Triangle);
+            Arc::new(egui::ColorImage::from_rgba_premultiplied([nw as usize, nh as usize], resized.as_raw()))
+        }
+        None => image,
+    }
+}
+
+/// 立绘资源涉及的所有文件：atlas 本身、同名 skel/json 以及各页贴图
+fn character_files(atlas_path: &str, pages: &[(String, TextureId)]) -> Vec<std::path::PathBuf> {
+    // 从压缩包加载的立绘只需关注压缩包本身
+    if let Some((archive, _)) = split_zip_path(atlas_path) {
+        return vec![std::path::PathBuf::from(archive)];
+    }
+    let atlas = std::path::Path::new(atlas_path);
+    let dir = atlas.parent().unwrap_or(std::path::Path::new(""));
+    let mut files = vec![atlas.to_path_buf(), atlas.with_extension("skel"), atlas.with_extension("json")];
+    files.extend(pages.iter().map(|(name, _)| dir.join(name)));
+    files
+}
+
+/// 拆分压缩包路径：`pack.zip` 或 `pack.zip::包内/路径.atlas`，不是压缩包时返回 None
+fn split_zip_path(path: &str) -> Option<(&str, Option<&str>)> {
+    let (archive, entry) = match path.split_once(ZIP_ENTRY_SEPARATOR) {
+        Some((archive, entry)) => (archive, Some(entry)),
+        None => (path, None),
+    };
+    if archive.to_lowercase().ends_with(".zip") { Some((archive, entry)) } else { None }
+}
+
+/// 列出压缩包内所有 .atlas 文件（忽略 macOS 打包时附带的 __MACOSX 元数据）
+fn zip_atlas_entries(archive: &str) -> Result<Vec<String>, String> {
+    let file = std::fs::File::open(archive).map_err(|e| format!("Zip Error ({}): {}", archive, e))?;
+    let zip = zip::ZipArchive::new(file).map_err(|e| format!("Zip Error ({}): {}", archive, e))?;
+    let mut entries: Vec<String> = zip.file_names()
+        .filter(|n| n.to_lowercase().ends_with(".atlas") && !n.starts_with("__MACOSX/"))
+        .map(String::from)
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+/// 把压缩包中 atlas 所在目录（含子目录）解压到一个新的临时目录，返回临时目录与解压后的 atlas 路径
+/// 解压失败时删除已写出的部分
+fn extract_zip_model(archive: &str, atlas_entry: &str) -> Result<(std::path::PathBuf, std::path::PathBuf), String> {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!("aefr_zip_{}_{}", std::process::id(), NEXT_ID.fetch_add(1, Ordering::Relaxed)));
+    let prefix = atlas_entry.rfind('/').map_or("", |i| &atlas_entry[..=i]);
+    let extract = || -> Result<(), String> {
+        let err = |e: &dyn std::fmt::Display| format!("Zip Error ({}): {}", archive, e);
+        let file = std::fs::File::open(archive).map_err(|e| err(&e))?;
+        let mut zip = zip::ZipArchive::new(file).map_err(|e| err(&e))?;
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i).map_err(|e| err(&e))?;
+            if entry.is_dir() || !entry.name().starts_with(prefix) {
+                continue;
+            }
+            // enclosed_name 拒绝绝对路径与 ..，保证只写入临时目录内
+            let out = match entry.enclosed_name() {
+                Some(rel) => dir.join(rel),
+                None => continue,
+            };
+            if let Some(parent) = out.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| err(&e))?;
+            }
+            let mut file = std::fs::File::create(&out).map_err(|e| err(&e))?;
+            std::io::copy(&mut entry, &mut file).map_err(|e| err(&e))?;
+        }
+        Ok(())
+    };
+    match extract() {
+        Ok(()) => {
+            let atlas_path = dir.join(atlas_entry);
+            Ok((dir, atlas_path))
+        }
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&dir);
+            Err(e)
+        }
+    }
+}
+
+/// 一组文件中最新的修改时间（不存在的文件忽略）
+fn latest_mtime(files: &[std::path::PathBuf]) -> Option<std::time::SystemTime> {
+    files.iter()
+        .filter_map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+/// 图片保持比例铺满 `rect` 时所占的矩形（居中，超出部分被裁掉）
+fn cover_rect(rect: Rect, img_size: Vec2) -> Rect {
+    let scale = (rect.width() / img_size.x).max(rect.height() / img_size.y); // 保持比例缩放
+    Rect::from_center_size(rect.center(), img_size * scale)
+}
+
+/// 按保持比例铺满的方式绘制背景图片
+fn draw_background(ui: &mut egui::Ui, rect: Rect, bg: &TextureHandle, tint: Color32) {
+    ui.painter().image(
+        bg.id(), 
+        cover_rect(rect, bg.size_vec2()), 
+        Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)), 
+        tint
+    );
+}
+
+/// 右上角 SKIP / AUTO / MENU 三个按钮的区域
+fn top_right_button_rects(screen: Rect) -> [Rect; 3] {
+    let (btn_w, btn_h, margin) = (90.0, 32.0, 20.0);
+    let size = Vec2::new(btn_w, btn_h);
+    [
+        Rect::from_min_size(Pos2::new(screen.right() - btn_w * 3.0 - margin - 20.0, margin), size),
+        Rect::from_min_size(Pos2::new(screen.right() - btn_w * 2.0 - margin - 10.0, margin), size),
+        Rect::from_min_size(Pos2::new(screen.right() - btn_w - margin, margin), size),
+    ]
+}
+
+/// 演示模式退出按钮的区域：MENU 按钮正下方右对齐，不与右上角三个按钮重叠
+fn exit_button_rect(screen: Rect) -> Rect {
+    let corner = top_right_button_rects(screen)[2].right_bottom();
+    Rect::from_min_size(Pos2::new(corner.x - 32.0, corner.y + 8.0), Vec2::splat(32.0))
+}
+
+/// 绘制右上角按钮（AUTO/MENU）
+fn draw_top_right_buttons(ui: &mut egui::Ui, screen: Rect, is_auto: &mut bool, is_skip: &mut bool) {
+    let [skip_rect, auto_rect, menu_rect] = top_right_button_rects(screen);
+    
+    // SKIP按钮（快进）
+    if ui.allocate_rect(skip_rect, egui::Sense::click()).clicked() { 
+        *is_skip = !*is_skip; 
+    }
+    ui.painter().rect_filled(skip_rect, 4.0, 
+        if *is_skip { Color32::from_rgb(255, 215, 0) } else { Color32::WHITE }
+    );
+    ui.painter().text(
+        skip_rect.center(), 
+        egui::Align2::CENTER_CENTER, 
+        "SKIP ▶▶", 
+        egui::FontId::proportional(18.0), 
+        Color32::from_rgb(20, 30, 50)
+    );
+    
+    // AUTO按钮
+    if ui.allocate_rect(auto_rect, egui::Sense::click()).clicked() { 
+        *is_auto = !*is_auto; 
+    }
+    ui.painter().rect_filled(auto_rect, 4.0, 
+        if *is_auto { Color32::from_rgb(255, 215, 0) } else { Color32::WHITE }
+    );
+    ui.painter().text(
+        auto_rect.center(), 
+        egui::Align2::CENTER_CENTER, 
+        "AUTO", 
+        egui::FontId::proportional(18.0), 
+        Color32::from_rgb(20, 30, 50)
+    );
+    
+    // MENU按钮
+    ui.painter().rect_filled(menu_rect, 4.0, Color32::WHITE);
+    ui.painter().text(
+        menu_rect.center(), 
+        egui::Align2::CENTER_CENTER, 
+        "MENU", 
+        egui::FontId::proportional(18.0), 
+        Color32::from_rgb(20, 30, 50)
+    );
+}
+
+/// 按宽度 `wrap` 计算对话文本的断行位置（字符下标，在该字符前换行），`glyph_width` 给出每个字的宽度，并做避头尾处理：
+/// 行末最多挂一个避头标点（悬挂，允许超出一个标点宽度），连续多个避头标点时把前面的字一起推到下一行（追い出し）；
+/// 避尾标点连同后面的字一起移到下一行；英文在空格处断开，中日文任意两字之间可断，整行找不到合法断点时在超宽处强制断开
+fn kinsoku_breaks(text: &str, wrap: f32, glyph_width: impl Fn(char) -> f32) -> Vec<usize> {
+    let chars: Vec<char> = text.chars().collect();
+    let widths: Vec<f32> = chars.iter().map(|&c| glyph_width(c)).collect();
+    let can_break = |i: usize| {
+        let (prev, next) = (chars[i - 1], chars[i]);
+        !LINE_START_FORBIDDEN.contains(next) && !LINE_END_FORBIDDEN.contains(prev) && !next.is_whitespace()
+            && (prev.is_whitespace() || is_cjk(prev) || is_cjk(next))
+    };
+    let mut breaks = Vec::new();
+    let (mut line_start, mut width) = (0, 0.0);
+    for i in 0..chars.len() {
+        if chars[i] == '\n' {
+            line_start = i + 1;
+            width = 0.0;
+            continue;
+        }
+        width += widths[i];
+        let hangs = LINE_START_FORBIDDEN.contains(chars[i]) && width - widths[i] <= wrap; // 只有第一个超宽的避头标点可以悬挂
+        if width <= wrap || i == line_start || chars[i].is_whitespace() || hangs {
+            continue;
+        }
+        let at = (line_start + 1..=i).rev().find(|&b| can_break(b)).unwrap_or(i);
+        breaks.push(at);
+        line_start = at;
+        width = widths[at..=i].iter().sum();
+    }
+    breaks
+}
+
+/// 按完整文本 `full_content` 的断行位置给当前显示的部分 `content` 插入换行（打字机逐字显示时各行不会跳动）
+/// 返回的文本应以不限宽度排版，换行已全部由这里决定
+fn kinsoku_wrap(ui: &egui::Ui, content: &str, full_content: &str, font: &egui::FontId, wrap: f32) -> String {
+    let breaks = ui.fonts(|f| kinsoku_breaks(full_content, wrap, |c| f.glyph_width(font, c)));
+    let mut out = String::with_capacity(content.len() + breaks.len());
+    let mut next = breaks.iter().peekable();
+    for (i, c) in content.chars().enumerate() {
+        if next.next_if(|&&b| b == i).is_some() {
+            out.truncate(out.trim_end_matches(' ').len()); // 行尾空格不占位，居中对齐时不偏移
+            out.push('\n');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// 蔚蓝档案风格对话框的高度与内容字号
+/// 名字区与上下留白按默认高度固定，自动高度时内容区随完整文本换行后的总高度伸缩，
+/// 超出上限时逐步缩小字号；关闭自动高度时保持默认高度与字号
+fn ba_dialogue_layout(ui: &egui::Ui, screen: Rect, font: egui::FontId, full_content: &str, wrap: f32, is_narration: bool, auto_height: bool) -> (f32, egui::FontId) {
+    let base_h = screen.height() * DIALOGUE_BOX_RATIO * if is_narration { NARRATION_BOX_SCALE } else { 1.0 };
+    if !auto_height {
+        return (base_h, font);
+    }
+    let name_h = if is_narration { 0.0 } else { base_h * 0.30 };
+    let chrome_h = name_h + base_h * 0.05 + base_h * 0.18; // 名字区 + 内容上方留白 + 继续指示器所在的底部留白
+    let max_h = (screen.height() * DIALOGUE_AUTO_MAX_RATIO).max(base_h);
+    let min_size = font.size * DIALOGUE_MIN_FONT_SCALE;
+    let mut font = font;
+    loop {
+        let text = kinsoku_wrap(ui, full_content, full_content, &font, wrap);
+        let content_h = ui.painter().layout(text, font.clone(), Color32::WHITE, f32::INFINITY).rect.height();
+        let needed = chrome_h + content_h;
+        if needed <= max_h || font.size <= min_size {
+            return (needed.clamp(base_h * DIALOGUE_AUTO_MIN_SCALE, max_h), font);
+        }
+        font.size = (font.size * 0.9).max(min_size);
+    }
+}
+
+/// 绘制蔚蓝档案风格对话框
+/// 返回值：是否被点击（用于跳过打字机）
+fn draw_ba_dialogue(ui: &mut egui::Ui, screen: Rect, skin: DialogueSkin, text: DialogueContent, is_finished: bool) -> bool {
+    let DialogueSkin { fonts, avatar, name_box, effect, indicator, auto_height } = skin;
+    let DialogueContent { name, affiliation, content, full_content } = text;
+    let is_narration = name.is_empty(); // 没有说话者即为旁白/系统提示
+    let base_h = screen.height() * DIALOGUE_BOX_RATIO * if is_narration { NARRATION_BOX_SCALE } else { 1.0 }; // 默认对话框高度
+    let pad_x = (screen.width() * 0.08).max(100.0); // 两侧内边距
+    let wrap = (screen.width() - pad_x * 2.0).max(1.0);
+    let (box_h, content_font) = ba_dialogue_layout(ui, screen, DialogueFonts::scaled(&fonts.content, screen), full_content, wrap, is_narration, auto_height);
+    let box_rect = Rect::from_min_max(Pos2::new(screen.left(), screen.bottom() - box_h), screen.max);
+    // 分割线Y位置（旁白模式没有名字区，背景从对话框顶部开始；名字区高度不随内容变化）
+    let line_y = if is_narration { box_rect.top() } else { box_rect.top() + (base_h * 0.30) };
+    
+    // 1. 绘制暗蓝色背景 (高不透明度)
+    let dark_blue_opaque = Color32::from_rgba_unmultiplied(12, 18, 28, 252);
+    ui.painter().rect_filled(
+        Rect::from_min_max(Pos2::new(screen.left(), line_y), screen.max), 
+        0.0, 
+        dark_blue_opaque
+    );
+    
+    // 2. 🌟 严谨：使用 Mesh 绘制名字区域的垂直渐变淡出（旁白模式没有名字区；有名字框贴图时改为在名字后面铺贴图）
+    if !is_narration && name_box.is_none() {
+        let gradient_rect = Rect::from_min_max(box_rect.left_top(), Pos2::new(screen.right(), line_y));
+        let mut mesh = Mesh::default();
+        // 使用 Unmultiplied 确保混合模式正确
+        let color_bottom = Color32::from_rgba_unmultiplied(12, 18, 28, 245);
+        let color_top = Color32::from_rgba_unmultiplied(12, 18, 28, 0);
+        
+        // 添加渐变四边形的四个顶点
+        mesh.vertices.push(Vertex { pos: gradient_rect.left_top(), uv: Pos2::ZERO, color: color_top });
+        mesh.vertices.push(Vertex { pos: gradient_rect.right_top(), uv: Pos2::ZERO, color: color_top });
+        mesh.vertices.push(Vertex { pos: gradient_rect.right_bottom(), uv: Pos2::ZERO, color: color_bottom });
+        mesh.vertices.push(Vertex { pos: gradient_rect.left_bottom(), uv: Pos2::ZERO, color: color_bottom });
+        mesh.add_triangle(0, 1, 2);
+        mesh.add_triangle(0, 2, 3);
+        ui.painter().add(Shape::mesh(mesh));
+    }
+
+    // 对话框点击区域（用于跳过）
+    let resp = ui.allocate_rect(box_rect, egui::Sense::click());
+    
+    // 绘制分割线（旁白模式下省略）
+    if !is_narration {
+        ui.painter().line_segment([Pos2::new(pad_x, line_y), Pos2::new(screen.right() - pad_x, line_y)], 
+            Stroke::new(1.5, Color32::from_rgb(100, 120, 150))
+        );
+    }
+
+    // 说话者头像：放在内容区左侧留白内
+    if let Some(avatar) = avatar {
+        let size = (pad_x * 0.8).min((screen.bottom() - line_y) * 0.85);
+        let center = Pos2::new(box_rect.left() + pad_x / 2.0, (line_y + screen.bottom()) / 2.0);
+        ui.painter().image(
+            avatar.id(), 
+            Rect::from_center_size(center, Vec2::splat(size)), 
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)), 
+            Color32::WHITE
+        );
+    }
+
+    // 绘制说话者姓名和所属
+    if !name.is_empty() {
+        let n_font = DialogueFonts::scaled(&fonts.name, screen);
+        let n_size = n_font.size;
+        
+        // 1. 先生成 Galley 拿到实际渲染高度，再决定坐标
+        let n_gal = ui.painter().layout_no_wrap(name.into(), n_font.clone(), Color32::WHITE);
+        let n_width = n_gal.rect.width();
+        let n_height = n_gal.rect.height();
+
+        // 🌟 修复点 1：位置不再从顶部往下算，而是【依托分割线往上推算】
+        // 这样不管什么字体，文字底部永远贴近 line_y
+        let margin_bottom = 4.0; // 距离分割线的固定留白，可微调
+        let n_pos = Pos2::new(box_rect.left() + pad_x, line_y - n_height - margin_bottom);
+
+        let aff_gal = (!affiliation.is_empty()).then(|| ui.painter().layout_no_wrap(
+            affiliation.into(), 
+            egui::FontId::new(n_size * 0.75, n_font.family.clone()), 
+            Color32::from_rgb(100, 200, 255)
+        ));
+
+        // 名字框贴图铺在名字与所属之后，宽度随文字长度伸缩
+        if let Some(skin) = name_box {
+            let text_right = n_pos.x + n_width + aff_gal.as_ref().map_or(0.0, |g| 15.0 + g.rect.width());
+            let pad = Vec2::new(n_height * 0.6, n_height * 0.25);
+            let rect = Rect::from_min_max(n_pos - pad, Pos2::new(text_right + pad.x, line_y));
+            paint_nine_slice(ui.painter(), rect, skin);
+        }
+
+        if let Some(aff_gal) = aff_gal {
+            let aff_height = aff_gal.rect.height();
+            
+            // 🌟 修复点 2：修复基线对齐
+            let visual_compensation = -3.0; // 如果觉得还偏高就把加大，觉得偏低就减小
+            let y_offset = n_height - aff_height + visual_compensation; 
+            
+            effect.paint(ui.painter(), n_pos, n_gal.clone(), Color32::WHITE);
+            effect.paint(
+                ui.painter(), 
+                n_pos + Vec2::new(n_width + 15.0, y_offset), 
+                aff_gal, 
+                Color32::from_rgb(100, 200, 255)
+            );
+        } else {
+            effect.paint(ui.painter(), n_pos, n_gal, Color32::WHITE);
+        }
+    }
+    
+    // 🌟 严谨：内容上移，紧贴分割线；旁白则在对话框内居中显示（超出宽度时自动换行）
+    let c_gal = ui.painter().layout(kinsoku_wrap(ui, content, full_content, &content_font, wrap), content_font, Color32::WHITE, f32::INFINITY);
+    let c_pos = if is_narration {
+        egui::Align2::CENTER_CENTER.anchor_size(box_rect.center(), c_gal.size()).min
+    } else {
+        Pos2::new(box_rect.left() + pad_x, line_y + base_h * 0.05)
+    };
+    effect.paint(ui.painter(), c_pos, c_gal, Color32::WHITE);
+    
+    // 绘制"继续"指示器（当文本显示完成时），与屏幕底部的距离不随对话框高度变化
+    if is_finished {
+        indicator.paint(ui, Pos2::new(screen.right() - pad_x, screen.bottom() - (base_h * 0.15)), base_h * 0.04, Color32::from_rgb(0, 180, 255));
+    }
+    resp.clicked() // 返回是否被点击
+}
+
+/// 九宫格绘制：四角保持贴图比例不变，四边沿单向拉伸，中间双向拉伸
+/// 屏幕上的边宽按矩形高度与贴图高度之比缩放，窄于两倍边宽时收缩边宽以免翻折
+fn paint_nine_slice(painter: &egui::Painter, rect: Rect, skin: NameBoxSkin) {
+    let tex_size = skin.texture.size_vec2();
+    if tex_size.x <= 0.0 || tex_size.y <= 0.0 {
+        return;
+    }
+    let border = skin.border.clamp(0.0, tex_size.x.min(tex_size.y) / 2.0);
+    let screen_border = (border * rect.height() / tex_size.y).min(rect.width() / 2.0).min(rect.height() / 2.0);
+    let xs = [rect.left(), rect.left() + screen_border, rect.right() - screen_border, rect.right()];
+    let ys = [rect.top(), rect.top() + screen_border, rect.bottom() - screen_border, rect.bottom()];
+    let us = [0.0, border / tex_size.x, 1.0 - border / tex_size.x, 1.0];
+    let vs = [0.0, border / tex_size.y, 1.0 - border / tex_size.y, 1.0];
+
+    let mut mesh = Mesh::with_texture(skin.texture.id());
+    for (y, v) in ys.iter().zip(vs) {
+        for (x, u) in xs.iter().zip(us) {
+            mesh.vertices.push(Vertex { pos: Pos2::new(*x, *y), uv: Pos2::new(u, v), color: Color32::WHITE });
+        }
+    }
+    for row in 0..3u32 {
+        for col in 0..3u32 {
+            let i = row * 4 + col;
+            mesh.add_triangle(i, i + 1, i + 5);
+            mesh.add_triangle(i, i + 5, i + 4);
+        }
+    }
+    painter.add(Shape::mesh(mesh));
+}
+
+/// 绘制经典视觉小说风格对话框：底部留边的半透明圆角条，名字写在条内左上角
+/// 返回值：是否被点击
+fn draw_classic_dialogue(ui: &mut egui::Ui, screen: Rect, skin: DialogueSkin, text: DialogueContent, is_finished: bool) -> bool {
+    let DialogueSkin { fonts, avatar, indicator, .. } = skin;
+    let DialogueContent { name, affiliation, content, full_content } = text;
+    let margin = screen.width() * 0.03;
+    let box_h = screen.height() * DIALOGUE_BOX_RATIO * 0.85;
+    let box_rect = Rect::from_min_max(
+        Pos2::new(screen.left() + margin, screen.bottom() - box_h - margin), 
+        Pos2::new(screen.right() - margin, screen.bottom() - margin)
+    );
+    ui.painter().rect(box_rect, 12.0, Color32::from_black_alpha(170), Stroke::new(1.5, Color32::from_white_alpha(90)));
+    let resp = ui.allocate_rect(box_rect, egui::Sense::click());
+
+    let pad = box_h * 0.12;
+    let mut text_left = box_rect.left() + pad;
+    if let Some(avatar) = avatar {
+        let size = box_h - pad * 2.0;
+        ui.painter().image(
+            avatar.id(), 
+            Rect::from_min_size(Pos2::new(text_left, box_rect.top() + pad), Vec2::splat(size)), 
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)), 
+            Color32::WHITE
+        );
+        text_left += size + pad;
+    }
+
+    let mut text_top = box_rect.top() + pad;
+    if !name.is_empty() {
+        let n_font = DialogueFonts::scaled(&fonts.name, screen);
+        let label = if affiliation.is_empty() { name.to_string() } else { format!("{}（{}）", name, affiliation) };
+        let n_gal = ui.painter().layout_no_wrap(label, n_font, Color32::from_rgb(255, 220, 120));
+        text_top += n_gal.rect.height() + pad * 0.4;
+        ui.painter().galley(Pos2::new(text_left, box_rect.top() + pad), n_gal, Color32::from_rgb(255, 220, 120));
+    }
+
+    let content_font = DialogueFonts::scaled(&fonts.content, screen);
+    let wrap = box_rect.right() - pad - text_left;
+    let c_gal = ui.painter().layout(kinsoku_wrap(ui, content, full_content, &content_font, wrap), content_font, Color32::WHITE, f32::INFINITY);
+    ui.painter().galley(Pos2::new(text_left, text_top), c_gal, Color32::WHITE);
+
+    if is_finished {
+        indicator.paint(ui, box_rect.right_bottom() - Vec2::splat(pad), box_h * 0.04, Color32::WHITE);
+    }
+    resp.clicked()
+}
+
+/// 绘制居中框风格对话框：名字与内容在屏幕下半部的圆角框内居中排列（不显示头像）
+/// 返回值：是否被点击
+fn draw_centered_dialogue(ui: &mut egui::Ui, screen: Rect, skin: DialogueSkin, text: DialogueContent, is_finished: bool) -> bool {
+    let DialogueSkin { fonts, indicator, .. } = skin;
+    let DialogueContent { name, affiliation, content, full_content } = text;
+    let box_w = (screen.width() * 0.6).max(320.0).min(screen.width());
+    let box_h = screen.height() * DIALOGUE_BOX_RATIO;
+    let box_rect = Rect::from_center_size(Pos2::new(screen.center().x, screen.bottom() - box_h * 0.75), Vec2::new(box_w, box_h));
+    ui.painter().rect(box_rect, 16.0, Color32::from_rgba_unmultiplied(20, 20, 30, 200), Stroke::new(2.0, Color32::from_rgb(200, 200, 220)));
+    let resp = ui.allocate_rect(box_rect, egui::Sense::click());
+
+    let pad = box_h * 0.1;
+    let mut text_top = box_rect.top() + pad;
+    if !name.is_empty() {
+        let n_font = DialogueFonts::scaled(&fonts.name, screen);
+        let n_gal = ui.painter().layout_no_wrap(name.into(), n_font.clone(), Color32::WHITE);
+        let mut height = n_gal.rect.height();
+        ui.painter().galley(Pos2::new(box_rect.center().x - n_gal.rect.width() / 2.0, text_top), n_gal, Color32::WHITE);
+        if !affiliation.is_empty() {
+            let aff_gal = ui.painter().layout_no_wrap(
+                affiliation.into(), 
+                egui::FontId::new(n_font.size * 0.7, n_font.family), 
+                Color32::from_rgb(160, 160, 180)
+            );
+            ui.painter().galley(Pos2::new(box_rect.center().x - aff_gal.rect.width() / 2.0, text_top + height), aff_gal.clone(), Color32::from_rgb(160, 160, 180));
+            height += aff_gal.rect.height();
+        }
+        text_top += height + pad * 0.5;
+    }
+
+    let content_font = DialogueFonts::scaled(&fonts.content, screen);
+    let text = kinsoku_wrap(ui, content, full_content, &content_font, box_w - pad * 2.0);
+    let mut job = egui::text::LayoutJob::simple(text, content_font, Color32::WHITE, f32::INFINITY);
+    job.halign = egui::Align::Center;
+    let c_gal = ui.painter().layout_job(job);
+    ui.painter().galley(Pos2::new(box_rect.center().x, text_top), c_gal, Color32::WHITE);
+
+    if is_finished {
+        indicator.paint(ui, Pos2::new(box_rect.center().x, box_rect.bottom() - pad), box_h * 0.035, Color32::from_rgb(200, 200, 220));
+    }
+    resp.clicked()
+}
+
+/// 分支选项按钮的区域（对话框上方居中排列）
+fn choice_rects(screen: Rect, count: usize) -> Vec<Rect> {
+    let box_h = screen.height() * DIALOGUE_BOX_RATIO;
+    let btn_w = (screen.width() * 0.5).min(640.0);
+    let btn_h = (screen.height() * 0.07).clamp(36.0, 56.0);
+    let gap = 12.0;
+    
+    // 在对话框以上的区域内垂直居中
+    let total_h = count as f32 * (btn_h + gap) - gap;
+    let top = (screen.top() + screen.bottom() - box_h) / 2.0 - total_h / 2.0;
+    (0..count)
+        .map(|i| Rect::from_min_size(Pos2::new(screen.center().x - btn_w / 2.0, top + i as f32 * (btn_h + gap)), Vec2::new(btn_w, btn_h)))
+        .collect()
+}
+
+/// 绘制剧本分支选项
+/// 返回值：被点击选项的目标幕索引
+fn draw_choices(ui: &mut egui::Ui, screen: Rect, choices: &[(String, usize)]) -> Option<usize> {
+    let mut picked = None;
+    for ((text, target), btn_rect) in choices.iter().zip(choice_rects(screen, choices.len())) {
+        let btn_h = btn_rect.height();
+        let resp = ui.allocate_rect(btn_rect, egui::Sense::click());
+        let fill = if resp.hovered() { Color32::from_rgb(225, 240, 255) } else { Color32::WHITE };
+        ui.painter().rect_filled(btn_rect, 6.0, fill);
+        ui.painter().rect_stroke(btn_rect, 6.0, Stroke::new(1.5, Color32::from_rgb(100, 120, 150)));
+        ui.painter().text(
+            btn_rect.center(), 
+            egui::Align2::CENTER_CENTER, 
+            text, 
+            egui::FontId::proportional((btn_h * 0.4).clamp(16.0, 22.0)), 
+            Color32::from_rgb(20, 30, 50)
+        );
+        if resp.clicked() { 
+            picked = Some(*target); 
+        }
+    }
+    picked
+}
+
+/// 绘制创作者控制面板
+fn draw_creator_panel(ctx: &egui::Context, app: &mut AefrApp) {
+    let mut cmd_to_send = None; // 待发送的命令
+    // 记录编辑前的当前幕，面板绘制完后比较，任何输入框/选项的修改都标记为未保存
+    let scene_before = (app.current_scene_idx, app.scenario.scenes[app.current_scene_idx].clone());
+    egui::Window::new("创作者面板 - AEFR v1.1.1")
+        .default_pos([20.0, 50.0])
+        .resizable(false)
+        .show(ctx, |ui| {
+            // 各子面板可独立开关，打开后可分别拖动、缩放
+            ui.horizontal_wrapped(|ui| {
+                ui.toggle_value(&mut app.panels.scenes, "🎬 场景");
+                ui.toggle_value(&mut app.panels.resources, "📂 资源");
+                ui.toggle_value(&mut app.panels.audio, "🎵 音频");
+                ui.toggle_value(&mut app.panels.dialogue, "💬 对话");
+                ui.toggle_value(&mut app.panels.console, "⌨ 控制台");
+            });
+        });
+
+    // 🎬 剧本幕数管理
+    let mut open = app.panels.scenes;
+    if open {
+        egui::Window::new("🎬 剧本幕数管理")
+            .open(&mut open)
+            .default_pos([20.0, 110.0])
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("⬅ 上一幕").clicked() && app.current_scene_idx > 0 {
+                        app.current_scene_idx -= 1; 
+                        app.sync_scene_to_ui(); 
+                        app.visible_count = app.target_chars.len(); // 立即显示全部文本
+                    }
+                    ui.label(format!(" 第 {} / {} 幕 ", app.current_scene_idx + 1, app.scenario.scenes.len()));
+                    let notes = app.scenario.scenes[app.current_scene_idx].notes.trim();
+                    if !notes.is_empty() {
+                        ui.label("📝").on_hover_text(notes);
+                    }
+                    if ui.button("下一幕 ➡").clicked() && app.current_scene_idx < app.scenario.scenes.len() - 1 {
+                        app.current_scene_idx += 1; 
+                        app.sync_scene_to_ui(); 
+                        app.visible_count = app.target_chars.len();
+                    }
+                    ui.separator();
+                    if ui.button("➕ 增加一幕").clicked() {
+                        app.push_undo();
+                        let mut new_scene = app.scenario.scenes[app.current_scene_idx].clone();
+                        new_scene.dialogue_content.clear();
+                        new_scene.choices.clear();
+                        new_scene.voice_path = None; // 语音与台词一一对应，不沿用上一幕
+                        new_scene.notes.clear();
+                        app.scenario.scenes.insert(app.current_scene_idx + 1, new_scene);
+                        app.current_scene_idx += 1; 
+                        app.sync_scene_to_ui();
+                    }
+                    if ui.button("❌ 删除").clicked() && app.scenario.scenes.len() > 1 {
+                        app.push_undo();
+                        app.scenario.scenes.remove(app.current_scene_idx);
+                        app.current_scene_idx = app.current_scene_idx.min(app.scenario.scenes.len() - 1);
+                        app.sync_scene_to_ui();
+                    }
+                    if ui.add_enabled(!app.undo_stack.is_empty(), egui::Button::new("↶ 撤销")).clicked() {
+                        app.undo();
+                    }
+                });
+            
+                // 跳转输入
+                ui.horizontal(|ui| {
+                    ui.label("跳转:");
+                    let mut jump = app.current_scene_idx + 1;
+                    let len = app.scenario.scenes.len();
+                    if ui.add(egui::DragValue::new(&mut jump).clamp_range(1..=len)).changed() {
+                        app.current_scene_idx = jump - 1; 
+                        app.sync_scene_to_ui(); 
+                        app.visible_count = app.target_chars.len();
+                    }
+                });
+
+                ui.separator();
+            
+                // 保存/加载剧本
+                ui.horizontal(|ui| {
+                    if ui.button("💾 保存剧本").clicked() {
+                        let file_name = app.current_file.as_ref()
+                            .and_then(|p| p.file_name())
+                            .map_or("scenario.json".to_string(), |n| n.to_string_lossy().into_owned());
+                        if let Some(p) = rfd::FileDialog::new()
+                            .set_file_name(file_name)
+                            .save_file() 
+                        {
+                            if let Err(e) = app.save_scenario_to(p) {
+                                app.log(format!("[错误] 剧本保存失败: {}", e));
+                            }
+                        }
+                    }
+                    if ui.button("📄 新建剧本").clicked() {
+                        app.show_new_wizard = true;
+                    }
+                    if ui.button("📂 重载剧本").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("JSON", &["json"])
+                            .pick_file() 
+                        {
+                            if let Err(e) = app.load_scenario_from(p) {
+                                app.log(format!("[错误] 剧本加载失败: {}", e));
+                            }
+                        }
+                    }
+                    if let Some(recent) = app.recent_file.clone().filter(|p| app.current_file.as_ref() != Some(p)) {
+                        let name = recent.file_name().map_or(recent.display().to_string(), |n| n.to_string_lossy().into_owned());
+                        if ui.button(format!("🕘 {}", name)).on_hover_text(format!("打开最近的剧本: {}", recent.display())).clicked() {
+                            if let Err(e) = app.load_scenario_from(recent) {
+                                app.log(format!("[错误] 剧本加载失败: {}", e));
+                            }
+                        }
+                    }
+                    if ui.button("➕ 追加剧本").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("JSON", &["json"])
+                            .pick_file() 
+                        {
+                            match read_scenario_file(&p) {
+                                Ok(mut other) => {
+                                    for warning in other.sanitize() {
+                                        app.log(format!("[警告] {}", warning));
+                                    }
+                                    let insert_at = if app.merge_after_current { app.current_scene_idx + 1 } else { app.scenario.scenes.len() };
+                                    app.push_undo();
+                                    let count = app.merge_scenario(other, p.parent(), insert_at);
+                                    app.log(format!("[系统] 已从 {} 追加 {} 幕（第 {} 幕起）", p.display(), count, insert_at + 1));
+                                }
+                                Err(e) => app.log(format!("[错误] 剧本读取失败: {}", e)),
+                            }
+                        }
+                    }
+                    ui.checkbox(&mut app.merge_after_current, "插入到当前幕之后");
+                });
+
+                // 📚 多章节项目：章节列表切换，连续播放时一章结束自动进入下一章
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("项目:");
+                    if ui.button("📂 打开项目").clicked() {
+                        if let Some(p) = rfd::FileDialog::new().add_filter("AEFR 项目", &[PROJECT_EXTENSION]).pick_file() {
+                            if let Err(e) = app.load_project_from(p) {
+                                app.log(format!("[错误] 项目加载失败: {}", e));
+                            }
+                        }
+                    }
+                    if ui.button("➕ 添加章节").on_hover_text("把剧本文件追加为项目的最后一章").clicked() {
+                        if let Some(p) = rfd::FileDialog::new().add_filter("JSON", &["json"]).pick_file() {
+                            app.project.get_or_insert_with(Project::default).chapters.push(p);
+                        }
+                    }
+                    if app.project.is_some() && ui.button("💾 保存项目").clicked() {
+                        let file_name = app.project_file.as_ref()
+                            .and_then(|p| p.file_name())
+                            .map_or(format!("project.{}", PROJECT_EXTENSION), |n| n.to_string_lossy().into_owned());
+                        if let Some(p) = rfd::FileDialog::new().add_filter("AEFR 项目", &[PROJECT_EXTENSION]).set_file_name(file_name).save_file() {
+                            if let Err(e) = app.save_project_to(p) {
+                                app.log(format!("[错误] 项目保存失败: {}", e));
+                            }
+                        }
+                    }
+                });
+                if let Some(project) = &app.project {
+                    let names: Vec<String> = project.chapters.iter()
+                        .map(|c| c.file_stem().map_or(c.display().to_string(), |n| n.to_string_lossy().into_owned()))
+                        .collect();
+                    let (mut open_idx, mut remove_idx) = (None, None);
+                    ui.horizontal_wrapped(|ui| {
+                        for (i, name) in names.iter().enumerate() {
+                            let current = i == app.chapter_idx && app.current_file == app.chapter_path(i);
+                            let response = ui.selectable_label(current, format!("{}. {}", i + 1, name));
+                            if response.clicked() && !current {
+                                open_idx = Some(i);
+                            }
+                            response.context_menu(|ui| {
+                                if ui.button("移除此章").clicked() {
+                                    remove_idx = Some(i);
+                                    ui.close_menu();
+                                }
+                            });
+                        }
+                    });
+                    if let Some(i) = open_idx {
+                        if let Err(e) = app.open_chapter(i) {
+                            app.log(format!("[错误] 第 {} 章加载失败: {}", i + 1, e));
+                        }
+                    }
+                    if let Some(i) = remove_idx {
+                        if let Some(project) = &mut app.project {
+                            project.chapters.remove(i);
+                        }
+                        if i < app.chapter_idx {
+                            app.chapter_idx -= 1;
+                        }
+                    }
+                }
+
+                // 导出帧序列（离线渲染）与资源检查
+                ui.horizontal(|ui| {
+                    if ui.button("🔍 检查资源").clicked() {
+                        app.resource_report = Some(app.check_resources());
+                    }
+                    if ui.button("📊 统计").clicked() {
+                        app.show_stats = true;
+                    }
+                    if ui.button("🆚 对比").on_hover_text("逐幕对比两版剧本的差异").clicked() {
+                        app.scenario_diff.get_or_insert_with(ScenarioDiffView::default);
+                    }
+                    if ui.button("📺 预览窗口").on_hover_text("打开只显示画面的独立窗口，可拖到投影仪并按 F11 全屏").clicked() {
+                        app.show_preview_window = true;
+                    }
+                    if ui.button("🎬 分镜").clicked() {
+                        app.show_storyboard = true;
+                    }
+                    if ui.button("📈 性能").clicked() {
+                        app.show_perf = true;
+                    }
+                    if ui.button("🖼 导出 GIF").clicked() {
+                        app.gif_export.slot_idx = app.selected_slot;
+                        app.show_gif_export = true;
+                    }
+                    if ui.button("🌐 导出 HTML").on_hover_text("导出为可在浏览器中点击播放的单个网页（背景+对话）").clicked() {
+                        if let Some(p) = rfd::FileDialog::new().add_filter("HTML", &["html"]).set_file_name("scenario.html").save_file() {
+                            cmd_to_send = Some(AppCommand::ExportHtml(p.display().to_string()));
+                        }
+                    }
+                    if ui.button("🎞 导出帧序列").clicked() {
+                        if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                            cmd_to_send = Some(AppCommand::StartOfflineRender { 
+                                out_dir: dir.display().to_string(), 
+                                fps: DEFAULT_RENDER_FPS 
+                            });
+                        }
+                    }
+                    ui.label("按固定帧率从第一幕自动播放并逐帧保存为 PNG");
+                });
+            });
+    }
+    app.panels.scenes = open;
+
+    // 📂 资源管理
+    let mut open = app.panels.resources;
+    if open {
+        egui::Window::new("📂 资源管理")
+            .open(&mut open)
+            .default_pos([520.0, 110.0])
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                // 槽位选择（已加载的角色显示缩略图，Ctrl+点击多选）
+                ui.horizontal(|ui| {
+                    ui.label("槽位:");
+                    for i in 0..5 { 
+                        if let Some(thumb) = &app.thumbnails[i] {
+                            ui.image((thumb.id(), Vec2::splat(24.0)));
+                        }
+                        if ui.radio(app.selected_slots.contains(&i), format!("[{}]", i)).clicked() { 
+                            if ui.input(|inp| inp.modifiers.command) {
+                                app.toggle_slot_selection(i);
+                            } else {
+                                app.select_slot(i);
+                            }
+                        } 
+                    }
+                    if ui.button("📸").on_hover_text("用当前姿势刷新缩略图").clicked() {
+                        app.refresh_thumbnail(app.selected_slot);
+                    }
+                });
+            
+                // 资源操作按钮
+                ui.horizontal(|ui| {
+                    if ui.button("📥 Spine").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("Spine", &["atlas", "zip"])
+                            .pick_file() 
+                        {
+                            cmd_to_send = Some(AppCommand::RequestLoad { 
+                                slot_idx: app.selected_slot, 
+                                path: p.display().to_string(),
+                                initial_anim: None,
+                                priority: LoadPriority::Current,
+                            });
+                        }
+                    }
+                    if ui.button("🖼 背景").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("Images", &["png", "jpg"])
+                            .pick_file() 
+                        {
+                            cmd_to_send = Some(AppCommand::LoadBackground(p.display().to_string()));
+                        }
+                    }
+                    if ui.button("🌧 前景").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("Images", &["png"])
+                            .pick_file() 
+                        {
+                            cmd_to_send = Some(AppCommand::LoadForeground(p.display().to_string()));
+                        }
+                    }
+                    if app.foreground.is_some() && ui.button("🗑 前景").clicked() {
+                        cmd_to_send = Some(AppCommand::ClearForeground);
+                    }
+                    if ui.add(egui::Button::new("🗑 移除").fill(Color32::from_rgb(150, 40, 40))).clicked() {
+                        for &idx in &app.selected_slots {
+                            let _ = app.tx.send(AppCommand::RemoveCharacter(idx));
+                        }
+                    }
+                    if ui.button("🏃 预览").clicked() { 
+                        app.show_anim_preview = true; 
+                    }
+                    ui.toggle_value(&mut app.solo_preview, "🔍 单人预览")
+                        .on_hover_text("只显示选中槽位的立绘，居中放大并隐藏对话框与其他角色");
+                    if ui.button("⚙ 设置").clicked() { 
+                        app.show_settings = true; 
+                    }
+                });
+
+                // 多选时的批量操作：统一动画与缩放
+                if app.selected_slots.len() > 1 {
+                    ui.horizontal(|ui| {
+                        let mut slots: Vec<usize> = app.selected_slots.iter().copied().collect();
+                        slots.sort_unstable();
+                        ui.label(format!("已选 {:?}", slots));
+                        egui::ComboBox::from_id_source("batch_anim")
+                            .selected_text("统一动画")
+                            .show_ui(ui, |ui| {
+                                for anim in app.common_selected_anims() {
+                                    if ui.selectable_label(false, &anim).clicked() {
+                                        for &slot_idx in &slots {
+                                            let _ = app.tx.send(AppCommand::SetAnimation { slot_idx, anim_name: anim.clone(), loop_anim: true });
+                                        }
+                                    }
+                                }
+                            });
+                        let mut scale = match app.characters.get(app.selected_slot) {
+                            Some(Some(char)) => char.user_scale,
+                            _ => 1.0,
+                        };
+                        if ui.add(egui::Slider::new(&mut scale, 0.2..=5.0).text("统一缩放")).changed() {
+                            for &idx in &slots {
+                                if let Some(Some(char)) = app.characters.get_mut(idx) {
+                                    char.user_scale = scale;
+                                }
+                            }
+                        }
+                    });
+                }
+
+                // 冻结画面（截图/调整姿势用）
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut app.animation_paused, "⏸ 暂停动画 (P)");
+                    if ui.add_enabled(app.animation_paused, egui::Button::new("⏭ 单帧步进")).clicked() {
+                        app.step_requested = true;
+                    }
+                });
+
+                // 排列预设
+                ui.horizontal(|ui| {
+                    ui.label("排列:");
+                    for preset in [LayoutPreset::Uniform, LayoutPreset::SplitSides, LayoutPreset::Pair] {
+                        if ui.selectable_label(app.layout_preset == preset, preset.label()).clicked() {
+                            app.apply_layout(preset);
+                        }
+                    }
+                });
+
+                // 景深排列：给选中角色设置深度（越远越小越暗，并画在后面）
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut app.depth_mode, "景深");
+                    if app.depth_mode {
+                        let mut depth = match app.characters.get(app.selected_slot) {
+                            Some(Some(char)) => char.depth,
+                            _ => 0.0,
+                        };
+                        if ui.add(egui::Slider::new(&mut depth, 0.0..=1.0).text("深度（0 近 ~ 1 远）")).changed() {
+                            for &idx in &app.selected_slots {
+                                if let Some(Some(char)) = app.characters.get_mut(idx) {
+                                    char.depth = depth;
+                                }
+                            }
+                        }
+                    }
+                });
+
+                // 穿插绘制：两个角色的部件交错排序（如一只手搭在另一个角色肩上）
+                ui.horizontal(|ui| {
+                    let mut enabled = app.interleave.is_some();
+                    if ui.checkbox(&mut enabled, "穿插").on_hover_text("两个角色的部件按统一深度交错绘制").changed() {
+                        app.interleave = enabled.then(|| {
+                            let other = (0..app.characters.len())
+                                .find(|&i| i != app.selected_slot && app.characters[i].is_some())
+                                .unwrap_or((app.selected_slot + 1) % app.characters.len());
+                            InterleavePair { first: app.selected_slot, second: other, bias: 0.0 }
+                        });
+                    }
+                    if let Some(pair) = &mut app.interleave {
+                        for (id, slot) in [("interleave_first", &mut pair.first), ("interleave_second", &mut pair.second)] {
+                            egui::ComboBox::from_id_source(id)
+                                .width(60.0)
+                                .selected_text(format!("槽位 {}", slot))
+                                .show_ui(ui, |ui| {
+                                    for i in 0..app.characters.len() {
+                                        ui.selectable_value(slot, i, format!("槽位 {}", i));
+                                    }
+                                });
+                        }
+                        ui.add(egui::Slider::new(&mut pair.bias, -1.0..=1.0).text("后者前移"));
+                    }
+                });
+
+                // 对话朝向：两个角色按左右位置自动翻转为面对面
+                ui.horizontal(|ui| {
+                    let mut enabled = app.facing_pair.is_some();
+                    if ui.checkbox(&mut enabled, "对话朝向").on_hover_text("两个角色按左右位置自动翻转，始终面对面").changed() {
+                        app.facing_pair = enabled.then(|| {
+                            let other = (0..app.characters.len())
+                                .find(|&i| i != app.selected_slot && app.characters[i].is_some())
+                                .unwrap_or((app.selected_slot + 1) % app.characters.len());
+                            (app.selected_slot, other)
+                        });
+                    }
+                    if let Some((a, b)) = &mut app.facing_pair {
+                        for (id, slot) in [("facing_first", a), ("facing_second", b)] {
+                            egui::ComboBox::from_id_source(id)
+                                .width(60.0)
+                                .selected_text(format!("槽位 {}", slot))
+                                .show_ui(ui, |ui| {
+                                    for i in 0..app.characters.len() {
+                                        ui.selectable_value(slot, i, format!("槽位 {}", i));
+                                    }
+                                });
+                        }
+                    }
+                });
+
+                // 命名构图：保存当前摆放 / 一键调用
+                ui.horizontal(|ui| {
+                    ui.label("构图:");
+                    let mut names: Vec<String> = app.layout_snapshots.keys().cloned().collect();
+                    names.sort();
+                    egui::ComboBox::from_id_source("layout_snapshots")
+                        .selected_text("调用…")
+                        .show_ui(ui, |ui| {
+                            for name in &names {
+                                if ui.selectable_label(false, name).clicked() {
+                                    app.apply_layout_snapshot(name);
+                                    app.snapshot_name = name.clone();
+                                }
+                            }
+                        });
+                    ui.add(egui::TextEdit::singleline(&mut app.snapshot_name).hint_text("构图名").desired_width(90.0));
+                    let name = app.snapshot_name.trim().to_string();
+                    if ui.add_enabled(!name.is_empty(), egui::Button::new("💾")).on_hover_text("保存当前摆放").clicked() {
+                        app.save_layout_snapshot(name.clone());
+                    }
+                    if ui.add_enabled(app.layout_snapshots.contains_key(&name), egui::Button::new("🗑")).on_hover_text("删除该构图").clicked() {
+                        app.delete_layout_snapshot(&name);
+                    }
+                });
+            });
+    }
+    app.panels.resources = open;
+
+    // 🎵 音频管理
+    let mut open = app.panels.audio;
+    if open {
+        egui::Window::new("🎵 音频管理")
+            .open(&mut open)
+            .default_pos([520.0, 420.0])
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    // 1. 导入音乐（循环播放）
+                    if ui.button("🔁 导入音乐(循环)").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("Audio", &["mp3", "wav", "ogg"])
+                            .pick_file() 
+                        {
+                            cmd_to_send = Some(AppCommand::PlayBgm(p.display().to_string()));
+                        }
+                    }
+                
+                    // 2. 音效（单次播放）
+                    if ui.button("🔊 音效").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("Audio", &["mp3", "wav", "ogg"])
+                            .pick_file() 
+                        {
+                            cmd_to_send = Some(AppCommand::PlaySe(p.display().to_string()));
+                        }
+                    }
+                
+                    // 3. 停止音乐
+                    if ui.add(egui::Button::new("⏹ 停止音乐").fill(Color32::from_rgb(150, 40, 40))).clicked() {
+                        cmd_to_send = Some(AppCommand::StopBgm);
+                    }
+                });
+
+                // 当前幕 BGM 的循环起点（intro + 循环段）
+                ui.horizontal(|ui| {
+                    let scene = &mut app.scenario.scenes[app.current_scene_idx];
+                    let mut has_loop = scene.bgm_loop_start.is_some();
+                    if ui.checkbox(&mut has_loop, "循环起点").changed() {
+                        scene.bgm_loop_start = if has_loop { Some(0.0) } else { None };
+                    }
+                    if let Some(start) = &mut scene.bgm_loop_start {
+                        ui.add(egui::DragValue::new(start).clamp_range(0.0..=600.0).speed(0.05).suffix(" 秒"));
+                    }
+                    if let Some(path) = scene.bgm_path.clone() {
+                        if ui.button("▶ 试听").clicked() {
+                            cmd_to_send = Some(AppCommand::Playback(Box::new(AppCommand::PlayBgm(path))));
+                        }
+                    }
+                });
+
+                // 当前幕语音（自动播放时等语音播完再推进）
+                ui.horizontal(|ui| {
+                    ui.label("🎙 语音:");
+                    let scene = &mut app.scenario.scenes[app.current_scene_idx];
+                    match scene.voice_path.clone() {
+                        Some(path) => {
+                            let name = std::path::Path::new(&path).file_name().map_or(path.clone(), |n| n.to_string_lossy().into_owned());
+                            ui.label(name).on_hover_text(&path);
+                            if ui.button("▶").clicked() {
+                                cmd_to_send = Some(AppCommand::PlayVoice(path));
+                            }
+                            if ui.button("✖").clicked() {
+                                scene.voice_path = None;
+                            }
+                        }
+                        None => { ui.weak("无"); }
+                    }
+                    if ui.button("选择…").clicked() {
+                        if let Some(p) = rfd::FileDialog::new()
+                            .add_filter("Audio", &["mp3", "wav", "ogg"])
+                            .pick_file() 
+                        {
+                            let path = p.display().to_string();
+                            scene.voice_path = Some(path.clone());
+                            cmd_to_send = Some(AppCommand::PlayVoice(path));
+                        }
+                    }
+                });
+
+                // 4. BGM 播放列表
+                ui.collapsing(format!("📃 播放列表 ({} 首)", app.bgm_playlist.tracks.len()), |ui| {
+                    let list = &mut app.bgm_playlist;
+                    let mut remove_track = None;
+                    for (i, track) in list.tracks.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            let name = std::path::Path::new(track).file_name().map_or(track.clone(), |n| n.to_string_lossy().into_owned());
+                            let playing = list.active && list.current == i;
+                            ui.label(if playing { format!("▶ {}", name) } else { name });
+                            if ui.button("❌").clicked() { 
+                                remove_track = Some(i); 
+                            }
+                        });
+                    }
+                    if let Some(i) = remove_track { 
+                        list.tracks.remove(i); 
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("模式:");
+                        for mode in [PlaylistMode::Sequential, PlaylistMode::Shuffle, PlaylistMode::RepeatOne] {
+                            ui.selectable_value(&mut list.mode, mode, mode.label());
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("➕ 添加曲目").clicked() {
+                            if let Some(paths) = rfd::FileDialog::new()
+                                .add_filter("Audio", &["mp3", "wav", "ogg"])
+                                .pick_files() 
+                            {
+                                list.tracks.extend(paths.iter().map(|p| p.display().to_string()));
+                            }
+                        }
+                        if ui.add_enabled(!list.tracks.is_empty(), egui::Button::new("▶ 播放列表")).clicked() {
+                            cmd_to_send = Some(AppCommand::SetBgmPlaylist(list.tracks.clone()));
+                        }
+                    });
+                });
+            });
+    }
+    app.panels.audio = open;
+
+    // 💬 对话 (当前幕)
+    let mut open = app.panels.dialogue;
+    if open {
+        egui::Window::new("💬 对话 (当前幕)")
+            .open(&mut open)
+            .default_pos([20.0, 330.0])
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                let speakers = app.known_speakers();
+                let scene = &mut app.scenario.scenes[app.current_scene_idx];
+
+                // 📝 制作备注：随剧本保存，播放与演示时不显示
+                egui::CollapsingHeader::new(if scene.notes.trim().is_empty() { "📝 备注" } else { "📝 备注（有）" })
+                    .id_source("scene_notes")
+                    .show(ui, |ui| {
+                        ui.add(egui::TextEdit::multiline(&mut scene.notes)
+                            .hint_text("只给制作者看的说明，如「这里要配激昂的音乐」")
+                            .desired_rows(2)
+                            .desired_width(f32::INFINITY));
+                    });
+            
+                // 说话者信息
+                ui.horizontal(|ui| {
+                    ui.label("名:"); 
+                    ui.add(egui::TextEdit::singleline(&mut scene.speaker_name).desired_width(80.0));
+                    ui.label("属:"); 
+                    ui.add(egui::TextEdit::singleline(&mut scene.speaker_aff).desired_width(80.0));
+                    ui.label("槽位:");
+                    egui::ComboBox::from_id_source("speaker_slot")
+                        .selected_text(scene.speaker_slot.map_or("无".to_string(), |i| format!("[{}]", i)))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut scene.speaker_slot, None, "无");
+                            for i in 0..5 {
+                                ui.selectable_value(&mut scene.speaker_slot, Some(i), format!("[{}]", i));
+                            }
+                        });
+                });
+
+                // 快速填入出现过的说话者
+                if !speakers.is_empty() {
+                    ui.horizontal(|ui| {
+                        ui.label("常用:");
+                        egui::ComboBox::from_id_source("speaker_preset")
+                            .selected_text("选择说话者…")
+                            .show_ui(ui, |ui| {
+                                for (name, aff) in &speakers {
+                                    let text = if aff.is_empty() { name.clone() } else { format!("{}（{}）", name, aff) };
+                                    if ui.selectable_label(scene.speaker_name == *name && scene.speaker_aff == *aff, text).clicked() {
+                                        scene.speaker_name = name.clone();
+                                        scene.speaker_aff = aff.clone();
+                                    }
+                                }
+                            });
+                    });
+                }
+            
+                // 🌟 TALK 按钮重新回归
+                ui.add(egui::TextEdit::multiline(&mut scene.dialogue_content).desired_width(f32::INFINITY));
+                ui.label(egui::RichText::new("单独一行 --- 可把对话分段，每次点击显示一段").small().weak());
+                if ui.button("▶ 发送对话 (TALK)").clicked() {
+                    app.sync_scene_to_ui();
+                    app.visible_count = 0; // 触发打字机
+                    app.trigger_speaker_bounce();
+                }
+
+                // 🔀 分支选项编辑
+                ui.label("分支选项:");
+                let scene_count = app.scenario.scenes.len();
+                let scene = &mut app.scenario.scenes[app.current_scene_idx];
+                let mut remove_choice = None;
+                for (i, (text, target)) in scene.choices.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(text).desired_width(160.0));
+                        ui.label("→");
+                        egui::ComboBox::from_id_source(("choice_target", i))
+                            .selected_text(format!("第 {} 幕", *target + 1))
+                            .show_ui(ui, |ui| {
+                                for t in 0..scene_count {
+                                    ui.selectable_value(target, t, format!("第 {} 幕", t + 1));
+                                }
+                            });
+                        if ui.button("❌").clicked() { 
+                            remove_choice = Some(i); 
+                        }
+                    });
+                }
+                if let Some(i) = remove_choice { 
+                    scene.choices.remove(i); 
+                }
+                if ui.button("➕ 添加选项").clicked() {
+                    scene.choices.push((String::new(), app.current_scene_idx));
+                }
+
+                // 🎞 进入本幕的过渡（未设置时使用全局默认）
+                ui.horizontal(|ui| {
+                    ui.label("过渡:");
+                    let mut use_custom = scene.transition.is_some();
+                    if ui.checkbox(&mut use_custom, "自定义").changed() {
+                        scene.transition = if use_custom { Some(app.default_transition) } else { None };
+                    }
+                    if let Some(config) = &mut scene.transition {
+                        transition_config_ui(ui, "scene_transition", config);
+                    } else {
+                        ui.label(format!("默认（{}）", app.default_transition.kind.label()));
+                    }
+                });
+
+                // 🌙 本幕环境光（与所有立绘颜色相乘）
+                ui.horizontal(|ui| {
+                    ui.label("环境光:");
+                    ui.color_edit_button_srgba(&mut scene.ambient_tint);
+                    if ui.button("↺ 无").clicked() {
+                        scene.ambient_tint = Color32::WHITE;
+                    }
+                });
+
+                // 🖼 本幕背景类型：图片 / 纯色 / 渐变
+                ui.horizontal(|ui| {
+                    ui.label("背景类型:");
+                    let start = match scene.background {
+                        SceneBackground::Solid(c) | SceneBackground::Gradient { start: c, .. } => c,
+                        SceneBackground::Image(_) => Color32::BLACK,
+                    };
+                    let options = [
+                        SceneBackground::Image(app.current_bg_path.clone()), // 切回图片时沿用舞台上最近显示的背景图
+                        SceneBackground::Solid(start),
+                        SceneBackground::Gradient { start, end: Color32::WHITE, horizontal: false },
+                    ];
+                    for option in options {
+                        let selected = std::mem::discriminant(&scene.background) == std::mem::discriminant(&option);
+                        if ui.selectable_label(selected, option.label()).clicked() && !selected {
+                            if let Some(path) = option.image_path() {
+                                cmd_to_send = Some(AppCommand::Playback(Box::new(AppCommand::LoadBackground(path.clone()))));
+                            }
+                            scene.background = option;
+                        }
+                    }
+                    match &mut scene.background {
+                        SceneBackground::Image(_) => {}
+                        SceneBackground::Solid(c) => { ui.color_edit_button_srgba(c); }
+                        SceneBackground::Gradient { start, end, horizontal } => {
+                            ui.color_edit_button_srgba(start);
+                            ui.label("→");
+                            ui.color_edit_button_srgba(end);
+                            ui.checkbox(horizontal, "横向");
+                        }
+                    }
+                });
+
+                // 🎞 本幕颜色滤镜（回忆/闪回等特殊氛围）
+                ui.horizontal(|ui| {
+                    ui.label("滤镜:");
+                    for filter in [ColorFilter::None, ColorFilter::Grayscale, ColorFilter::Sepia, ColorFilter::Invert] {
+                        ui.selectable_value(&mut scene.color_filter, filter, filter.label());
+                    }
+                });
+
+                // ⏱ 本幕定时推进（不依赖全局 AUTO，适合纯演出过场）
+                ui.horizontal(|ui| {
+                    let mut timed = scene.auto_advance_after.is_some();
+                    if ui.checkbox(&mut timed, "⏱ 定时推进").changed() {
+                        scene.auto_advance_after = if timed { Some(app.auto_delay) } else { None };
+                        app.scene_timer = 0.0;
+                    }
+                    if let Some(secs) = &mut scene.auto_advance_after {
+                        ui.add(egui::DragValue::new(secs).clamp_range(0.1..=120.0).speed(0.05).suffix(" 秒后进入下一幕"));
+                    }
+                });
+            });
+    }
+    app.panels.dialogue = open;
+
+    // ⌨ 控制台
+    let mut open = app.panels.console;
+    if open {
+        egui::Window::new("⌨ 控制台")
+            .open(&mut open)
+            .default_pos([20.0, 620.0])
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                // 命令行输入（Tab 补全第一个候选）
+                let completions = app.command_completions(&app.console_input);
+                ui.horizontal(|ui| {
+                    let res = ui.add(egui::TextEdit::singleline(&mut app.console_input).hint_text("CMD...").lock_focus(true));
+                    if res.has_focus() && ctx.input(|i| i.key_pressed(egui::Key::Tab)) {
+                        if let Some((completed, _)) = completions.first() {
+                            app.console_input = completed.clone();
+                            // 光标移到末尾，方便继续输入参数
+                            if let Some(mut state) = egui::TextEdit::load_state(ctx, res.id) {
+                                let end = egui::text::CCursor::new(app.console_input.chars().count());
+                                state.cursor.set_char_range(Some(egui::text::CCursorRange::one(end)));
+                                state.store(ctx, res.id);
                             }
                         }
-                    });
-                }
-                
-                // 角色加载成功回调
-                AppCommand::LoadSuccess(idx, obj, color_image, page_name, anims) => {
-                    if let Some(slot) = self.characters.get_mut(idx) {
-                        let mut loaded = *obj;
-                        // 加载纹理到GPU
-                        let handle = ctx.load_texture(page_name, color_image, egui::TextureOptions::LINEAR);
-                        loaded.texture_id = Some(handle.id()); 
-                        loaded._texture = Some(handle);
-                        *slot = Some(loaded);
                     }
+                    if ui.button("发送").clicked() || (res.lost_focus() && ctx.input(|i| i.key_pressed(egui::Key::Enter))) {
+                        let input = app.console_input.clone(); 
+                        app.queue_commands(&input); // 粘贴的多行文本按行依次执行
+                        app.console_input.clear(); 
+                        res.request_focus();
+                    }
+                });
+                for (_, hint) in completions.iter().take(6) {
+                    ui.label(egui::RichText::new(hint).small().weak());
                 }
-                
-                // 移除角色
-                AppCommand::RemoveCharacter(idx) => { 
-                    self.characters[idx] = None; 
-                }
-                
-                // 加载背景图片
-                AppCommand::LoadBackground(path) => {
-                    let tx_cb = self.tx.clone();
-                    let path_clone = path.clone();
-                    thread::spawn(move || {
-                        if let Ok(img) = image::open(&path_clone) {
-                            let c_img = egui::ColorImage::from_rgba_unmultiplied([img.width() as _, img.height() as _], 
-                                img.to_rgba8().as_raw()
-                            );
-                            let _ = tx_cb.send(AppCommand::LoadBackgroundSuccess(c_img));
-                        }
-                    });
-                    self.scenario.scenes[self.current_scene_idx].bg_path = Some(path);
-                }
-                
-                // 背景加载成功回调
-                AppCommand::LoadBackgroundSuccess(c_img) => {
-                    self.background = Some(ctx.load_texture("bg", c_img, egui::TextureOptions::LINEAR));
-                }
-                
-                // 设置动画
-                AppCommand::SetAnimation { slot_idx, anim_name, loop_anim } => {
-                     if let Some(Some(char)) = self.characters.get_mut(slot_idx) {
-                         let _ = char.set_animation_by_name(&anim_name, loop_anim);
-                     }
-                }
-                
-                // 播放BGM
-                AppCommand::PlayBgm(path) => {
-                    let tx_cb = self.tx.clone();
-                    let path_clone = path.clone();
-                    thread::spawn(move || { 
-                        if let Ok(d) = std::fs::read(&path_clone) { 
-                            let _ = tx_cb.send(AppCommand::AudioReady(d, true)); 
+            
+                // 日志显示
+                egui::ScrollArea::vertical()
+                    .stick_to_bottom(true)
+                    .max_height(60.0)
+                    .show(ui, |ui| { 
+                        for log in &app.console_logs { 
+                            if is_comment_line(log) {
+                                ui.label(egui::RichText::new(log).weak());
+                            } else {
+                                ui.label(log); 
+                            }
                         } 
                     });
-                    self.scenario.scenes[self.current_scene_idx].bgm_path = Some(path);
-                }
+            });
+    }
+    app.panels.console = open;
 
-                // 播放音效
-                AppCommand::PlaySe(path) => {
-                    let tx_cb = self.tx.clone();
-                    let path_clone = path.clone();
-                    thread::spawn(move || { 
-                        if let Ok(d) = std::fs::read(&path_clone) { 
-                            let _ = tx_cb.send(AppCommand::AudioReady(d, false)); 
-                        } 
+    // 资源检查报告窗口
+    if let Some(report) = &app.resource_report {
+        let mut open = true;
+        let mut jump_to = None;
+        egui::Window::new("🔍 资源检查")
+            .open(&mut open)
+            .default_size([420.0, 300.0])
+            .show(ctx, |ui| {
+                if report.is_empty() {
+                    ui.label("✅ 所有资源路径均有效");
+                } else {
+                    ui.label(format!("发现 {} 处缺失资源（点击定位到该幕）:", report.len()));
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        for item in report {
+                            let text = format!("第 {} 幕 · {} · {}", item.scene_idx + 1, item.kind, item.path);
+                            if ui.selectable_label(item.scene_idx == app.current_scene_idx, text).clicked() {
+                                jump_to = Some(item.scene_idx);
+                            }
+                        }
                     });
                 }
-                
-                // 音频数据就绪
-                AppCommand::AudioReady(data, is_bgm) => {
-                    if let Some(mgr) = &self.audio_manager { 
-                        if is_bgm { 
-                            mgr.play_bgm(data); 
-                        } else { 
-                            mgr.play_se(data); 
-                        } 
-                    }
-                }
-                
-                // 停止BGM
-                AppCommand::StopBgm => { 
-                    if let Some(mgr) = &self.audio_manager { 
-                        mgr.stop_bgm(); 
-                    } 
-                }
-                
-                _ => {}
-            }
+            });
+        if let Some(idx) = jump_to {
+            app.current_scene_idx = idx.min(app.scenario.scenes.len() - 1);
+            app.sync_scene_to_ui();
+            app.visible_count = app.target_chars.len();
+        }
+        if !open {
+            app.resource_report = None;
         }
     }
-}
 
-// ============================================================================
-// 主应用循环实现
-// ============================================================================
+    // 剧本统计窗口
+    if app.show_stats {
+        let stats = app.scenario.stats();
+        egui::Window::new("📊 剧本统计")
+            .open(&mut app.show_stats)
+            .default_size([360.0, 320.0])
+            .show(ctx, |ui| {
+                egui::Grid::new("stats_summary").striped(true).show(ui, |ui| {
+                    ui.label("总幕数"); ui.label(stats.scene_count.to_string()); ui.end_row();
+                    ui.label("对话总字数"); ui.label(stats.char_count.to_string()); ui.end_row();
+                    ui.label("背景数"); ui.label(stats.bg_count.to_string()); ui.end_row();
+                    ui.label("BGM 数"); ui.label(stats.bgm_count.to_string()); ui.end_row();
+                });
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("stats_speakers").striped(true).num_columns(3).show(ui, |ui| {
+                        ui.strong("说话者"); ui.strong("出场幕数"); ui.strong("台词字数"); ui.end_row();
+                        for (name, scenes, chars) in &stats.speakers {
+                            ui.label(name); ui.label(scenes.to_string()); ui.label(chars.to_string()); ui.end_row();
+                        }
+                    });
+                });
+            });
+    }
 
-impl eframe::App for AefrApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // 处理异步事件
-        self.handle_async_events(ctx);
-        let dt = ctx.input(|i| i.stable_dt); // 获取稳定帧时间
-        
-        // 🌟 打字机效果更新
-        if self.show_dialogue && self.visible_count < self.target_chars.len() {
-            self.type_timer += dt;
-            if self.type_timer > 0.03 { // 每0.03秒显示一个字符
-                self.visible_count += 1; 
-                self.type_timer = 0.0; 
+    // 剧本对比窗口：旧版本与新版本（文件或当前剧本）逐幕对比
+    if let Some(mut view) = app.scenario_diff.take() {
+        let mut open = true;
+        let mut recompute = false;
+        let mut jump_to = None;
+        egui::Window::new("🆚 剧本对比")
+            .open(&mut open)
+            .default_size([520.0, 420.0])
+            .show(ctx, |ui| {
+                let pick = || rfd::FileDialog::new().add_filter("JSON", &["json"]).pick_file();
+                let mut load = |p: std::path::PathBuf| -> Option<(String, Scenario)> {
+                    match read_scenario_file(&p) {
+                        Ok(s) => Some((p.file_name().map_or(p.display().to_string(), |n| n.to_string_lossy().into_owned()), s)),
+                        Err(e) => { app.log(format!("[错误] 剧本读取失败: {}", e)); None }
+                    }
+                };
+                ui.horizontal(|ui| {
+                    ui.label("旧版本:");
+                    ui.label(view.old.as_ref().map_or("未选择", |(name, _)| name.as_str()));
+                    if ui.button("📂").clicked() {
+                        if let Some(loaded) = pick().and_then(&mut load) {
+                            view.old = Some(loaded);
+                            recompute = true;
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("新版本:");
+                    ui.label(view.new.as_ref().map_or("当前剧本", |(name, _)| name.as_str()));
+                    if ui.button("📂").clicked() {
+                        if let Some(loaded) = pick().and_then(&mut load) {
+                            view.new = Some(loaded);
+                            recompute = true;
+                        }
+                    }
+                    if view.new.is_some() && ui.button("↺ 当前剧本").clicked() {
+                        view.new = None;
+                        recompute = true;
+                    }
+                    if view.new.is_none() && ui.button("🔄 重新对比").on_hover_text("当前剧本修改后重新计算差异").clicked() {
+                        recompute = true;
+                    }
+                });
+                ui.separator();
+                if view.old.is_none() {
+                    ui.label(egui::RichText::new("选择旧版本剧本后显示差异").weak());
+                    return;
+                }
+                ui.label(format!("相同 {} 幕，变化 {} 处", view.unchanged, view.changes.len()));
+                let (added_color, removed_color, modified_color) = (Color32::from_rgb(120, 220, 120), Color32::from_rgb(255, 110, 110), Color32::from_rgb(255, 200, 80));
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (k, change) in view.changes.iter().enumerate() {
+                        let (title, color, target) = match change {
+                            SceneChange::Added(n) => (format!("＋ 新增 新第 {} 幕", n + 1), added_color, Some(*n)),
+                            SceneChange::Removed(o) => (format!("－ 删除 旧第 {} 幕", o + 1), removed_color, None),
+                            SceneChange::Modified { old, new, .. } => (format!("✎ 修改 旧第 {} 幕 → 新第 {} 幕", old + 1, new + 1), modified_color, Some(*new)),
+                        };
+                        ui.horizontal(|ui| {
+                            ui.colored_label(color, title);
+                            // 与当前剧本对比时可直接跳到对应幕
+                            if let (None, Some(n)) = (&view.new, target) {
+                                if ui.small_button("跳转").clicked() {
+                                    jump_to = Some(n);
+                                }
+                            }
+                        });
+                        if let SceneChange::Modified { fields, .. } = change {
+                            egui::Grid::new(("diff_fields", k)).num_columns(3).show(ui, |ui| {
+                                for f in fields {
+                                    ui.label(format!("    {}", f.field));
+                                    ui.label(egui::RichText::new(&f.old).color(removed_color).strikethrough());
+                                    ui.label(egui::RichText::new(&f.new).color(added_color));
+                                    ui.end_row();
+                                }
+                            });
+                        }
+                    }
+                });
+            });
+        if recompute {
+            if let Some((_, old)) = &view.old {
+                let (changes, unchanged) = diff_scenarios(old, view.new.as_ref().map_or(&app.scenario, |(_, s)| s));
+                view.changes = changes;
+                view.unchanged = unchanged;
             }
         }
-
-        // 根据屏幕分辨率计算缩放比例
-        let screen = ctx.screen_rect();
-        let scale_factor = screen.height() / BASE_HEIGHT;
-        
-        // 更新角色位置（底部等距排列）
-        for (i, slot) in self.characters.iter_mut().enumerate() {
-            if let Some(char) = slot {
-                char.scale = 0.45 * scale_factor;
-                let x_percent = 0.15 + (i as f32 * 0.175); // 横向位置百分比
-                char.position = Pos2::new(
-                    screen.width() * x_percent, 
-                    screen.bottom() + (30.0 * scale_factor) // 从屏幕底部开始
-                );
-            }
+        if let Some(n) = jump_to.filter(|&n| n < app.scenario.scenes.len()) {
+            app.go_to_scene(n);
         }
+        if open {
+            app.scenario_diff = Some(view);
+        }
+    }
 
-        // 🌟 使用调度器并行更新所有角色的骨骼动画
-        self.scheduler.run_parallel(|| {
-            self.characters.par_iter_mut().for_each(|slot| {
-                if let Some(char) = slot { 
-                    char.update_parallel(dt); 
+    // 新建剧本向导：选择模板生成预设的幕
+    if app.show_new_wizard {
+        let mut open = true;
+        let mut create = false;
+        egui::Window::new("📄 新建剧本")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                for template in [ScenarioTemplate::Blank, ScenarioTemplate::TwoPersonTalk, ScenarioTemplate::NarrationOpening] {
+                    ui.radio_value(&mut app.new_template, template, template.label());
                 }
+                ui.label(egui::RichText::new(app.new_template.description()).weak());
+                ui.separator();
+                let button = if app.dirty {
+                    ui.colored_label(Color32::from_rgb(255, 180, 80), "⚠ 当前剧本有未保存的修改，新建将丢弃这些修改");
+                    "放弃修改并新建"
+                } else {
+                    "创建"
+                };
+                create = ui.button(button).clicked();
             });
-        });
+        if create {
+            app.new_scenario(app.new_template);
+            open = false;
+        }
+        app.show_new_wizard = open;
+    }
 
-        // 主绘制区域
-        egui::CentralPanel::default()
-            .frame(egui::Frame::none().fill(Color32::BLACK))
+    // 性能面板：帧率与立绘资源缓存的内存占用
+    if app.show_perf {
+        let mut open = true;
+        let mut trim = false;
+        let mb = |bytes: usize| bytes as f32 / (1024.0 * 1024.0);
+        egui::Window::new("📈 性能")
+            .open(&mut open)
+            .default_size([360.0, 300.0])
             .show(ctx, |ui| {
-                let rect = ui.max_rect();
-                
-                // 绘制背景图片（如有）
-                if let Some(bg) = &self.background {
-                    let img_size = bg.size_vec2();
-                    let scale = (rect.width() / img_size.x).max(rect.height() / img_size.y); // 保持比例缩放
-                    ui.painter().image(
-                        bg.id(), 
-                        Rect::from_center_size(rect.center(), img_size * scale), 
-                        Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)), 
-                        Color32::WHITE
-                    );
-                }
-                
-                // 绘制所有角色
-                for char in self.characters.iter().flatten() { 
-                    char.paint(ui); 
-                }
-                
-                // 右上角按钮
-                draw_top_right_buttons(ui, rect, &mut self.is_auto_enabled);
-                
-                // 🌟 对话框渲染逻辑：只有当【已提交】的对话内容不为空时，才显示对话框
-                if self.show_dialogue {
-                    let committed_text: String = self.target_chars.iter().collect();
-                    if !committed_text.trim().is_empty() {
-                        let scene = &self.scenario.scenes[self.current_scene_idx];
-                        let text: String = self.target_chars.iter().take(self.visible_count).collect();
-                        if draw_ba_dialogue(
-                            ui, rect, 
-                            &scene.speaker_name, 
-                            &scene.speaker_aff, 
-                            &text, 
-                            self.visible_count >= self.target_chars.len()
-                        ) { 
-                            self.visible_count = self.target_chars.len(); // 点击跳过打字机
+                let dt = ctx.input(|i| i.stable_dt).max(1e-4);
+                let loaded = app.characters.iter().filter(|c| c.is_some()).count();
+                egui::Grid::new("perf_summary").striped(true).show(ui, |ui| {
+                    ui.label("帧率"); ui.label(format!("{:.0} FPS", 1.0 / dt)); ui.end_row();
+                    ui.label("已加载角色"); ui.label(format!("{} / {}", loaded, app.characters.len())); ui.end_row();
+                    ui.label("屏幕外剔除"); ui.label(app.culled_count.to_string()); ui.end_row();
+                    ui.label("加载中"); ui.label(app.pending_loads.to_string()); ui.end_row();
+                });
+                ui.separator();
+
+                let used = mb(app.resource_cache.total_bytes());
+                let limit = app.cache_limit_mb as f32;
+                ui.label(format!("立绘缓存: {:.1} MB / {} MB（估算）", used, app.cache_limit_mb));
+                ui.add(egui::ProgressBar::new((used / limit).min(1.0)));
+                ui.horizontal(|ui| {
+                    ui.label("上限:");
+                    if ui.add(egui::DragValue::new(&mut app.cache_limit_mb).clamp_range(64..=8192).speed(8).suffix(" MB")).changed() {
+                        trim = true;
+                    }
+                    if ui.button("🧹 清理未使用").on_hover_text("卸载所有没有槽位在用的缓存资源").clicked() {
+                        for path in app.resource_cache.evict(0) {
+                            app.log(format!("[缓存] 已卸载 {}", path));
                         }
                     }
+                });
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("perf_cache").striped(true).num_columns(3).show(ui, |ui| {
+                        ui.strong("资源"); ui.strong("大小"); ui.strong("状态"); ui.end_row();
+                        let mut entries: Vec<_> = app.resource_cache.entries.iter().collect();
+                        entries.sort_by(|a, b| b.1.last_used.cmp(&a.1.last_used));
+                        for (path, entry) in entries {
+                            let name = std::path::Path::new(path).file_name().map_or(path.clone(), |n| n.to_string_lossy().into_owned());
+                            ui.label(name).on_hover_text(path);
+                            ui.label(format!("{:.1} MB", mb(entry.bytes)));
+                            ui.label(if entry.in_use() { "使用中" } else { "空闲" });
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+        if trim {
+            app.trim_resource_cache();
+        }
+        app.show_perf = open;
+    }
+
+    // GIF 导出：选择槽位与动画，渲染一个完整周期
+    if app.show_gif_export {
+        let mut open = true;
+        egui::Window::new("🖼 导出 GIF")
+            .open(&mut open)
+            .default_size([320.0, 200.0])
+            .show(ctx, |ui| {
+                let job = &mut app.gif_export;
+                egui::ComboBox::from_label("槽位")
+                    .selected_text(format!("槽位 {}", job.slot_idx))
+                    .show_ui(ui, |ui| {
+                        for (i, char) in app.characters.iter().enumerate() {
+                            if char.is_some() {
+                                ui.selectable_value(&mut job.slot_idx, i, format!("槽位 {}", i));
+                            }
+                        }
+                    });
+                let anims = app.characters.get(job.slot_idx).and_then(|c| c.as_ref()).map(|c| c.get_anim_names()).unwrap_or_default();
+                if !anims.contains(&job.anim) {
+                    job.anim = anims.first().cloned().unwrap_or_default();
                 }
-                
-                // 控制台开关按钮
-                if ui.put(Rect::from_min_size(Pos2::new(10.0, 10.0), Vec2::new(60.0, 30.0)), 
-                    egui::Button::new("CMD")).clicked() 
-                { 
-                    self.console_open = !self.console_open; 
-                }
-                
-                // 控制台面板
-                if self.console_open { 
-                    draw_creator_panel(ctx, self); 
+                egui::ComboBox::from_label("动画")
+                    .selected_text(job.anim.clone())
+                    .show_ui(ui, |ui| {
+                        for anim in &anims {
+                            ui.selectable_value(&mut job.anim, anim.clone(), anim);
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut job.fps).clamp_range(1.0..=50.0).speed(0.5).suffix(" FPS"));
+                    ui.add(egui::DragValue::new(&mut job.size).clamp_range(64..=2048).speed(8).suffix(" px"));
+                    ui.add(egui::DragValue::new(&mut job.loops).clamp_range(0..=100).prefix("循环 "));
+                    ui.label(if job.loops == 0 { "（无限）" } else { "次" });
+                });
+                ui.label(egui::RichText::new("透明背景，帧数由动画时长决定，尺寸为长边像素").small().weak());
+                if ui.add_enabled(!job.anim.is_empty(), egui::Button::new("💾 导出…")).clicked() {
+                    if let Some(p) = rfd::FileDialog::new()
+                        .add_filter("GIF", &["gif"])
+                        .set_file_name(format!("{}.gif", job.anim))
+                        .save_file() 
+                    {
+                        job.path = p.display().to_string();
+                        cmd_to_send = Some(AppCommand::ExportGif(job.clone()));
+                    }
                 }
             });
-        
-        ctx.request_repaint(); // 请求下一帧重绘
+        app.show_gif_export = open;
     }
-}
-
-// ============================================================================
-// UI 组件函数
-// ============================================================================
 
-/// 绘制右上角按钮（AUTO/MENU）
-fn draw_top_right_buttons(ui: &mut egui::Ui, screen: Rect, is_auto: &mut bool) {
-    let (btn_w, btn_h, margin) = (90.0, 32.0, 20.0);
-    
-    // AUTO按钮
-    let auto_rect = Rect::from_min_size(
-        Pos2::new(screen.right() - btn_w * 2.0 - margin - 10.0, margin), 
-        Vec2::new(btn_w, btn_h)
-    );
-    if ui.allocate_rect(auto_rect, egui::Sense::click()).clicked() { 
-        *is_auto = !*is_auto; 
+    // 分镜视图：网格排列每一幕的缩略图，点击跳转
+    if app.show_storyboard {
+        let mut open = true;
+        let mut jump_to = None;
+        let cell = Vec2::new(STORYBOARD_THUMB_SIZE[0] as f32, STORYBOARD_THUMB_SIZE[1] as f32);
+        egui::Window::new("🎬 分镜")
+            .open(&mut open)
+            .default_size([640.0, 480.0])
+            .show(ctx, |ui| {
+                ui.label(egui::RichText::new("未浏览的幕只显示背景（标有「仅背景」），浏览到该幕时生成含立绘的完整缩略图").small().weak());
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    let spacing = ui.spacing().item_spacing.x;
+                    let columns = ((ui.available_width() + spacing) / (cell.x + spacing)).floor().max(1.0) as usize;
+                    egui::Grid::new("storyboard_grid").spacing([spacing, 8.0]).show(ui, |ui| {
+                        for (i, scene) in app.scenario.scenes.iter().enumerate() {
+                            ui.vertical(|ui| {
+                                let response = match app.scene_thumbnails.get(&i) {
+                                    Some(SceneThumbnail::Full(tex)) => ui.add(egui::ImageButton::new((tex.id(), cell)).selected(i == app.current_scene_idx)),
+                                    Some(SceneThumbnail::Backdrop(tex)) => {
+                                        let response = ui.add(egui::ImageButton::new((tex.id(), cell)).selected(i == app.current_scene_idx));
+                                        let corner = response.rect.left_bottom() + Vec2::new(6.0, -4.0);
+                                        ui.painter().text(corner, egui::Align2::LEFT_BOTTOM, "仅背景", egui::FontId::proportional(12.0), Color32::from_white_alpha(200));
+                                        response
+                                    }
+                                    Some(SceneThumbnail::Pending) | None => {
+                                        let (rect, response) = ui.allocate_exact_size(cell, egui::Sense::click());
+                                        ui.painter().rect_filled(rect, 4.0, Color32::from_gray(40));
+                                        ui.painter().text(rect.center(), egui::Align2::CENTER_CENTER, "未预览", egui::FontId::proportional(14.0), Color32::GRAY);
+                                        if i == app.current_scene_idx {
+                                            ui.painter().rect_stroke(rect, 4.0, egui::Stroke::new(2.0, ui.visuals().selection.bg_fill));
+                                        }
+                                        response
+                                    }
+                                };
+                                if response.clicked() {
+                                    jump_to = Some(i);
+                                }
+                                let speaker = if scene.speaker_name.is_empty() { "（旁白）" } else { scene.speaker_name.as_str() };
+                                if scene.notes.trim().is_empty() {
+                                    ui.label(format!("#{} {}", i + 1, speaker));
+                                } else {
+                                    ui.label(format!("#{} {} 📝", i + 1, speaker)).on_hover_text(scene.notes.trim());
+                                }
+                            });
+                            if (i + 1) % columns == 0 {
+                                ui.end_row();
+                            }
+                        }
+                    });
+                });
+            });
+        if let Some(i) = jump_to {
+            app.current_scene_idx = i;
+            app.apply_scene();
+            app.visible_count = app.target_chars.len();
+        }
+        app.show_storyboard = open;
     }
-    ui.painter().rect_filled(auto_rect, 4.0, 
-        if *is_auto { Color32::from_rgb(255, 215, 0) } else { Color32::WHITE }
-    );
-    ui.painter().text(
-        auto_rect.center(), 
-        egui::Align2::CENTER_CENTER, 
-        "AUTO", 
-        egui::FontId::proportional(18.0), 
-        Color32::from_rgb(20, 30, 50)
-    );
-    
-    // MENU按钮
-    ui.painter().rect_filled(
-        Rect::from_min_size(Pos2::new(screen.right() - btn_w - margin, margin), Vec2::new(btn_w, btn_h)), 
-        4.0, 
-        Color32::WHITE
-    );
-    ui.painter().text(
-        Pos2::new(screen.right() - btn_w / 2.0 - margin, margin + btn_h / 2.0), 
-        egui::Align2::CENTER_CENTER, 
-        "MENU", 
-        egui::FontId::proportional(18.0), 
-        Color32::from_rgb(20, 30, 50)
-    );
-}
-
-/// 绘制蔚蓝档案风格对话框
-/// 返回值：是否被点击（用于跳过打字机）
-fn draw_ba_dialogue(ui: &mut egui::Ui, screen: Rect, name: &str, affiliation: &str, content: &str, is_finished: bool) -> bool {
-    let box_h = screen.height() * DIALOGUE_BOX_RATIO; // 对话框高度
-    let box_rect = Rect::from_min_max(Pos2::new(screen.left(), screen.bottom() - box_h), screen.max);
-    let line_y = box_rect.top() + (box_h * 0.30); // 分割线Y位置
-    
-    // 1. 绘制暗蓝色背景 (高不透明度)
-    let dark_blue_opaque = Color32::from_rgba_unmultiplied(12, 18, 28, 252);
-    ui.painter().rect_filled(
-        Rect::from_min_max(Pos2::new(screen.left(), line_y), screen.max), 
-        0.0, 
-        dark_blue_opaque
-    );
-    
-    // 2. 🌟 严谨：使用 Mesh 绘制名字区域的垂直渐变淡出
-    let gradient_rect = Rect::from_min_max(box_rect.left_top(), Pos2::new(screen.right(), line_y));
-    let mut mesh = Mesh::default();
-    // 使用 Unmultiplied 确保混合模式正确
-    let color_bottom = Color32::from_rgba_unmultiplied(12, 18, 28, 245);
-    let color_top = Color32::from_rgba_unmultiplied(12, 18, 28, 0);
-    
-    // 添加渐变四边形的四个顶点
-    mesh.vertices.push(Vertex { pos: gradient_rect.left_top(), uv: Pos2::ZERO, color: color_top });
-    mesh.vertices.push(Vertex { pos: gradient_rect.right_top(), uv: Pos2::ZERO, color: color_top });
-    mesh.vertices.push(Vertex { pos: gradient_rect.right_bottom(), uv: Pos2::ZERO, color: color_bottom });
-    mesh.vertices.push(Vertex { pos: gradient_rect.left_bottom(), uv: Pos2::ZERO, color: color_bottom });
-    mesh.add_triangle(0, 1, 2);
-    mesh.add_triangle(0, 2, 3);
-    ui.painter().add(Shape::mesh(mesh));
 
-    // 对话框点击区域（用于跳过）
-    let resp = ui.allocate_rect(box_rect, egui::Sense::click());
-    
-    // 绘制分割线
-    let pad_x = (screen.width() * 0.08).max(100.0); // 两侧内边距
-    ui.painter().line_segment([Pos2::new(pad_x, line_y), Pos2::new(screen.right() - pad_x, line_y)], 
-        Stroke::new(1.5, Color32::from_rgb(100, 120, 150))
-    );
+    // 动画预览窗口
+    if app.show_anim_preview {
+        egui::Window::new("动作")
+            .open(&mut app.show_anim_preview)
+            .show(ctx, |ui| {
+                 if let Some(Some(char)) = app.characters.get(app.selected_slot) {
+                    // 动画列表：输入关键词实时过滤（不区分大小写），点击直接播放
+                    let anims = char.get_anim_names();
+                    if !anims.is_empty() {
+                        let current = char.current_anim_name();
+                        ui.heading(current.as_deref().unwrap_or("（无动画）"));
+                        ui.horizontal(|ui| {
+                            ui.label("🔍");
+                            ui.add(egui::TextEdit::singleline(&mut app.anim_filter).hint_text("搜索动画名，如 idle / talk").desired_width(160.0));
+                            if !app.anim_filter.is_empty() && ui.small_button("✖").clicked() {
+                                app.anim_filter.clear();
+                            }
+                        });
+                        let keyword = app.anim_filter.trim().to_lowercase();
+                        let matched: Vec<&String> = anims.iter().filter(|name| name.to_lowercase().contains(&keyword)).collect();
+                        ui.label(egui::RichText::new(format!("{} / {} 个动画", matched.len(), anims.len())).small().weak());
+                        egui::ScrollArea::vertical().id_source("anim_list").max_height(200.0).show(ui, |ui| {
+                            for name in matched {
+                                if ui.selectable_label(current.as_ref() == Some(name), name).clicked() {
+                                    cmd_to_send = Some(AppCommand::SetAnimation { 
+                                        slot_idx: app.selected_slot, 
+                                        anim_name: name.clone(), 
+                                        loop_anim: true 
+                                    });
+                                }
+                            }
+                        });
+                    }
 
-    // 绘制说话者姓名和所属
-    if !name.is_empty() {
-        let n_size = (box_h * 0.16).clamp(22.0, 30.0);
-        
-        // 1. 先生成 Galley 拿到实际渲染高度，再决定坐标
-        let n_gal = ui.painter().layout_no_wrap(name.into(), egui::FontId::proportional(n_size), Color32::WHITE);
-        let n_width = n_gal.rect.width();
-        let n_height = n_gal.rect.height();
+                    // 播放进度条：拖拽时暂停全部动画并定位到对应时间
+                    if let Some((time, duration)) = char.track_time() {
+                        let mut t = time;
+                        let resp = ui.add(
+                            egui::Slider::new(&mut t, 0.0..=duration.max(0.001))
+                                .text(format!("/ {:.2} 秒", duration))
+                                .fixed_decimals(2)
+                        );
+                        if resp.drag_started() {
+                            app.animation_paused = true;
+                        }
+                        if resp.changed() {
+                            cmd_to_send = Some(AppCommand::SeekAnimation { slot_idx: app.selected_slot, time: t });
+                        }
+                    }
 
-        // 🌟 修复点 1：位置不再从顶部往下算，而是【依托分割线往上推算】
-        // 这样不管什么字体，文字底部永远贴近 line_y
-        let margin_bottom = 4.0; // 距离分割线的固定留白，可微调
-        let n_pos = Pos2::new(box_rect.left() + pad_x, line_y - n_height - margin_bottom);
+                    // 可选部件（眼镜、帽子、武器等）：按插槽切换显隐，有多个附件时可选其一
+                    let options = char.slot_attachment_options();
+                    ui.collapsing(format!("🧩 部件 ({} 个插槽)", options.len()), |ui| {
+                        egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                            for (slot_name, names) in &options {
+                                let current = char.slot_attachment(slot_name);
+                                let set = |attachment: Option<String>| AppCommand::SetAttachment {
+                                    slot_idx: app.selected_slot,
+                                    slot_name: slot_name.clone(),
+                                    attachment,
+                                };
+                                ui.horizontal(|ui| {
+                                    let mut visible = current.is_some();
+                                    if ui.checkbox(&mut visible, slot_name).changed() {
+                                        cmd_to_send = Some(set(if visible { names.first().cloned() } else { None }));
+                                    }
+                                    if names.len() > 1 {
+                                        egui::ComboBox::from_id_source(("slot_attachment", slot_name))
+                                            .selected_text(current.as_deref().unwrap_or("隐藏"))
+                                            .show_ui(ui, |ui| {
+                                                for name in names {
+                                                    if ui.selectable_label(current.as_ref() == Some(name), name).clicked() {
+                                                        cmd_to_send = Some(set(Some(name.clone())));
+                                                    }
+                                                }
+                                            });
+                                    }
+                                });
+                            }
+                        });
+                    });
 
-        if !affiliation.is_empty() {
-            let aff_size = n_size * 0.75;
-            let aff_gal = ui.painter().layout_no_wrap(
-                affiliation.into(), 
-                egui::FontId::proportional(aff_size), 
-                Color32::from_rgb(100, 200, 255)
-            );
-            let aff_height = aff_gal.rect.height();
-            
-            // 🌟 修复点 2：修复基线对齐
-            let visual_compensation = -3.0; // 如果觉得还偏高就把加大，觉得偏低就减小
-            let y_offset = n_height - aff_height + visual_compensation; 
-            
-            ui.painter().galley(n_pos, n_gal.clone(), Color32::WHITE);
-            ui.painter().galley(
-                n_pos + Vec2::new(n_width + 15.0, y_offset), 
-                aff_gal, 
-                Color32::from_rgb(100, 200, 255)
-            );
-        } else {
-            ui.painter().galley(n_pos, n_gal, Color32::WHITE);
-        }
+                    // 皮肤下拉选择
+                    let skins = char.get_skin_names();
+                    if !skins.is_empty() {
+                        let current = char.current_skin().unwrap_or_default();
+                        egui::ComboBox::from_label("皮肤")
+                            .selected_text(&current)
+                            .show_ui(ui, |ui| {
+                                for skin in &skins {
+                                    if ui.selectable_label(*skin == current, skin).clicked() {
+                                        cmd_to_send = Some(AppCommand::SetSkin {
+                                            slot_idx: app.selected_slot,
+                                            skin_name: skin.clone()
+                                        });
+                                    }
+                                }
+                            });
+                    }
+                 }
+            });
+    }
+
+    // 当前幕在面板中被编辑过（切换幕不算修改）
+    let (idx_before, scene_before) = scene_before;
+    if app.scenario.scenes.get(idx_before).map_or(false, |s| *s != scene_before) {
+        app.dirty = true;
     }
     
-    // 🌟 严谨：内容上移，紧贴分割线
-    ui.painter().text(
-        Pos2::new(box_rect.left() + pad_x, line_y + box_h * 0.05), 
-        egui::Align2::LEFT_TOP, 
-        content, 
-        egui::FontId::proportional((box_h * 0.13).clamp(18.0, 25.0)), 
-        Color32::WHITE
-    );
-    
-    // 绘制"继续"指示器（当文本显示完成时）
-    if is_finished {
-        let tri_center = Pos2::new(
-            screen.right() - pad_x, 
-            screen.bottom() - (box_h * 0.15) + (ui.input(|i| i.time) * 3.0).sin() as f32 * 3.0 // 上下浮动效果
-        );
-        let ts = box_h * 0.04; // 三角形大小
-        ui.painter().add(Shape::convex_polygon(
-            vec![
-                tri_center + Vec2::new(-ts, -ts), 
-                tri_center + Vec2::new(ts, -ts), 
-                tri_center + Vec2::new(0.0, ts)
-            ], 
-            Color32::from_rgb(0, 180, 255), 
-            Stroke::NONE
-        ));
+    // 发送待处理命令
+    if let Some(cmd) = cmd_to_send { 
+        let _ = app.tx.send(cmd); 
     }
-    resp.clicked() // 返回是否被点击
 }
 
-/// 绘制创作者控制面板
-fn draw_creator_panel(ctx: &egui::Context, app: &mut AefrApp) {
+/// 绘制设置面板
+fn draw_settings_window(ctx: &egui::Context, app: &mut AefrApp) {
     let mut cmd_to_send = None; // 待发送的命令
-    egui::Window::new("创作者面板 - AEFR v1.1.1")
-        .default_size([500.0, 600.0])
+    let mut open = app.show_settings;
+    egui::Window::new("⚙ 设置")
+        .open(&mut open)
+        .default_size([320.0, 240.0])
         .show(ctx, |ui| {
-            // 🎬 剧本幕数管理
-            ui.heading("🎬 剧本幕数管理");
+            ui.heading("🔤 对话框字体");
+            
+            // 可选字体族：内嵌字体 + 运行时注册的外部字体
+            let mut families = vec![("内嵌字体".to_string(), FontFamily::Proportional)];
+            families.extend(app.custom_fonts.iter().map(|n| (n.clone(), FontFamily::Name(n.as_str().into()))));
+            let family_label = |f: &FontFamily| families.iter().find(|(_, fam)| fam == f).map_or("内嵌字体".to_string(), |(n, _)| n.clone());
+
+            for (label, font) in [("名字", &mut app.dialogue_fonts.name), ("内容", &mut app.dialogue_fonts.content)] {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}:", label));
+                    egui::ComboBox::from_id_source(("font_family", label))
+                        .selected_text(family_label(&font.family))
+                        .show_ui(ui, |ui| {
+                            for (name, family) in &families {
+                                ui.selectable_value(&mut font.family, family.clone(), name);
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut font.size, 12.0..=48.0).text("字号"));
+                });
+            }
+
+            ui.separator();
+            ui.heading("🖼 立绘贴图");
             ui.horizontal(|ui| {
-                if ui.button("⬅ 上一幕").clicked() && app.current_scene_idx > 0 {
-                    app.current_scene_idx -= 1; 
-                    app.sync_scene_to_ui(); 
-                    app.visible_count = app.target_chars.len(); // 立即显示全部文本
+                ui.label("Alpha:");
+                let mut changed = false;
+                for alpha in [TextureAlpha::Straight, TextureAlpha::Premultiplied] {
+                    changed |= ui.selectable_value(&mut app.texture_alpha, alpha, alpha.label()).changed();
                 }
-                ui.label(format!(" 第 {} / {} 幕 ", app.current_scene_idx + 1, app.scenario.scenes.len()));
-                if ui.button("下一幕 ➡").clicked() && app.current_scene_idx < app.scenario.scenes.len() - 1 {
-                    app.current_scene_idx += 1; 
-                    app.sync_scene_to_ui(); 
-                    app.visible_count = app.target_chars.len();
+                if changed {
+                    app.reload_characters(); // 贴图需要按新方式重新上传
                 }
-                ui.separator();
-                if ui.button("➕ 增加一幕").clicked() {
-                    let mut new_scene = app.scenario.scenes[app.current_scene_idx].clone();
-                    new_scene.dialogue_content.clear();
-                    app.scenario.scenes.insert(app.current_scene_idx + 1, new_scene);
-                    app.current_scene_idx += 1; 
-                    app.sync_scene_to_ui();
+            });
+            ui.label("边缘有黑边时选择「已预乘」，有白边时选择「普通」");
+            ui.horizontal(|ui| {
+                ui.label("过滤:");
+                let mut changed = false;
+                for (filter, label) in [(egui::TextureFilter::Linear, "平滑（Linear）"), (egui::TextureFilter::Nearest, "像素风（Nearest）")] {
+                    changed |= ui.selectable_value(&mut app.texture_filter, filter, label).changed();
                 }
-                if ui.button("❌ 删除").clicked() && app.scenario.scenes.len() > 1 {
-                    app.scenario.scenes.remove(app.current_scene_idx);
-                    app.current_scene_idx = app.current_scene_idx.min(app.scenario.scenes.len() - 1);
-                    app.sync_scene_to_ui();
+                if changed {
+                    app.reupload_character_textures(ctx);
                 }
             });
-            
-            // 跳转输入
+            // 贴图分辨率：拖动结束后才重新上传，避免拖动过程中反复缩放大贴图
             ui.horizontal(|ui| {
-                ui.label("跳转:");
-                let mut jump = app.current_scene_idx + 1;
-                let len = app.scenario.scenes.len();
-                if ui.add(egui::DragValue::new(&mut jump).clamp_range(1..=len)).changed() {
-                    app.current_scene_idx = jump - 1; 
-                    app.sync_scene_to_ui(); 
-                    app.visible_count = app.target_chars.len();
+                ui.label("贴图分辨率:");
+                let response = ui.add(egui::Slider::new(&mut app.texture_scale, TEXTURE_SCALE_RANGE).step_by(0.05).suffix("×"));
+                if !response.dragged() && app.texture_scale != app.applied_texture_scale {
+                    app.reupload_character_textures(ctx);
                 }
             });
+            ui.label("低于 1 时立绘贴图缩小后再上传，节省显存（画面分辨率不变，立绘会略模糊）");
+
+            ui.checkbox(&mut app.hot_reload, "热重载：立绘文件修改后自动重新加载");
+            ui.horizontal(|ui| {
+                ui.label("加载超时:");
+                ui.add(egui::DragValue::new(&mut app.load_timeout).clamp_range(0.0..=600.0).speed(1.0).suffix(" 秒"));
+                ui.weak("（0 表示不限）");
+            });
+            ui.horizontal(|ui| {
+                ui.label("失败重试:");
+                ui.add(egui::DragValue::new(&mut app.load_retry.attempts).clamp_range(0..=10).suffix(" 次"));
+                ui.add(egui::DragValue::new(&mut app.load_retry.delay).clamp_range(0.1..=10.0).speed(0.1).prefix("间隔 ").suffix(" 秒"));
+                ui.weak("（每次递增，文件不存在时不重试）");
+            });
+            if ui.checkbox(&mut app.preload_next, "预加载下一幕的立绘（排在当前幕之后）").changed() {
+                app.schedule_preloads();
+            }
+            ui.checkbox(&mut app.shadow.enabled, "脚底阴影");
+            ui.add_enabled_ui(app.shadow.enabled, |ui| {
+                ui.add(egui::Slider::new(&mut app.shadow.opacity, 0.0..=1.0).text("浓度"));
+                ui.add(egui::Slider::new(&mut app.shadow.offset, -40.0..=40.0).text("下移").suffix(" px"));
+            });
+            ui.horizontal(|ui| {
+                ui.label("切幕依次入场间隔:");
+                ui.add(egui::DragValue::new(&mut app.auto_stagger).clamp_range(0.0..=3.0).speed(0.05).suffix(" 秒"));
+                ui.weak("（0 表示同时出现）");
+            });
 
             ui.separator();
-            
-            // 保存/加载剧本
+            ui.heading("💬 对话框样式");
             ui.horizontal(|ui| {
-                if ui.button("💾 保存剧本").clicked() {
-                    if let Ok(json) = serde_json::to_string_pretty(&app.scenario) {
-                        if let Some(p) = rfd::FileDialog::new()
-                            .set_file_name("scenario.json")
-                            .save_file() 
-                        {
-                            let _ = std::fs::write(p, json);
+                for style in [DialogueStyle::BlueArchive, DialogueStyle::ClassicBar, DialogueStyle::Centered] {
+                    ui.selectable_value(&mut app.dialogue_style, style, style.label());
+                }
+            });
+
+            ui.checkbox(&mut app.show_speaker_avatar, "对话框显示说话者头像（槽位缩略图）");
+            ui.checkbox(&mut app.dialogue_auto_height, "对话框高度随内容自适应（蔚蓝档案风格，过长时缩小字号）");
+
+            // 文字描边/投影（蔚蓝档案风格）：浅色背景或半透明对话框上提升可读性
+            ui.horizontal(|ui| {
+                ui.label("文字效果:");
+                for kind in [TextEffectKind::None, TextEffectKind::Outline, TextEffectKind::Shadow] {
+                    ui.selectable_value(&mut app.text_effect.kind, kind, kind.label());
+                }
+                if app.text_effect.kind != TextEffectKind::None {
+                    ui.color_edit_button_srgba(&mut app.text_effect.color);
+                    ui.add(egui::DragValue::new(&mut app.text_effect.width).clamp_range(0.5..=6.0).speed(0.1).suffix(" px"));
+                }
+            });
+
+            // "继续"指示器：形状、动画方式、颜色（默认跟随对话框样式）与动画参数
+            ui.horizontal(|ui| {
+                ui.label("继续指示器:");
+                let indicator = &mut app.continue_indicator;
+                for shape in [IndicatorShape::Triangle, IndicatorShape::Dot, IndicatorShape::Arrow] {
+                    ui.selectable_value(&mut indicator.shape, shape, shape.label());
+                }
+                ui.separator();
+                for motion in [IndicatorMotion::Float, IndicatorMotion::Blink, IndicatorMotion::Spin] {
+                    ui.selectable_value(&mut indicator.motion, motion, motion.label());
+                }
+            });
+            ui.horizontal(|ui| {
+                let indicator = &mut app.continue_indicator;
+                let mut custom = indicator.color.is_some();
+                if ui.checkbox(&mut custom, "自定义颜色").changed() {
+                    indicator.color = custom.then_some(Color32::from_rgb(0, 180, 255));
+                }
+                if let Some(color) = &mut indicator.color {
+                    ui.color_edit_button_srgba(color);
+                }
+                ui.add(egui::DragValue::new(&mut indicator.speed).clamp_range(0.5..=12.0).speed(0.1).prefix("频率 "));
+                if indicator.motion == IndicatorMotion::Float {
+                    ui.add(egui::DragValue::new(&mut indicator.amplitude).clamp_range(0.0..=12.0).speed(0.1).prefix("幅度 ").suffix(" px"));
+                }
+            });
+
+            // 打字机推进单位：英文台词可按词显示，需要整句弹出时选整段
+            ui.horizontal(|ui| {
+                ui.label("打字机单位:");
+                for unit in [TypeUnit::Char, TypeUnit::Word, TypeUnit::Paragraph] {
+                    ui.selectable_value(&mut app.type_unit, unit, unit.label());
+                }
+            });
+
+            // 名字框贴图（蔚蓝档案风格），九宫格拉伸适应名字长度
+            ui.horizontal(|ui| {
+                ui.label("名字框贴图:");
+                match &app.name_box_path {
+                    Some(path) => {
+                        let name = std::path::Path::new(path).file_name().map_or(path.clone(), |n| n.to_string_lossy().into_owned());
+                        ui.label(name).on_hover_text(path);
+                        if ui.button("✖").on_hover_text("恢复默认渐变").clicked() {
+                            cmd_to_send = Some(AppCommand::LoadNameBox(None));
                         }
                     }
+                    None => { ui.weak("默认渐变"); }
                 }
-                if ui.button("📂 重载剧本").clicked() {
+                if ui.button("📂 选择").clicked() {
                     if let Some(p) = rfd::FileDialog::new()
-                        .add_filter("JSON", &["json"])
+                        .add_filter("Image", &["png", "webp"])
                         .pick_file() 
                     {
-                        if let Ok(data) = std::fs::read_to_string(p) {
-                            if let Ok(s) = serde_json::from_str::<Scenario>(&data) {
-                                app.scenario = s; 
-                                app.current_scene_idx = 0; 
-                                app.sync_scene_to_ui(); 
-                                app.visible_count = app.target_chars.len();
-                            }
-                        }
+                        cmd_to_send = Some(AppCommand::LoadNameBox(Some(p.display().to_string())));
                     }
                 }
             });
+            if app.name_box.is_some() {
+                ui.horizontal(|ui| {
+                    ui.label("九宫格边宽:");
+                    ui.add(egui::DragValue::new(&mut app.name_box_border).clamp_range(0.0..=256.0).speed(0.5).suffix(" px"));
+                });
+            }
 
             ui.separator();
-            ui.heading("📂 资源管理");
-            
-            // 槽位选择
+            ui.heading("🎚 BGM 闪避");
             ui.horizontal(|ui| {
-                ui.label("槽位:");
-                for i in 0..5 { 
-                    if ui.radio_value(&mut app.selected_slot, i, format!("[{}]", i)).clicked() { 
-                        app.preview_anim_idx = 0; 
-                    } 
-                }
+                ui.checkbox(&mut app.bgm_duck.enabled, "语音/音效播放时压低 BGM");
             });
-            
-            // 资源操作按钮
+            ui.add_enabled_ui(app.bgm_duck.enabled, |ui| {
+                ui.add(egui::Slider::new(&mut app.bgm_duck.level, 0.0..=1.0).text("压低到"));
+                ui.add(egui::Slider::new(&mut app.bgm_duck.release, 0.0..=3.0).text("恢复时间").suffix(" 秒"));
+            });
+            ui.checkbox(&mut app.show_spectrum, "画面左上角显示 BGM 频谱");
+
+            ui.separator();
+            ui.heading("⌨ 打字机音效");
             ui.horizontal(|ui| {
-                if ui.button("📥 Spine").clicked() {
+                ui.checkbox(&mut app.type_sound.enabled, "启用");
+                ui.add(egui::Slider::new(&mut app.type_sound.volume, 0.0..=1.0).text("音量"));
+                ui.add(egui::Slider::new(&mut app.type_sound.every, 1..=5).text("每 N 字"));
+            });
+            ui.horizontal(|ui| {
+                if ui.button("📂 选择音效").clicked() {
                     if let Some(p) = rfd::FileDialog::new()
-                        .add_filter("Atlas", &["atlas"])
+                        .add_filter("Audio", &["mp3", "wav", "ogg"])
                         .pick_file() 
                     {
-                        cmd_to_send = Some(AppCommand::RequestLoad { 
-                            slot_idx: app.selected_slot, 
-                            path: p.display().to_string() 
-                        });
+                        cmd_to_send = Some(AppCommand::LoadTypeSound(p.display().to_string()));
                     }
                 }
-                if ui.button("🖼 背景").clicked() {
-                    if let Some(p) = rfd::FileDialog::new()
-                        .add_filter("Images", &["png", "jpg"])
-                        .pick_file() 
-                    {
-                        cmd_to_send = Some(AppCommand::LoadBackground(p.display().to_string()));
+                ui.label(app.type_sound.path.as_deref().map_or("未设置", |p| {
+                    std::path::Path::new(p).file_name().and_then(|n| n.to_str()).unwrap_or(p)
+                }));
+            });
+
+            ui.separator();
+            ui.heading("😮 自动表情");
+            ui.checkbox(&mut app.auto_expression, "按对话关键词切换说话者表情（按顺序匹配第一条）");
+            let mut remove_rule = None;
+            for (i, rule) in app.expression_rules.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut rule.keyword).hint_text("关键词").desired_width(80.0));
+                    ui.label("→");
+                    ui.add(egui::TextEdit::singleline(&mut rule.anim_name).hint_text("表情动画名").desired_width(140.0));
+                    if ui.button("❌").clicked() { 
+                        remove_rule = Some(i); 
+                    }
+                });
+            }
+            if let Some(i) = remove_rule { 
+                app.expression_rules.remove(i); 
+            }
+            if ui.button("➕ 添加规则").clicked() {
+                app.expression_rules.push(ExpressionRule { keyword: String::new(), anim_name: String::new() });
+            }
+
+            ui.separator();
+            ui.heading("👁 自动眨眼");
+            ui.horizontal(|ui| {
+                let mut changed = ui.checkbox(&mut app.auto_blink, "启用").changed();
+                ui.label("动画名:");
+                changed |= ui.add(egui::TextEdit::singleline(&mut app.blink_anim_name).desired_width(100.0)).changed();
+                if changed {
+                    let name = Some(app.blink_anim_name.as_str()).filter(|_| app.auto_blink);
+                    for char in app.characters.iter_mut().flatten() {
+                        char.setup_blink(name);
                     }
                 }
-                if ui.add(egui::Button::new("🗑 移除").fill(Color32::from_rgb(150, 40, 40))).clicked() {
-                    cmd_to_send = Some(AppCommand::RemoveCharacter(app.selected_slot));
-                }
-                if ui.button("🏃 预览").clicked() { 
-                    app.show_anim_preview = true; 
+            });
+            ui.label("模型中没有该名称的动画时不眨眼");
+
+            ui.separator();
+            ui.heading("🖱 看向鼠标");
+            ui.horizontal(|ui| {
+                let was_on = app.look_at_mouse;
+                let old_bone = app.look_at_bone.clone();
+                ui.checkbox(&mut app.look_at_mouse, "启用");
+                ui.label("骨骼名:");
+                let bone_changed = ui.add(egui::TextEdit::singleline(&mut app.look_at_bone).desired_width(100.0)).changed();
+                if (was_on && !app.look_at_mouse) || bone_changed {
+                    let new_bone = std::mem::replace(&mut app.look_at_bone, old_bone);
+                    app.clear_look_at();
+                    app.look_at_bone = new_bone;
                 }
             });
+            ui.label("头部骨骼随鼠标位置偏转（最多 ±30°），模型中没有该骨骼时不生效");
+
+            ui.separator();
+            ui.heading("🗣 说话弹跳");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut app.talk_bounce.enabled, "启用");
+                ui.add(egui::Slider::new(&mut app.talk_bounce.amplitude, 0.0..=40.0).text("幅度"));
+                ui.add(egui::Slider::new(&mut app.talk_bounce.duration, 0.1..=1.0).text("时长"));
+            });
+            ui.checkbox(&mut app.speaker_label, "在说话者头顶显示名字标签（使用「看向鼠标」的骨骼，找不到时用包围盒顶部）");
+
+            ui.separator();
+            ui.heading("🎞 默认切幕过渡");
+            ui.horizontal(|ui| {
+                transition_config_ui(ui, "default_transition", &mut app.default_transition);
+            });
 
-            // --- 音频管理模块 ---
             ui.separator();
-            ui.heading("🎵 音频管理");
             ui.horizontal(|ui| {
-                // 1. 导入音乐（循环播放）
-                if ui.button("🔁 导入音乐(循环)").clicked() {
+                if ui.button("📂 添加字体").clicked() {
                     if let Some(p) = rfd::FileDialog::new()
-                        .add_filter("Audio", &["mp3", "wav", "ogg"])
+                        .add_filter("Font", &["ttf", "otf"])
                         .pick_file() 
                     {
-                        cmd_to_send = Some(AppCommand::PlayBgm(p.display().to_string()));
+                        cmd_to_send = Some(AppCommand::LoadFont(p.display().to_string()));
                     }
                 }
-                
-                // 2. 音效（单次播放）
-                if ui.button("🔊 音效").clicked() {
+                if ui.button("😀 添加回退字体").on_hover_text("主字体缺少的 emoji/符号会用此字体显示").clicked() {
                     if let Some(p) = rfd::FileDialog::new()
-                        .add_filter("Audio", &["mp3", "wav", "ogg"])
+                        .add_filter("Font", &["ttf", "otf"])
                         .pick_file() 
                     {
-                        cmd_to_send = Some(AppCommand::PlaySe(p.display().to_string()));
+                        cmd_to_send = Some(AppCommand::LoadFallbackFont(p.display().to_string()));
                     }
                 }
-                
-                // 3. 停止音乐
-                if ui.add(egui::Button::new("⏹ 停止音乐").fill(Color32::from_rgb(150, 40, 40))).clicked() {
-                    cmd_to_send = Some(AppCommand::StopBgm);
+                if ui.button("↺ 恢复默认").clicked() {
+                    app.dialogue_fonts = DialogueFonts::default();
                 }
             });
+        });
+    app.show_settings = open;
 
-            ui.separator();
-            ui.heading("💬 对话 (当前幕)");
-            let scene = &mut app.scenario.scenes[app.current_scene_idx];
-            
-            // 说话者信息
-            ui.horizontal(|ui| {
-                ui.label("名:"); 
-                ui.add(egui::TextEdit::singleline(&mut scene.speaker_name).desired_width(80.0));
-                ui.label("属:"); 
-                ui.add(egui::TextEdit::singleline(&mut scene.speaker_aff).desired_width(80.0));
-            });
-            
-            // 🌟 TALK 按钮重新回归
-            ui.add(egui::TextEdit::multiline(&mut scene.dialogue_content).desired_width(f32::INFINITY));
-            if ui.button("▶ 发送对话 (TALK)").clicked() {
-                app.sync_scene_to_ui();
-                app.visible_count = 0; // 触发打字机
-            }
+    // 发送待处理命令
+    if let Some(cmd) = cmd_to_send { 
+        let _ = app.tx.send(cmd); 
+    }
+}
 
-            ui.separator();
-            
-            // 命令行输入
+/// 绘制未保存退出确认对话框（保存 / 不保存 / 取消）
+fn draw_exit_confirm(ctx: &egui::Context, app: &mut AefrApp) {
+    egui::Window::new("⚠ 未保存的修改")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+        .show(ctx, |ui| {
+            ui.label("有未保存的修改，确定退出吗？");
             ui.horizontal(|ui| {
-                let res = ui.add(egui::TextEdit::singleline(&mut app.console_input).hint_text("CMD..."));
-                if ui.button("发送").clicked() || (res.lost_focus() && ctx.input(|i| i.key_pressed(egui::Key::Enter))) {
-                    let input = app.console_input.clone(); 
-                    app.parse_and_send_command(&input); 
-                    app.console_input.clear(); 
-                    res.request_focus();
+                if ui.button("💾 保存").clicked() {
+                    // 已有文件时直接覆盖保存，否则选择保存位置；取消选择则留在编辑器
+                    let path = app.current_file.clone().or_else(|| {
+                        rfd::FileDialog::new().set_file_name("scenario.json").save_file()
+                    });
+                    if let Some(p) = path {
+                        match app.save_scenario_to(p) {
+                            Ok(()) => {
+                                app.allow_close = true;
+                                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                            }
+                            Err(e) => app.log(format!("[错误] 剧本保存失败: {}", e)),
+                        }
+                    }
+                }
+                if ui.button("不保存").clicked() {
+                    app.allow_close = true;
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+                if ui.button("取消").clicked() {
+                    app.show_exit_confirm = false;
                 }
             });
-            
-            // 日志显示
-            egui::ScrollArea::vertical()
-                .stick_to_bottom(true)
-                .max_height(60.0)
-                .show(ui, |ui| { 
-                    for log in &app.console_logs { 
-                        ui.label(log); 
-                    } 
-                });
         });
+}
 
-    // 动画预览窗口
-    if app.show_anim_preview {
-        egui::Window::new("动作")
-            .open(&mut app.show_anim_preview)
-            .show(ctx, |ui| {
-                 if let Some(Some(char)) = app.characters.get(app.selected_slot) {
-                    let anims = char.get_anim_names();
-                    if !anims.is_empty() {
-                        if app.preview_anim_idx >= anims.len() { 
-                            app.preview_anim_idx = 0; 
-                        }
-                        ui.heading(&anims[app.preview_anim_idx]);
-                        ui.horizontal(|ui| {
-                            if ui.button("⬅").clicked() { 
-                                app.preview_anim_idx = (app.preview_anim_idx + anims.len() - 1) % anims.len(); 
-                                cmd_to_send = Some(AppCommand::SetAnimation { 
-                                    slot_idx: app.selected_slot, 
-                                    anim_name: anims[app.preview_anim_idx].clone(), 
-                                    loop_anim: true 
-                                }); 
-                            }
-                            if ui.button("➡").clicked() { 
-                                app.preview_anim_idx = (app.preview_anim_idx + 1) % anims.len(); 
-                                cmd_to_send = Some(AppCommand::SetAnimation { 
-                                    slot_idx: app.selected_slot, 
-                                    anim_name: anims[app.preview_anim_idx].clone(), 
-                                    loop_anim: true 
-                                }); 
-                            }
-                        });
+/// 压缩包立绘选择：包内有多个 atlas 时逐个列出，选中后按 `pack.zip::包内路径` 重新发起加载
+fn draw_zip_choice(ctx: &egui::Context, app: &mut AefrApp) {
+    let choice = match &app.zip_choice {
+        Some(c) => c,
+        None => return,
+    };
+    let mut chosen = None;
+    let mut cancel = false;
+    egui::Window::new("📦 选择立绘")
+        .collapsible(false)
+        .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+        .show(ctx, |ui| {
+            let name = std::path::Path::new(&choice.archive).file_name().map_or(choice.archive.clone(), |n| n.to_string_lossy().into_owned());
+            ui.label(format!("{} 中有多个立绘，请选择要加载到槽位 {} 的一个：", name, choice.slot_idx));
+            egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for entry in &choice.entries {
+                    if ui.button(entry).clicked() {
+                        chosen = Some(entry.clone());
                     }
-                 }
+                }
             });
+            if ui.button("取消").clicked() {
+                cancel = true;
+            }
+        });
+    if let Some(entry) = chosen {
+        if let Some(choice) = app.zip_choice.take() {
+            let _ = app.tx.send(AppCommand::RequestLoad { 
+                slot_idx: choice.slot_idx, 
+                path: format!("{}{}{}", choice.archive, ZIP_ENTRY_SEPARATOR, entry), 
+                initial_anim: choice.initial_anim,
+                priority: LoadPriority::Current,
+            });
+        }
+    } else if cancel {
+        app.zip_choice = None;
     }
-    
-    // 发送待处理命令
-    if let Some(cmd) = cmd_to_send { 
-        let _ = app.tx.send(cmd); 
-    }
+}
+
+/// 过渡配置编辑控件（类型下拉 + 时长）
+fn transition_config_ui(ui: &mut egui::Ui, id: &str, config: &mut TransitionConfig) {
+    egui::ComboBox::from_id_source(id)
+        .selected_text(config.kind.label())
+        .show_ui(ui, |ui| {
+            for kind in [TransitionKind::Cut, TransitionKind::FadeBlack, TransitionKind::FadeWhite, TransitionKind::Dissolve] {
+                ui.selectable_value(&mut config.kind, kind, kind.label());
+            }
+        });
+    ui.add(egui::DragValue::new(&mut config.duration).clamp_range(0.0..=5.0).speed(0.05).suffix(" 秒"));
+}
+
+/// 读取字体文件，以文件名（不含扩展名）作为字体名
+fn read_font_file(path: &str) -> Result<(String, Vec<u8>), String> {
+    let name = std::path::Path::new(path)
+        .file_stem()
+        .map_or_else(|| path.to_string(), |s| s.to_string_lossy().into_owned());
+    std::fs::read(path).map(|data| (name, data)).map_err(|e| e.to_string())
 }
 
 /// 设置嵌入式字体（优化中文字体渲染）
-fn setup_embedded_font(ctx: &egui::Context) {
+/// egui 默认字体中的 emoji 字体保留在内嵌字体之后作为缺字回退
+/// 返回设置后的字体定义，供之后注册外部字体时在其基础上追加
+fn setup_embedded_font(ctx: &egui::Context) -> FontDefinitions {
     let mut fonts = FontDefinitions::default();
     let font_bytes = include_bytes!("font.ttf"); // 内嵌字体文件
     fonts.font_data.insert("my_font".to_owned(), FontData::from_static(font_bytes));
     fonts.families.get_mut(&FontFamily::Proportional).unwrap().insert(0, "my_font".to_owned());
     fonts.families.get_mut(&FontFamily::Monospace).unwrap().insert(0, "my_font".to_owned());
-    ctx.set_fonts(fonts);
+    ctx.set_fonts(fonts.clone());
+    fonts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 不带窗口与持久化状态的编辑器实例
+    fn test_app() -> (AefrApp, egui::Context) {
+        let ctx = egui::Context::default();
+        (AefrApp::with_context(&ctx, None), ctx)
+    }
+
+    /// 每个测试独立的临时目录
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("aefr_test_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 写一份单幕剧本，背景与 BGM 指向不存在的文件（切幕时仍会照常发出加载命令）
+    fn write_chapter(dir: &std::path::Path, name: &str) -> std::path::PathBuf {
+        let mut scene = Scene::default();
+        scene.background = SceneBackground::Image(Some(format!("{}_bg.png", name)));
+        scene.bgm_path = Some(format!("{}_bgm.ogg", name));
+        let path = dir.join(format!("{}.json", name));
+        std::fs::write(&path, serde_json::to_string(&Scenario { scenes: vec![scene] }).unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn tokenize_quoted_path_with_spaces() {
+        assert_eq!(tokenize_command(r#"load 0 "C:\My Folder\a.atlas" Idle"#), vec!["load", "0", r"C:\My Folder\a.atlas", "Idle"]);
+    }
+
+    #[test]
+    fn tokenize_escaped_quotes() {
+        assert_eq!(tokenize_command(r#"talk "say \"hi\"" \"x"#), vec!["talk", r#"say "hi""#, r#""x"#]);
+    }
+
+    #[test]
+    fn tokenize_unterminated_quote_keeps_rest() {
+        assert_eq!(tokenize_command(r#"bg "D:\bg room.png"#), vec!["bg", r"D:\bg room.png"]);
+    }
+
+    #[test]
+    fn tokenize_consecutive_spaces_and_empty_quotes() {
+        assert_eq!(tokenize_command("anim   0 \t Idle  "), vec!["anim", "0", "Idle"]);
+        assert_eq!(tokenize_command(r#"set name """#), vec!["set", "name", ""]);
+    }
+
+    #[test]
+    fn tokenize_empty_input() {
+        assert!(tokenize_command("").is_empty());
+        assert!(tokenize_command("   ").is_empty());
+    }
+
+    /// 单页 2×2 贴图、只有一个区域 "px" 的 atlas（Spine 3.8 格式）
+    const TEST_ATLAS: &str = "\npage.png\nsize: 2,2\nformat: RGBA8888\nfilter: Linear,Linear\nrepeat: none\npx\n  rotate: false\n  xy: 0, 0\n  size: 2, 2\n  orig: 2, 2\n  offset: 0, 0\n  index: -1\n";
+
+    /// 只有根骨骼、没有附件的骨架
+    const EMPTY_SKELETON: &str = r#"{"skeleton":{"spine":"3.8.99"},"bones":[{"name":"root"}]}"#;
+
+    /// 从内存中的 atlas 与 JSON 骨架构造角色（不读文件、不上传贴图）
+    fn test_model(skeleton_json: &str) -> SpineObject {
+        let atlas = Arc::new(Atlas::new(TEST_ATLAS.as_bytes(), std::path::Path::new("")).unwrap());
+        let skeleton_data = SkeletonJson::new(atlas).read_skeleton_data(skeleton_json.as_bytes()).unwrap();
+        SpineObject::from_skeleton_data(Arc::new(skeleton_data), None).0
+    }
+
+    fn test_page() -> Arc<egui::ColorImage> {
+        Arc::new(egui::ColorImage::new([2, 2], Color32::WHITE))
+    }
+
+    #[test]
+    fn shared_pages_survive_partial_removal() {
+        let (mut app, ctx) = test_app();
+        let page = test_page();
+        for slot in 0..2 {
+            app.install_character(&ctx, slot, test_model(EMPTY_SKELETON), vec![("page.png".into(), page.clone())]);
+        }
+        let texture_id = |app: &AefrApp, slot: usize| app.characters[slot].as_ref().unwrap().texture_ids[0].1;
+        assert_eq!(texture_id(&app, 0), texture_id(&app, 1), "同一份贴图应共用 GPU 纹理");
+
+        let _ = app.tx.send(AppCommand::RemoveCharacter(0));
+        app.handle_async_events(&ctx);
+        assert!(app.characters[0].is_none());
+        let remaining = texture_id(&app, 1);
+        assert!(ctx.tex_manager().read().meta(remaining).is_some(), "另一槽位仍在用的纹理不应被释放");
+    }
+
+    #[test]
+    fn shared_texture_matches_by_arc_identity() {
+        let (mut app, ctx) = test_app();
+        let page = test_page();
+        assert!(app.shared_page(&page).is_none());
+        app.install_character(&ctx, 0, test_model(EMPTY_SKELETON), vec![("page.png".into(), page.clone())]);
+        let (handle, _) = app.shared_page(&page).expect("同一个 Arc 应复用已上传的纹理");
+        assert_eq!(handle.id(), app.characters[0].as_ref().unwrap().texture_ids[0].1);
+        let same_pixels = Arc::new((*page).clone());
+        assert!(app.shared_page(&same_pixels).is_none(), "内容相同但不是同一份副本时不共用");
+    }
+
+    /// 带一个空插槽的骨架（插槽只用来提供颜色）
+    const SLOT_SKELETON: &str = r#"{"skeleton":{"spine":"3.8.99"},"bones":[{"name":"root"}],"slots":[{"name":"body","bone":"root"}]}"#;
+
+    /// 单个三角形的附件几何（UV 为左下直角三角形）与白色插槽颜色
+    fn triangle_geometry(positions: [[f32; 2]; 3]) -> (AttachmentGeometry, rusty_spine::Color) {
+        let mut geometry = AttachmentGeometry::default();
+        geometry.positions = positions.to_vec();
+        geometry.uvs = vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]];
+        geometry.indices = vec![0, 1, 2];
+        (geometry, rusty_spine::Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 })
+    }
+
+    #[test]
+    fn flip_mirrors_positions_but_keeps_uvs_and_winding() {
+        let mut obj = test_model(SLOT_SKELETON);
+        obj.position = Pos2::new(100.0, 200.0);
+        obj.scale = 10.0;
+        // 不对称的直角三角形与贴图坐标：镜像错误或 UV 被一起翻转都会被发现
+        let (geometry, white) = triangle_geometry([[0.0, 0.0], [2.0, 0.0], [0.0, 1.0]]);
+        let mut meshes = [Mesh::default(), Mesh::default()];
+        for (flip, mesh) in [false, true].into_iter().zip(&mut meshes) {
+            obj.flip_x = flip;
+            let slot = obj.skeleton.slots().next().unwrap();
+            obj.push_to_mesh(mesh, &geometry, &slot, white, Color32::WHITE);
+        }
+        let [normal, flipped] = &meshes;
+        for (a, b) in normal.vertices.iter().zip(&flipped.vertices) {
+            assert_eq!(b.pos.x - obj.position.x, -(a.pos.x - obj.position.x), "以角色原点为轴镜像");
+            assert_eq!(a.pos.y, b.pos.y);
+            assert_eq!(a.uv, b.uv, "UV 不随翻转改变");
+        }
+        // 屏幕上的有向面积：符号相同即绕序相同，背面剔除时两种朝向都可见
+        let area = |m: &Mesh| {
+            let [a, b, c] = [0, 1, 2].map(|i| m.vertices[m.indices[i] as usize].pos);
+            (b - a).x * (c - a).y - (b - a).y * (c - a).x
+        };
+        assert!(area(normal) * area(flipped) > 0.0, "翻转后三角形绕序应保持一致");
+    }
+
+    #[test]
+    fn project_continues_into_next_chapter() {
+        let dir = temp_dir("chapters");
+        let chapters = vec![write_chapter(&dir, "ch1"), write_chapter(&dir, "ch2")];
+        let project_path = dir.join(format!("story.{}", PROJECT_EXTENSION));
+        std::fs::write(&project_path, serde_json::to_string(&Project { chapters }).unwrap()).unwrap();
+
+        let (mut app, ctx) = test_app();
+        app.load_project_from(project_path).unwrap();
+        app.handle_async_events(&ctx); // 第一章切幕发出的背景/BGM 加载命令
+        assert_eq!(app.chapter_idx, 0);
+        assert!(app.advance_scene(), "最后一幕之后应进入下一章");
+        assert_eq!(app.chapter_idx, 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn playing_a_scene_does_not_mark_unsaved() {
+        let (mut app, ctx) = test_app();
+        let scene = &mut app.scenario.scenes[0];
+        scene.background = SceneBackground::Image(Some("missing_bg.png".into()));
+        scene.foreground_path = Some("missing_fg.png".into());
+        scene.bgm_path = Some("missing_bgm.ogg".into());
+        scene.char_paths[0] = Some("missing.atlas".into());
+        app.apply_scene();
+        app.handle_async_events(&ctx);
+        assert!(!app.dirty, "切幕恢复画面不应标记为未保存");
+
+        // 用户自己发出的同类命令仍算修改
+        let _ = app.tx.send(AppCommand::LoadBackground("other_bg.png".into()));
+        app.handle_async_events(&ctx);
+        assert!(app.dirty);
+    }
+
+    #[test]
+    fn legacy_bg_path_migrates_into_background() {
+        let dir = temp_dir("legacy_bg");
+        let path = dir.join("old.json");
+        let mut scene = serde_json::to_value(Scene::default()).unwrap();
+        scene.as_object_mut().unwrap().remove("background");
+        scene["bg_path"] = serde_json::json!("old_bg.png");
+        std::fs::write(&path, serde_json::json!({ "scenes": [scene] }).to_string()).unwrap();
+
+        let scenario = read_scenario_file(&path).unwrap();
+        assert!(scenario.scenes[0].background == SceneBackground::Image(Some("old_bg.png".into())));
+        let saved = serde_json::to_value(&scenario).unwrap();
+        assert!(saved["scenes"][0].get("bg_path").is_none());
+    }
+
+    #[test]
+    fn kinsoku_hangs_a_single_punctuation_mark() {
+        assert_eq!(kinsoku_breaks("啊啊啊。", 3.0, |_| 1.0), Vec::<usize>::new());
+        assert_eq!(kinsoku_breaks("啊啊啊。啊", 3.0, |_| 1.0), vec![4]);
+    }
+
+    #[test]
+    fn kinsoku_pushes_down_before_punctuation_runs() {
+        // 连续的避头标点只挂一个，其余连同前一个字推到下一行
+        assert_eq!(kinsoku_breaks("啊啊啊……」", 3.0, |_| 1.0), vec![2]);
+        assert_eq!(kinsoku_breaks("啊啊啊！？）", 3.0, |_| 1.0), vec![2]);
+    }
+
+    #[test]
+    fn kinsoku_keeps_opening_bracket_with_next_char() {
+        assert_eq!(kinsoku_breaks("啊啊「啊", 3.0, |_| 1.0), vec![2]);
+    }
+
+    #[test]
+    fn color_filter_reaches_vertex_tint() {
+        let mut obj = test_model(SLOT_SKELETON);
+        let (geometry, white) = triangle_geometry([[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]]);
+        let ambient = Color32::from_rgb(255, 120, 60); // 偏橙的环境光
+        for filter in [ColorFilter::Grayscale, ColorFilter::Sepia] {
+            obj.color_filter = filter;
+            let mut mesh = Mesh::default();
+            let slot = obj.skeleton.slots().next().unwrap();
+            obj.push_to_mesh(&mut mesh, &geometry, &slot, white, ambient);
+            let c = mesh.vertices[0].color;
+            assert!(c.r() == c.g() && c.g() == c.b(), "{} 下顶点颜色应为灰色", filter.label());
+            // 白色不被改变，复古色调只来自贴图
+            assert_eq!(filter.apply_tint(Color32::WHITE), Color32::WHITE);
+        }
+    }
+
+    /// 插槽 body 上有两个网格附件 a（默认）与 b，动画 wave 只对 b 做网格变形：第 0 个顶点右移 5
+    const FFD_SKELETON: &str = r#"{
+        "skeleton": {"spine": "3.8.99"},
+        "bones": [{"name": "root"}],
+        "slots": [{"name": "body", "bone": "root", "attachment": "a"}],
+        "skins": [{"name": "default", "attachments": {"body": {
+            "a": {"type": "mesh", "path": "px", "uvs": [0, 0, 1, 0, 0, 1], "triangles": [0, 1, 2], "vertices": [0, 0, 10, 0, 0, 10], "hull": 3},
+            "b": {"type": "mesh", "path": "px", "uvs": [0, 0, 1, 0, 0, 1], "triangles": [0, 1, 2], "vertices": [0, 0, 10, 0, 0, 10], "hull": 3}
+        }}}],
+        "animations": {"wave": {"deform": {"default": {"body": {"b": [
+            {"time": 0, "vertices": [5, 0]},
+            {"time": 1, "vertices": [5, 0]}
+        ]}}}}}
+    }"#;
+
+    /// 当前帧插槽 body 上网格的世界顶点
+    fn body_vertices(obj: &SpineObject) -> Vec<[f32; 2]> {
+        let slot = obj.skeleton.slots().next().unwrap();
+        let mesh = slot.attachment().and_then(|a| a.as_mesh()).expect("body 上应挂着网格附件");
+        let mut geometry = AttachmentGeometry::default();
+        assert!(geometry.load_mesh(&slot, &mesh));
+        geometry.positions().to_vec()
+    }
+
+    #[test]
+    fn ffd_deform_applies_to_overridden_mesh() {
+        let mut obj = test_model(FFD_SKELETON);
+        obj.update_parallel(0.1);
+        assert_eq!(body_vertices(&obj)[0], [0.0, 0.0], "变形时间轴只作用于附件 b，a 保持绑定姿势");
+
+        // 手动把插槽换成 b：变形数据应按当前帧写入 b，而不是被换附件清空
+        obj.set_attachment_overrides(&[("body".to_string(), Some("b".to_string()))]);
+        obj.update_parallel(0.1);
+        let vertices = body_vertices(&obj);
+        assert!((vertices[0][0] - 5.0).abs() < 1e-4, "第 0 个顶点应右移 5，实际 {:?}", vertices[0]);
+        assert_eq!(vertices[1], [10.0, 0.0], "未变形的顶点保持原位");
+    }
+
+    #[test]
+    fn only_io_errors_are_retried() {
+        let path = std::path::Path::new("a.atlas");
+        assert!(!LoadError::from("Atlas Error: bad".to_string()).retryable, "解析错误重试也不会成功");
+        assert!(!LoadError::io("Read Error", path, std::io::ErrorKind::NotFound.into()).retryable);
+        assert!(LoadError::io("Read Error", path, std::io::ErrorKind::Interrupted.into()).retryable);
+    }
 }